@@ -50,6 +50,9 @@ CLAM_MODELS['groq:		llama-3.3-70b-specdec']='{ "completion_cost":0.0000000, "pro
 CLAM_MODELS['groq:		llama-3.2-1b-preview']='{ "completion_cost":0.0000000, "prompt_cost":0.0000000, "endpoint": "https://api.groq.com/openai/v1/chat/completions", "model": "llama-3.2-1b-preview", "provider": "groq" }'
 CLAM_MODELS['groq:		llama-3.2-3b-preview']='{ "completion_cost":0.0000000, "prompt_cost":0.0000000, "endpoint": "https://api.groq.com/openai/v1/chat/completions", "model": "llama-3.2-3b-preview", "provider": "groq" }'
 
+CLAM_MODELS['mistral:	mistral-large-latest']='{ "completion_cost":0.0000060, "prompt_cost":0.0000020, "endpoint": "https://api.mistral.ai/v1/chat/completions", "model": "mistral-large-latest", "provider": "mistral" }'
+CLAM_MODELS['mistral:	mistral-small-latest']='{ "completion_cost":0.0000006, "prompt_cost":0.0000002, "endpoint": "https://api.mistral.ai/v1/chat/completions", "model": "mistral-small-latest", "provider": "mistral" }'
+
 CLAM_MODELS['ollama:	codellama']='{ "completion_cost":0.0000000, "prompt_cost":0.0000000, "endpoint": "http://localhost:11434/api/chat", "model": "codellama", "provider": "ollama" }'
 CLAM_MODELS['ollama:	qwen2.5-coder:7b-instruct']='{ "completion_cost":0.0000000, "prompt_cost":0.0000000, "endpoint": "http://localhost:11434/api/chat", "model": "qwen2.5-coder:7b-instruct", "provider": "ollama" }'
 
@@ -62,6 +65,126 @@ export CLAM_LAST_OUTPUT_FILE="${CLAM_LAST_OUTPUT_FILE:-$HOME/.clam/last_output.t
 capture_command_result() {
     export CLAM_LAST_EXIT_CODE="$?"
     export CLAM_LAST_COMMAND="$(fc -ln -1 2>/dev/null | sed 's/^[[:space:]]*//')"
+
+    # An empty Enter redraws the prompt without running anything, so only
+    # count a result when the history number has moved on.
+    local history_number=$(history 1 | awk '{print $1}')
+    if [[ -n "$history_number" && "$history_number" != "$_CLAM_LAST_HISTORY_NUMBER" ]]; then
+        _CLAM_LAST_HISTORY_NUMBER="$history_number"
+        record_command_result "$CLAM_LAST_EXIT_CODE"
+        record_session_command "$CLAM_LAST_EXIT_CODE" "$CLAM_LAST_COMMAND"
+    fi
+}
+
+# Not exported, so raw commands never reach child processes; the clam
+# wrapper from cmd_enable passes them to 'clam recap' only.
+record_session_command() {
+    local exit_code="$1"
+    local command="$2"
+
+    [[ "$exit_code" =~ ^[0-9]+$ && -n "$command" ]] || return 0
+    local limit="${CLAM_MAX_HISTORY_COMMANDS:-20}"
+    _CLAM_SESSION_COMMANDS+=("$exit_code"$'\t'"${command//$'\n'/ }")
+    (( ${#_CLAM_SESSION_COMMANDS[@]} > limit )) &&
+        _CLAM_SESSION_COMMANDS=("${_CLAM_SESSION_COMMANDS[@]: -$limit}")
+    return 0
+}
+
+record_command_result() {
+    local exit_code="$1"
+    local outcome_file=${CLAM_OUTCOME_LOG_FILE:-"$HOME/.clam/outcomes.log"}
+
+    [[ "$exit_code" =~ ^[0-9]+$ ]] || return 0
+    [[ -f "$outcome_file" ]] || mkdir -p "$(dirname "$outcome_file")"
+
+    # Runs on every prompt of every open shell, so each rewrite gets its own
+    # temp file and lands with an atomic mv.
+    local tmp_file code count found=false
+    tmp_file=$(mktemp "$outcome_file.XXXXXX" 2>/dev/null) || return 0
+    {
+        if [[ -f "$outcome_file" ]]; then
+            while IFS=, read -r code count; do
+                [[ "$code" == "$exit_code" ]] && { count=$((count + 1)); found=true; }
+                echo "$code,$count"
+            done < "$outcome_file"
+        fi
+        [[ "$found" == true ]] || echo "$exit_code,1"
+    } > "$tmp_file"
+    mv -f "$tmp_file" "$outcome_file"
+}
+
+get_command_outcome_stats() {
+    local outcome_file=${CLAM_OUTCOME_LOG_FILE:-"$HOME/.clam/outcomes.log"}
+
+    if [[ ! -s "$outcome_file" ]]; then
+        echo '{"total":0,"failures":0,"exit_codes":{}}'
+        return
+    fi
+
+    sort -t, -k1,1n "$outcome_file" \
+        | jq -Rn '[inputs | capture("^(?<code>[0-9]+),(?<count>[0-9]+)$")]
+            | {
+                total: (map(.count | tonumber) | add),
+                failures: (map(select(.code != "0") | .count | tonumber) | add // 0),
+                exit_codes: (map({(.code): (.count | tonumber)}) | add)
+              }'
+}
+
+record_suggestion_feedback() {
+    local outcome="$1"
+    local feedback_file=${CLAM_SUGGESTION_LOG_FILE:-"$HOME/.clam/suggestions.log"}
+
+    [[ "$outcome" == "accepted" || "$outcome" == "rejected" ]] || return 0
+    mkdir -p "$(dirname "$feedback_file")"
+    echo "$(date +%s),$outcome" >> "$feedback_file"
+}
+
+record_completion_latency() {
+    local latency_ms="$1"
+    local latency_file=${CLAM_LATENCY_LOG_FILE:-"$HOME/.clam/latency.log"}
+    local window="${CLAM_LATENCY_WINDOW:-500}"
+
+    [[ "$latency_ms" =~ ^[0-9]+$ ]] || return 0
+    mkdir -p "$(dirname "$latency_file")"
+    echo "$(date +%s),$latency_ms" >> "$latency_file"
+    if (( $(wc -l < "$latency_file") > window )); then
+        tail -n "$window" "$latency_file" > "$latency_file.tmp" && mv -f "$latency_file.tmp" "$latency_file"
+    fi
+}
+
+get_latency_stats() {
+    local latency_file=${CLAM_LATENCY_LOG_FILE:-"$HOME/.clam/latency.log"}
+
+    if [[ ! -s "$latency_file" ]]; then
+        echo '{"count":0,"average_ms":null,"p50_ms":null,"p95_ms":null,"p99_ms":null}'
+        return
+    fi
+
+    awk -F, '{print $2}' "$latency_file" | jq -sc '
+        sort as $sorted
+        | length as $count
+        | def percentile($p): $sorted[([($p * $count / 100 | ceil), 1] | max) - 1];
+          {count: $count,
+           average_ms: (add / $count | round),
+           p50_ms: percentile(50),
+           p95_ms: percentile(95),
+           p99_ms: percentile(99)}'
+}
+
+get_suggestion_acceptance_stats() {
+    local feedback_file=${CLAM_SUGGESTION_LOG_FILE:-"$HOME/.clam/suggestions.log"}
+    local accepted=0 rejected=0
+
+    if [[ -s "$feedback_file" ]]; then
+        read -r accepted rejected <<< "$(awk -F, '
+            $2 == "accepted" { a++ }
+            $2 == "rejected" { r++ }
+            END { print a + 0, r + 0 }' "$feedback_file")"
+    fi
+
+    jq -nc --argjson accepted "$accepted" --argjson rejected "$rejected" \
+        '{accepted: $accepted, rejected: $rejected,
+          rate: (if $accepted + $rejected == 0 then null else $accepted / ($accepted + $rejected) end)}'
 }
 
 # === System Information ===
@@ -118,17 +241,183 @@ get_output_instructions() {
 }
 
 get_command_history() {
+    local user_input="$1"
     local history_limit=${CLAM_MAX_HISTORY_COMMANDS:-20}
-    history | tail -n "$history_limit"
+
+    if [[ "${CLAM_RANK_HISTORY_BY_RELEVANCE:-false}" == "true" && -n "$user_input" ]]; then
+        rank_history_by_similarity "$user_input" | head -n "$history_limit" | cut -f 2- | sort -n -k1,1
+        return
+    fi
+
+    get_shell_history | awk '{
+        command = $0
+        sub(/^[[:space:]]*[0-9]+\*?[[:space:]]+/, "", command)
+        if (NR == 1 || command != previous) print
+        previous = command
+    }' | tail -n "$history_limit"
+}
+
+split_patterns() {
+    local pattern_list="$1"
+    local patterns=()
+    IFS=',' read -ra patterns <<< "$pattern_list"
+    for pattern in "${patterns[@]}"; do
+        pattern=$(echo "$pattern" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+        [[ -n "$pattern" ]] && echo "$pattern"
+    done
+}
+
+is_valid_regex() {
+    echo | grep -E -- "$1" &>/dev/null
+    [[ $? -ne 2 ]]
+}
+
+apply_redaction_patterns() {
+    local text="$1"
+    local pattern
+    while IFS= read -r pattern; do
+        [[ -z "$pattern" ]] && continue
+        text=$(echo "$text" | sed -E "s"$'\x01'"$pattern"$'\x01'"REDACTED"$'\x01'"g")
+    done <<< "$(split_patterns "$CLAM_REDACTION_PATTERNS")"
+    echo "$text"
+}
+
+sanitize_text() {
+    # Typed credential formats go first so the generic rules below don't
+    # swallow them. JWTs must start with "eyJ" (a base64 "{") so dotted
+    # filenames are left alone.
+    local text=$(echo "$1" | sed -E \
+        -e 's/\b(AKIA|ASIA)[0-9A-Z]{16}\b/REDACTED_AWS_KEY/g' \
+        -e 's/\bAIza[0-9A-Za-z_-]{35}/REDACTED_GCP_KEY/g' \
+        -e 's/\b(gh[pousr]_[A-Za-z0-9]{36,255}|github_pat_[A-Za-z0-9_]{22,255})\b/REDACTED_GITHUB_TOKEN/g' \
+        -e 's/\bxox[abprs]-[A-Za-z0-9-]{10,}/REDACTED_SLACK_TOKEN/g' \
+        -e 's/\beyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+/REDACTED_JWT/g' \
+        -e 's/\b[[:xdigit:]]{32,40}\b/REDACTED_HASH/g' \
+        -e 's/\b[0-9a-fA-F-]{36}\b/REDACTED_UUID/g' \
+        -e 's/\b[A-Za-z0-9]{16,40}\b/REDACTED_APIKEY/g')
+    apply_redaction_patterns "$text"
+}
+
+get_history_file() {
+    if [[ -n "$HISTFILE" ]]; then
+        echo "$HISTFILE"
+    elif [[ "$(basename "${SHELL:-bash}")" == "zsh" ]]; then
+        echo "$HOME/.zsh_history"
+    else
+        echo "$HOME/.bash_history"
+    fi
+}
+
+read_history_file() {
+    local history_file="$1"
+    [[ -f "$history_file" ]] || return 0
+
+    awk '
+        !continued && /^#[0-9]+$/ { next }
+        {
+            line = $0
+            if (continued) {
+                command = command " " line
+            } else {
+                extended = (line ~ /^: [0-9]+:[0-9]+;/)
+                sub(/^: [0-9]+:[0-9]+;/, "", line)
+                command = line
+            }
+            continued = extended && command ~ /\\$/
+            if (continued) {
+                sub(/\\$/, "", command)
+                next
+            }
+            if (command != "") print command
+        }' "$history_file"
+}
+
+get_shell_history() {
+    local history_output=$(history)
+    if [[ -n "$history_output" ]]; then
+        echo "$history_output"
+    else
+        read_history_file "$(get_history_file)" | awk '{ printf "%5d  %s\n", NR, $0 }'
+    fi
+}
+
+get_indexed_history() {
+    get_shell_history | sed -E 's/^[[:space:]]*([0-9]+)\*?[[:space:]]+/\1\t/'
+}
+
+search_history() {
+    local query="$1"
+    local limit="${2:-20}"
+
+    get_indexed_history | awk -F'\t' -v query="$query" '
+        function fuzzy_score(text, pattern,    lowered, i, found, position, previous, character, score) {
+            lowered = tolower(text)
+            position = 0
+            previous = -1
+            score = 0
+            for (i = 1; i <= length(pattern); i++) {
+                character = substr(pattern, i, 1)
+                found = index(substr(lowered, position + 1), character)
+                if (found == 0) return -1
+                position += found
+                score += 1
+                if (position == previous + 1) score += 5
+                if (position == 1 || substr(lowered, position - 1, 1) ~ /[ \/_.-]/) score += 3
+                previous = position
+            }
+            return score
+        }
+        NF >= 2 && $2 != "" {
+            latest[$2] = $1
+            order[$2] = NR
+        }
+        END {
+            pattern = tolower(query)
+            for (command in latest) {
+                score = fuzzy_score(command, pattern)
+                if (score < 0) continue
+                printf "%.4f\t%s\t%s\n", score + 3 * order[command] / NR, latest[command], command
+            }
+        }' | sort -t$'\t' -k1,1gr | head -n "$limit" | cut -f 2-
 }
 
 get_sanitized_history() {
-    local history_output
-    history_output=$(get_command_history)
-    history_output=$(echo "$history_output" | sed -E 's/\b[[:xdigit:]]{32,40}\b/REDACTED_HASH/g')
-    history_output=$(echo "$history_output" | sed -E 's/\b[0-9a-fA-F-]{36}\b/REDACTED_UUID/g')
-    history_output=$(echo "$history_output" | sed -E 's/\b[A-Za-z0-9]{16,40}\b/REDACTED_APIKEY/g')
-    echo "$history_output"
+    sanitize_text "$(get_command_history "$1")"
+}
+
+rank_history_by_similarity() {
+    local user_input="$1"
+
+    get_shell_history | awk -v input="$user_input" '
+        function trigrams(text, grams,    i, count) {
+            split("", grams)
+            text = " " tolower(text) " "
+            count = 0
+            for (i = 1; i <= length(text) - 2; i++) {
+                if (!(substr(text, i, 3) in grams)) {
+                    grams[substr(text, i, 3)] = 1
+                    count++
+                }
+            }
+            return count
+        }
+        BEGIN { input_count = trigrams(input, input_grams) }
+        {
+            command = $0
+            sub(/^[[:space:]]*[0-9]+\*?[[:space:]]+/, "", command)
+            if (command == "") next
+            latest[command] = $0
+            order[command] = NR
+        }
+        END {
+            for (command in latest) {
+                command_count = trigrams(command, command_grams)
+                shared = 0
+                for (gram in command_grams) if (gram in input_grams) shared++
+                score = shared / (input_count + command_count - shared)
+                printf "%.9f\t%s\n", score + order[command] / (NR * 1000000), latest[command]
+            }
+        }' | sort -t $'\t' -k1,1gr
 }
 
 get_recent_files() {
@@ -136,6 +425,122 @@ get_recent_files() {
     find . -maxdepth 1 -type f -exec ls -ld {} + | sort -r | head -n "$file_limit"
 }
 
+get_directory_listing() {
+    local entry_limit=${CLAM_MAX_DIR_ENTRIES:-50}
+    local entries=()
+    local entry
+    while IFS= read -r entry; do
+        [[ -n "$entry" ]] && entries+=("$entry")
+    done < <(find . -mindepth 1 -maxdepth 1 -not -name '.*' \( -type d -printf '%f/\n' -o -printf '%f\n' \) 2>/dev/null | LC_ALL=C sort)
+
+    [[ ${#entries[@]} -eq 0 ]] && return
+    printf '%s\n' "${entries[@]:0:$entry_limit}"
+    if [[ ${#entries[@]} -gt $entry_limit ]]; then
+        echo "... ($(( ${#entries[@]} - entry_limit )) more entries not shown)"
+    fi
+}
+
+run_with_timeout() {
+    local seconds="$1"
+    shift
+    if command -v timeout &>/dev/null; then
+        timeout "$seconds" "$@"
+    else
+        command "$@"
+    fi
+}
+
+get_cancel_token_file() {
+    echo "${CLAM_RUN_DIR:-$HOME/.clam/run}/$1.pid"
+}
+
+run_cancellable() {
+    local kind="$1"
+    shift
+    local token_file=$(get_cancel_token_file "$kind")
+    local output_file=$(mktemp)
+    mkdir -p "$(dirname "$token_file")"
+
+    "$@" > "$output_file" &
+    local pid=$!
+    echo "$pid" > "$token_file"
+
+    local previous_int_trap=$(trap -p INT)
+    trap "cancel_request $kind" INT
+    local status=0
+    wait "$pid" 2>/dev/null || status=$?
+    eval "${previous_int_trap:-trap - INT}"
+
+    if [[ "$(cat "$token_file" 2>/dev/null)" == "cancelled $pid" ]]; then
+        command rm -f "$token_file" "$output_file"
+        return "$CLAM_EXIT_CANCELLED"
+    fi
+    [[ "$(cat "$token_file" 2>/dev/null)" == "$pid" ]] && command rm -f "$token_file"
+    cat "$output_file"
+    command rm -f "$output_file"
+    return "$status"
+}
+
+cancel_request() {
+    local token_file=$(get_cancel_token_file "$1")
+    local pid=$(cat "$token_file" 2>/dev/null)
+    [[ "$pid" =~ ^[0-9]+$ ]] && kill -0 "$pid" 2>/dev/null || return 1
+
+    echo "cancelled $pid" > "$token_file"
+    kill_process_tree "$pid"
+}
+
+# Children go first, so a curl started under timeout is not left running.
+kill_process_tree() {
+    local child
+    for child in $(pgrep -P "$1" 2>/dev/null); do
+        kill_process_tree "$child"
+    done
+    kill -TERM "$1" 2>/dev/null
+}
+
+get_git_context() {
+    command -v git &>/dev/null || return
+    local branch
+    branch=$(run_with_timeout 1 git rev-parse --abbrev-ref HEAD 2>/dev/null) || return
+    [[ -z "$branch" ]] && return
+
+    local changed_count=$(run_with_timeout 1 git status --porcelain 2>/dev/null | wc -l)
+    if [[ $changed_count -gt 0 ]]; then
+        echo "Branch: $branch (dirty: $changed_count uncommitted changes)"
+    else
+        echo "Branch: $branch (clean)"
+    fi
+}
+
+get_safe_env_vars() {
+    local sensitive_patterns=("KEY" "SECRET" "TOKEN" "PASSWORD" "PASSWD" "CREDENTIAL" "AUTH")
+    local pattern
+    while IFS= read -r pattern; do
+        [[ -n "$pattern" ]] && sensitive_patterns+=("$pattern")
+    done <<< "$(split_patterns "$CLAM_SENSITIVE_ENV_PATTERNS")"
+
+    local sensitive_regex=$(IFS='|'; echo "${sensitive_patterns[*]}")
+    env | grep '=' | grep -v 'CLAM_' | awk -F= '{print $1}' \
+        | grep -v 'PWD\|OSTYPE\|BASH\|USER\|HOME\|TERM\|OLDPWD\|HOSTNAME' \
+        | grep -Ev -- "$sensitive_regex"
+}
+
+get_prompt_env_vars() {
+    local limit=${CLAM_MAX_ENV_VARS:-30}
+    local priority_names=("PATH" "SHELL" "LANG" "VIRTUAL_ENV" "CONDA_DEFAULT_ENV" "EDITOR")
+    local env_vars=$(get_safe_env_vars | LC_ALL=C sort -u)
+    local name
+
+    {
+        for name in "${priority_names[@]}"; do
+            grep -qx -- "$name" <<< "$env_vars" && echo "$name"
+        done
+        local priority_regex="^($(IFS='|'; echo "${priority_names[*]}"))$"
+        grep -Ev -- "$priority_regex" <<< "$env_vars"
+    } | sed '/^$/d' | head -n "$limit"
+}
+
 get_command_help() {
     local command_name help_output
     command_name=$(echo "$1" | awk '{print $1}')
@@ -150,52 +555,213 @@ get_command_help() {
 
 build_prompt() {
     local user_input="$*"
-    local command_history=$(get_sanitized_history)
+    local command_history=$(get_sanitized_history "$user_input")
     local terminal_context=$(get_terminal_info)
     local help_message=$(get_command_help "$user_input")
     local recent_files=$(get_recent_files)
     local output_instructions=$(get_output_instructions)
-    local env_vars=$(env | grep '=' | grep -v 'CLAM_' | awk -F= '{print $1}' | grep -v 'PWD\|OSTYPE\|BASH\|USER\|HOME\|TERM\|OLDPWD\|HOSTNAME')
+    local env_vars=$(get_prompt_env_vars)
+    local dir_section=""
+    if [[ "${CLAM_INCLUDE_DIR_LISTING:-true}" == "true" ]]; then
+        dir_section="
+Directory contents (hidden entries excluded):
+\`\`\`
+$(get_directory_listing)
+\`\`\`
+"
+    fi
+    local git_context=$(get_git_context)
+    local git_section=""
+    if [[ -n "$git_context" ]]; then
+        git_section="
+## Git repository
+$git_context
+"
+    fi
+    local output_section=""
+    if [[ "${CLAM_INCLUDE_RECENT_OUTPUT:-false}" == "true" ]]; then
+        local recent_output
+        if recent_output=$(get_recent_output "${CLAM_RECENT_OUTPUT_LINES:-20}"); then
+            output_section="
+## Last command output
+Output of \`$(sanitize_text "$CLAM_LAST_COMMAND")\` (some information redacted):
+\`\`\`
+$(sanitize_text "$(truncate_middle "$recent_output" 2000)")
+\`\`\`
+"
+        fi
+    fi
 
-    cat <<EOF
-User command: \`$user_input\`
+    render_prompt_template "$(get_prompt_template "$CLAM_PROMPT_TEMPLATE")" \
+        "current_input=$user_input" \
+        "cwd=$PWD" \
+        "terminal=$terminal_context" \
+        "env=$env_vars" \
+        "history=$command_history" \
+        "files=$recent_files" \
+        "directory=$dir_section" \
+        "git=$git_section" \
+        "output=$output_section" \
+        "help=$help_message" \
+        "instructions=$output_instructions"
+}
+
+get_default_prompt_template() {
+    cat <<'EOF'
+User command: `{current_input}`
 
 # Terminal Context
 ## Environment variables
-$terminal_context
+{terminal}
 
 Other defined environment variables
-\`\`\`
-$env_vars
-\`\`\`
+```
+{env}
+```
 
 ## History
 Recently run commands (some information redacted):
-\`\`\`
-$command_history
-\`\`\`
+```
+{history}
+```
 
 ## File system
 Most recently modified files:
-\`\`\`
-$recent_files
-\`\`\`
-
+```
+{files}
+```
+{directory}{git}{output}
 ## Help Information
-$help_message
+{help}
 
 # Instructions
-$output_instructions
+{instructions}
 EOF
 }
 
+get_prompt_template() {
+    local name="${1:-default}"
+    local template_file="$HOME/.clam/templates/$name.txt"
+
+    if [[ "$name" == "default" ]]; then
+        get_default_prompt_template
+    elif [[ "$name" =~ ^[A-Za-z0-9_-]+$ && -f "$template_file" ]]; then
+        cat "$template_file"
+    else
+        echo_error "Prompt template '$name' not found in ~/.clam/templates, using the default"
+        get_default_prompt_template
+    fi
+}
+
+render_prompt_template() {
+    local rest="$1"
+    shift
+    local -A values=()
+    local field
+    for field in "$@"; do
+        values["${field%%=*}"]="${field#*=}"
+    done
+
+    local rendered="" placeholder name
+    while [[ "$rest" =~ \{([A-Za-z_]+)\} ]]; do
+        placeholder="${BASH_REMATCH[0]}"
+        name="${BASH_REMATCH[1]}"
+        rendered+="${rest%%"$placeholder"*}"
+        rest="${rest#*"$placeholder"}"
+        if [[ -v "values[$name]" ]]; then
+            rendered+="${values[$name]}"
+        else
+            rendered+="$placeholder"
+        fi
+    done
+    printf '%s\n' "$rendered$rest"
+}
+
+get_package_manager() {
+    local manager
+    for manager in apt-get dnf yum pacman zypper apk brew; do
+        if command -v "$manager" &>/dev/null; then
+            echo "$manager"
+            return
+        fi
+    done
+}
+
+detect_missing_command() {
+    local last_cmd="$1"
+    local last_exit="$2"
+    local last_output="$3"
+    local missing=""
+
+    if [[ "$last_output" =~ command\ not\ found:\ ([^[:space:]]+) ]]; then
+        missing="${BASH_REMATCH[1]}"
+    elif [[ "$last_output" =~ ([^[:space:]:]+):\ (command\ )?not\ found ]]; then
+        missing="${BASH_REMATCH[1]}"
+    elif [[ "$last_output" =~ Command\ \'([^\']+)\'\ not\ found ]]; then
+        missing="${BASH_REMATCH[1]}"
+    elif [[ "$last_exit" == "127" ]]; then
+        missing=$(echo "$last_cmd" | awk '{print $1}')
+    fi
+
+    echo "$missing"
+}
+
+strip_ansi() {
+    local esc=$'\e' bel=$'\a' cr=$'\r'
+    echo "$1" | sed -E "
+        s/${esc}\][^${bel}${esc}]*(${bel}|${esc}\\\\)//g
+        s|${esc}\[[0-9;?]*[ -/]*[@-~]||g
+        s/${esc}[()][0-9A-Za-z]//g
+        s/${esc}[@-_]//g
+        s/${cr}+\$//
+        s/.*${cr}//"
+}
+
+truncate_middle() {
+    local text="$1"
+    local max_chars="$2"
+    local length=${#text}
+
+    if (( length <= max_chars )); then
+        echo "$text"
+        return
+    fi
+
+    local head_chars=$(( max_chars / 4 ))
+    local tail_chars=$(( max_chars - head_chars ))
+    echo "${text:0:head_chars}"
+    echo "... [$(( length - max_chars )) characters omitted] ..."
+    echo "${text: -tail_chars}"
+}
+
+get_recent_output() {
+    local line_count="${1:-20}"
+
+    [[ "$line_count" =~ ^[0-9]+$ ]] || line_count=20
+    [[ -s "$CLAM_LAST_OUTPUT_FILE" ]] || return 1
+    strip_ansi "$(cat "$CLAM_LAST_OUTPUT_FILE")" | tail -n "$line_count"
+}
+
 build_fep_prompt() {
     local user_context="$1"
     local last_cmd="${CLAM_LAST_COMMAND:-$(fc -ln -1 2>/dev/null | sed 's/^[[:space:]]*//')}"
     local last_exit="${CLAM_LAST_EXIT_CODE:-$?}"
     local last_output=""
 
-    [[ -f "$CLAM_LAST_OUTPUT_FILE" ]] && last_output="$(tail -100 "$CLAM_LAST_OUTPUT_FILE")"
+    if [[ -f "$CLAM_LAST_OUTPUT_FILE" ]]; then
+        last_output=$(truncate_middle "$(strip_ansi "$(cat "$CLAM_LAST_OUTPUT_FILE")")" "${CLAM_FEP_MAX_OUTPUT_CHARS:-4000}")
+    fi
+
+    local missing_section=""
+    local missing_command=$(detect_missing_command "$last_cmd" "$last_exit" "$last_output")
+    if [[ -n "$missing_command" ]]; then
+        local package_manager=$(get_package_manager)
+        missing_section="
+## Missing Command
+\`$missing_command\` was not found (OS: $OSTYPE${package_manager:+, package manager: $package_manager}).
+If it is not a typo, suggest the package installation that provides \`$missing_command\` on this OS${package_manager:+ (e.g. with $package_manager)}.
+"
+    fi
 
     cat <<EOF
 # Error Recovery Request
@@ -215,7 +781,7 @@ ${last_output:-"(No captured output - please describe the error)"}
 
 ## Additional Context from User
 ${user_context:-"(None provided)"}
-
+$missing_section
 ## Environment
 $(get_terminal_info)
 
@@ -223,28 +789,86 @@ $(get_terminal_info)
 $(ls -la 2>/dev/null | head -20)
 
 ## Recent Command History
-$(fc -ln -10 2>/dev/null)
+$(get_sanitized_history)
 
 # Instructions
-Analyze the failed command and error. Provide:
+Analyze the failed command and error. Provide up to three fixes, ranked from most to least likely. For each fix give:
 1. **Recommended Command**: A corrected or alternative command that fixes the issue
 2. **Explanation**: Brief explanation of what went wrong and why the fix works
+3. **Confidence**: A number from 0 to 1 for how likely the fix is to work
 
 Respond in this exact JSON format:
 {
-    "recommended_command": "the fixed command here",
-    "explanation": "brief explanation of the fix"
+    "fixes": [
+        {
+            "recommended_command": "the fixed command here",
+            "explanation": "brief explanation of the fix",
+            "confidence": 0.9
+        }
+    ]
 }
 EOF
 }
 
 # === Payload Building ===
 
+get_max_tokens() {
+    local kind="$1"
+    local default_tokens=1024
+    case "$kind" in
+        harm|explain|generate|next|recap) default_tokens=512 ;;
+        ping) default_tokens=1 ;;
+    esac
+
+    local config_var="CLAM_${kind^^}_MAX_TOKENS"
+    local max_tokens="${!config_var:-$default_tokens}"
+    [[ "$max_tokens" =~ ^[0-9]+$ ]] || max_tokens="$default_tokens"
+    (( max_tokens < 1 )) && max_tokens=1
+    (( max_tokens > 8192 )) && max_tokens=8192
+    # Hidden reasoning tokens count against the cap, so reasoning models get
+    # room for them on top of the answer.
+    [[ "$kind" != "ping" ]] && is_reasoning_model && max_tokens=$((max_tokens + 4096))
+    echo "$max_tokens"
+}
+
+is_reasoning_model() {
+    local model="${1:-$CLAM_MODEL}"
+    [[ "${CLAM_PROVIDER:-openai}" == "openai" && "$model" =~ ^o[0-9]+(-|$) ]]
+}
+
+# Reasoning models reject temperature and have no system role, so the
+# system prompt is folded into the user message. o1-mini and o1-preview also
+# reject tools and response_format and answer with JSON in plain text.
+with_max_tokens() {
+    local max_tokens="$1"
+
+    if [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
+        jq --argjson max_tokens "$max_tokens" '.options = ((.options // {}) + {num_predict: $max_tokens})'
+    elif is_reasoning_model; then
+        local plain_text=false
+        [[ "$CLAM_MODEL" =~ ^o1-(mini|preview)(-|$) ]] && plain_text=true
+        jq --argjson max_tokens "$max_tokens" --argjson plain_text "$plain_text" '
+            .messages = [{
+                role: "user",
+                content: (.messages | map(.content) | join("\n\n"))
+            }]
+            | del(.temperature)
+            | if $plain_text then del(.tools, .tool_choice, .response_format) else . end
+            | . + {max_completion_tokens: $max_tokens}'
+    else
+        jq --argjson max_tokens "$max_tokens" '. + {max_tokens: $max_tokens}'
+    fi
+}
+
 build_base_payload() {
+    local keep_alive=""
+    [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]] && keep_alive="${CLAM_OLLAMA_KEEP_ALIVE:-5m}"
+
     jq -n --arg model "$model" \
           --arg temperature "$temperature" \
           --arg system_prompt "$system_prompt" \
           --arg prompt_content "$prompt_content" \
+          --arg keep_alive "$keep_alive" \
           '{
              model: $model,
              messages: [
@@ -252,7 +876,23 @@ build_base_payload() {
                {role: "user", content: $prompt_content}
              ],
              temperature: ($temperature | tonumber)
-          }'
+          }
+          + (if $keep_alive == "" then {}
+             else {keep_alive: (if $keep_alive | test("^-?[0-9]+$") then ($keep_alive | tonumber) else $keep_alive end)} end)'
+}
+
+# The Messages API rejects a system role inside messages.
+anthropic_payload() {
+    jq '. + {system: .messages[0].content, messages: [{role: "user", content: .messages[1].content}]}'
+}
+
+add_json_reminder() {
+    jq '.messages[-1].content += "\n\nRespond with ONLY valid JSON, no prose."'
+}
+
+preview_prompt() {
+    jq -n --arg system "$(get_system_prompt)" --arg user "$(build_prompt "$@")" \
+        '{system: $system, user: $user}'
 }
 
 build_completion_payload() {
@@ -272,10 +912,7 @@ $prompt"
 
     case "${CLAM_PROVIDER^^}" in
         "ANTHROPIC")
-            echo "$payload_base" | jq '. + {
-                system: .messages[0].content,
-                messages: [{role:"user", content: .messages[1].content}],
-                max_tokens: 1024,
+            echo "$payload_base" | anthropic_payload | jq '. + {
                 tool_choice: {type: "tool", name: "bash_completions"},
                 tools: [{
                     name: "bash_completions",
@@ -300,7 +937,7 @@ $prompt"
                 }]
             }'
             ;;
-        "GROQ")
+        "GROQ"|"MISTRAL")
             echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
             ;;
         "OLLAMA")
@@ -363,31 +1000,21 @@ $prompt"
                 }]
             }'
             ;;
-    esac
+    esac | with_max_tokens "$(get_max_tokens completion)"
 }
 
 build_fep_payload() {
     local prompt="$1"
     local model="${CLAM_MODEL:-gpt-4o}"
     local temperature="${CLAM_TEMPERATURE:-0.0}"
-    local system_prompt="You are an expert command-line debugger. Analyze errors and provide fixes. Respond only with valid JSON in this exact format: {\"recommended_command\": \"the fixed command\", \"explanation\": \"brief explanation\"}."
-
-    local payload_base=$(jq -n --arg model "$model" \
-        --arg temperature "$temperature" \
-        --arg system_prompt "$system_prompt" \
-        --arg prompt "$prompt" \
-        '{
-            model: $model,
-            messages: [
-                {role: "system", content: $system_prompt},
-                {role: "user", content: $prompt}
-            ],
-            temperature: ($temperature | tonumber)
-        }')
+    local system_prompt="You are an expert command-line debugger. Analyze errors and provide fixes. Respond only with valid JSON in this exact format: {\"fixes\": [{\"recommended_command\": \"the fixed command\", \"explanation\": \"brief explanation\", \"confidence\": 0.9}]}, listing up to three fixes ranked best first."
+
+    local prompt_content="$prompt"
+    local payload_base=$(build_base_payload)
 
     case "${CLAM_PROVIDER^^}" in
         "ANTHROPIC")
-            echo "$payload_base" | jq '. + {max_tokens: 1024}'
+            echo "$payload_base" | anthropic_payload
             ;;
         "GROQ")
             echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
@@ -398,14 +1025,37 @@ build_fep_payload() {
         *)
             echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
             ;;
+    esac | with_max_tokens "$(get_max_tokens fep)"
+}
+
+get_os_name() {
+    case "${1:-$OSTYPE}" in
+        linux*) echo "Linux" ;;
+        darwin*) echo "macOS" ;;
+        msys*|cygwin*|win*) echo "Windows" ;;
+        freebsd*|openbsd*|netbsd*) echo "BSD" ;;
+        *) echo "${1:-$OSTYPE}" ;;
+    esac
+}
+
+get_harm_system_prompt() {
+    local os_name=$(get_os_name "$1")
+    local shell_name=$(basename "${2:-${SHELL:-bash}}")
+    local examples
+    case "$os_name" in
+        "Windows") examples="Remove-Item -Recurse -Force C:\\, format C:, del /s /q, Set-ExecutionPolicy Unrestricted, reg delete HKLM" ;;
+        "macOS") examples="rm -rf ~/Library, diskutil eraseDisk, csrutil disable, sudo chmod -R 777 /, launchctl unload on system daemons" ;;
+        *) examples="rm -rf /, dd of=/dev/sda, mkfs, chmod -R 777 /, systemctl stop sshd on a remote host" ;;
     esac
+
+    echo "You are a shell command security analyzer. The command will run on $os_name in $shell_name. Your role is to identify potentially harmful commands that could cause data loss, system damage, security risks, or unintended consequences. Analyze commands for: destructive file operations, system modifications, permission changes, network security risks, resource consumption attacks, and dangerous command chaining. Judge the command as the $os_name tools would interpret it. Examples of harmful commands on $os_name: $examples."
 }
 
 build_harm_detection_payload() {
     local command="$1"
     local model="${CLAM_MODEL:-gpt-4o}"
     local temperature="0.0"
-    local system_prompt="You are a bash command security analyzer. Your role is to identify potentially harmful bash commands that could cause data loss, system damage, security risks, or unintended consequences. Analyze commands for: destructive file operations, system modifications, permission changes, network security risks, resource consumption attacks, and dangerous command chaining."
+    local system_prompt=$(get_harm_system_prompt)
     local prompt_content="Analyze this bash command for potential harm:
 
 Command: $command
@@ -416,25 +1066,11 @@ Classify this command and respond with ONLY a JSON object in this exact format:
   \"explanation\": \"Brief explanation of why this command is harmful or safe (max 100 chars)\"
 }"
 
-    local payload_base=$(jq -n --arg model "$model" \
-                         --arg temperature "$temperature" \
-                         --arg system_prompt "$system_prompt" \
-                         --arg prompt_content "$prompt_content" \
-                         '{
-                            model: $model,
-                            messages: [
-                              {role: "system", content: $system_prompt},
-                              {role: "user", content: $prompt_content}
-                            ],
-                            temperature: ($temperature | tonumber)
-                          }')
+    local payload_base=$(build_base_payload)
 
     case "${CLAM_PROVIDER^^}" in
         "ANTHROPIC")
-            echo "$payload_base" | jq '. + {
-                system: .messages[0].content,
-                messages: [{role:"user", content: .messages[1].content}],
-                max_tokens: 512,
+            echo "$payload_base" | anthropic_payload | jq '. + {
                 tool_choice: {type: "tool", name: "harm_assessment"},
                 tools: [{
                     name: "harm_assessment",
@@ -450,7 +1086,7 @@ Classify this command and respond with ONLY a JSON object in this exact format:
                 }]
             }'
             ;;
-        "GROQ")
+        "GROQ"|"MISTRAL")
             echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
             ;;
         "OLLAMA")
@@ -480,211 +1116,1153 @@ Classify this command and respond with ONLY a JSON object in this exact format:
                 tool_choice: {type: "function", function: {name: "harm_assessment"}}
             }'
             ;;
-    esac
+    esac | with_max_tokens "$(get_max_tokens harm)"
 }
 
-# === API Communication ===
-
-log_api_request() {
-    local user_input="$1"
-    local response_body="$2"
-    local input_hash=$(echo -n "$user_input" | md5sum | cut -d ' ' -f 1)
-    local prompt_tokens completion_tokens created api_cost
-
-    if [[ "${CLAM_PROVIDER^^}" == "ANTHROPIC" ]]; then
-        prompt_tokens=$(echo "$response_body" | jq -r '.usage.input_tokens')
-        completion_tokens=$(echo "$response_body" | jq -r '.usage.output_tokens')
-    else
-        prompt_tokens=$(echo "$response_body" | jq -r '.usage.prompt_tokens')
-        completion_tokens=$(echo "$response_body" | jq -r '.usage.completion_tokens')
-    fi
-
-    prompt_tokens=$((prompt_tokens))
-    completion_tokens=$((completion_tokens))
-    created=$(date +%s)
-    created=$(echo "$response_body" | jq -r ".created // $created")
-    api_cost=$(echo "$prompt_tokens * $CLAM_API_PROMPT_COST + $completion_tokens * $CLAM_API_COMPLETION_COST" | bc)
+build_explain_payload() {
+    local command="$1"
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local temperature="0.0"
+    local system_prompt="You explain shell commands before the user runs them. Describe what the command does in one or two plain-English sentences and list its side effects (files created, changed or deleted, network access, privilege changes, processes started or stopped). Respond only with valid JSON in this exact format: {\"summary\": \"what the command does\", \"side_effects\": [\"a side effect\"]}. Use an empty list when there are no side effects."
+    local prompt_content="Explain this command: $command"
+    local payload_base=$(build_base_payload)
 
-    local log_file=${CLAM_LOG_FILE:-"$HOME/.clam/clam.log"}
-    echo "$created,$input_hash,$prompt_tokens,$completion_tokens,$api_cost" >> "$log_file"
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC")
+            echo "$payload_base" | anthropic_payload
+            ;;
+        "OLLAMA")
+            echo "$payload_base" | jq '. + {format: "json", stream: false}'
+            ;;
+        *)
+            echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
+            ;;
+    esac | with_max_tokens "$(get_max_tokens explain)"
 }
 
-call_api() {
-    local endpoint="$1"
-    local payload="$2"
-    local api_key="$3"
-    local timeout="$4"
+build_generate_payload() {
+    local description="$1"
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local temperature="${CLAM_TEMPERATURE:-0.0}"
+    local os_name=$(get_os_name)
+    local shell_name=$(basename "${SHELL:-bash}")
+    local system_prompt="You turn a plain-English request into a single shell command for $os_name in $shell_name. Prefer standard tools that ship with $os_name. Respond only with valid JSON in this exact format: {\"command\": \"the command\", \"explanation\": \"one sentence on what it does\"}. Do not wrap the command in backticks or quotes."
+    local prompt_content="Request: $description
+Current directory: $PWD"
+    local payload_base=$(build_base_payload)
 
-    if [[ "${CLAM_PROVIDER^^}" == "ANTHROPIC" ]]; then
-        command curl -s -m "$timeout" -w "\n%{http_code}" "$endpoint" \
-            -H "content-type: application/json" \
-            -H "anthropic-version: 2023-06-01" \
-            -H "x-api-key: $api_key" \
-            --data "$payload"
-    elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
-        command curl -s -m "$timeout" -w "\n%{http_code}" "$endpoint" --data "$payload"
-    else
-        command curl -s -m "$timeout" -w "\n%{http_code}" "$endpoint" \
-            -H "Content-Type: application/json" \
-            -H "Authorization: Bearer $api_key" \
-            -d "$payload"
-    fi
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC")
+            echo "$payload_base" | anthropic_payload
+            ;;
+        "OLLAMA")
+            echo "$payload_base" | jq '. + {format: "json", stream: false}'
+            ;;
+        *)
+            echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
+            ;;
+    esac | with_max_tokens "$(get_max_tokens generate)"
 }
 
-get_completion() {
-    local endpoint=${CLAM_ENDPOINT:-"https://api.openai.com/v1/chat/completions"}
-    local timeout=${CLAM_TIMEOUT:-30}
-    local default_input="Write two to six most likely commands given the provided information"
-    local user_input=${*:-$default_input}
+build_next_command_payload() {
+    local command="$1"
+    local output="$2"
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local temperature="${CLAM_TEMPERATURE:-0.0}"
+    local system_prompt="You predict the next shell command a user will run, based on the command they just ran and its output. For example, after git status lists modified files, suggest git add for them. Respond only with valid JSON in this exact format: {\"suggestions\": [{\"command\": \"the command\", \"explanation\": \"why it is a likely next step\"}]}, listing up to five suggestions, most likely first. Do not wrap the commands in backticks or quotes."
+    local prompt_content="Last command: ${command:-unknown}
+Current directory: $PWD
 
-    if [[ -z "$CLAM_ACTIVE_API_KEY" && ${CLAM_PROVIDER^^} != "OLLAMA" ]]; then
-        echo_error "CLAM_ACTIVE_API_KEY not set. Please set it with: export ${CLAM_PROVIDER^^}_API_KEY=<your-api-key>"
-        return
-    fi
+Its output (some information redacted):
+\`\`\`
+$output
+\`\`\`"
+    local payload_base=$(build_base_payload)
 
-    local api_key="$CLAM_ACTIVE_API_KEY"
-    local payload=$(build_completion_payload "$user_input")
-    local max_attempts=2
-    local attempt=1
-    local response status_code response_body
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC")
+            echo "$payload_base" | anthropic_payload
+            ;;
+        "OLLAMA")
+            echo "$payload_base" | jq '. + {format: "json", stream: false}'
+            ;;
+        *)
+            echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
+            ;;
+    esac | with_max_tokens "$(get_max_tokens next)"
+}
 
-    while [ $attempt -le $max_attempts ]; do
-        response=$(call_api "$endpoint" "$payload" "$api_key" "$timeout")
-        status_code=$(echo "$response" | tail -n1)
-        response_body=$(echo "$response" | sed '$d')
+build_recap_payload() {
+    local session="$1"
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local temperature="0.0"
+    local system_prompt="You recap a user's terminal session. From the commands and their exit codes, write a short narrative of what the user did, point out which commands failed, and suggest what to do next. Respond only with valid JSON in this exact format: {\"summary\": \"two or three sentences\", \"next_steps\": [\"a suggested next step\"]}."
+    local prompt_content="Commands run this session, oldest first, as exit code then command (some information redacted):
+$session"
+    local payload_base=$(build_base_payload)
 
-        if [[ $status_code -eq 200 ]]; then
-            break
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC")
+            echo "$payload_base" | anthropic_payload
+            ;;
+        "OLLAMA")
+            echo "$payload_base" | jq '. + {format: "json", stream: false}'
+            ;;
+        *)
+            echo "$payload_base" | jq '. + {response_format: {type: "json_object"}}'
+            ;;
+    esac | with_max_tokens "$(get_max_tokens recap)"
+}
+
+build_ping_payload() {
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local temperature="0.0"
+    local system_prompt="Reply with OK."
+    local prompt_content="ping"
+    local payload_base=$(build_base_payload)
+
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC")
+            echo "$payload_base" | anthropic_payload
+            ;;
+        *)
+            echo "$payload_base"
+            ;;
+    esac | with_max_tokens "$(get_max_tokens ping)"
+}
+
+# === API Communication ===
+
+log_api_request() {
+    local user_input="$1"
+    local response_body="$2"
+    local input_hash=$(echo -n "$user_input" | sha256sum | cut -d ' ' -f 1)
+    local usage=$(extract_usage "$response_body")
+    local prompt_tokens=$(echo "$usage" | jq -r '.prompt_tokens // 0')
+    local completion_tokens=$(echo "$usage" | jq -r '.completion_tokens // 0')
+    local created api_cost
+
+    created=$(date +%s)
+    created=$(echo "$response_body" | jq -r ".created // $created")
+    api_cost=$(echo "$prompt_tokens * $CLAM_API_PROMPT_COST + $completion_tokens * $CLAM_API_COMPLETION_COST" | bc)
+
+    local log_file=${CLAM_LOG_FILE:-"$HOME/.clam/clam.log"}
+    echo "$created,$input_hash,$prompt_tokens,$completion_tokens,$api_cost" 2>/dev/null >> "$log_file" ||
+        note_storage_failure "$log_file"
+}
+
+estimate_tokens() {
+    local text="$1"
+    echo $(( (${#text} + 3) / 4 ))
+}
+
+prompt_size() {
+    estimate_tokens "$(build_prompt "$@")"
+}
+
+estimate_request_cost() {
+    local prompt_text="$1"
+    local prompt_tokens=$(estimate_tokens "$prompt_text")
+    local cost=$(awk -v tokens="$prompt_tokens" -v rate="${CLAM_API_PROMPT_COST:-0}" 'BEGIN {printf "%.8f", tokens * rate}')
+
+    jq -n --argjson prompt_tokens "$prompt_tokens" --arg cost "$cost" \
+        '{prompt_tokens: $prompt_tokens, cost_usd: ($cost | tonumber), is_estimate: true}'
+}
+
+# Clamped to a minimum because `curl -m 0` means no timeout at all.
+resolve_timeout() {
+    local value="$1"
+    local default="$2"
+    local minimum="${3:-1}"
+    [[ "$value" =~ ^[0-9]+(\.[0-9]+)?$ ]] || value="$default"
+    awk -v value="$value" -v minimum="$minimum" 'BEGIN { print (value < minimum) ? minimum : value }'
+}
+
+is_valid_proxy_url() {
+    [[ "$1" =~ ^(http|https|socks5|socks5h)://[^/[:space:]]+(/.*)?$ ]]
+}
+
+extract_api_error() {
+    local response_body="$1"
+    local message=$(echo "$response_body" | jq -r '
+        if (.error | type) == "object" then
+            [(.error.type // .error.code // empty | tostring), (.error.message // empty)] | join(": ")
+        elif (.error | type) == "string" then
+            .error
         else
-            echo_error "API call failed with status $status_code. Retrying... (Attempt $attempt of $max_attempts)"
-            sleep 1
-            attempt=$((attempt+1))
-        fi
+            empty
+        end' 2>/dev/null)
+    echo "${message:-$response_body}"
+}
+
+CLAM_EXIT_CONFIG=2
+CLAM_EXIT_NETWORK=3
+CLAM_EXIT_AUTH=4
+CLAM_EXIT_RATE_LIMIT=5
+CLAM_EXIT_PROVIDER=6
+CLAM_EXIT_TIMEOUT=7
+CLAM_EXIT_CANCELLED=130
+
+api_error_exit_code() {
+    case "$(classify_api_error "$1")" in
+        network) echo "$CLAM_EXIT_NETWORK" ;;
+        auth) echo "$CLAM_EXIT_AUTH" ;;
+        rate_limit) echo "$CLAM_EXIT_RATE_LIMIT" ;;
+        *) echo "$CLAM_EXIT_PROVIDER" ;;
+    esac
+}
+
+classify_api_error() {
+    case "$1" in
+        000|"") echo "network" ;;
+        400) echo "bad_request" ;;
+        401|403) echo "auth" ;;
+        404) echo "not_found" ;;
+        429) echo "rate_limit" ;;
+        5[0-9][0-9]) echo "server" ;;
+        *) echo "unknown" ;;
+    esac
+}
+
+describe_api_failure() {
+    local status_code="$1"
+    local provider="${CLAM_PROVIDER:-openai}"
+    local model="${CLAM_MODEL:-<model>}"
+
+    case "$(classify_api_error "$status_code")" in
+        network) echo "No response from ${CLAM_ENDPOINT:-the API}: check your network, proxy_url, or that the server is running." ;;
+        bad_request) echo "Bad Request: The API request was invalid or malformed." ;;
+        auth) echo "Unauthorized: check your API key (clam config set ${provider,,}_api_key <key>)." ;;
+        not_found)
+            if [[ "${provider^^}" == "OLLAMA" ]]; then
+                echo "Model '$model' not found: run \`ollama pull $model\`."
+            else
+                echo "Not found: check the endpoint ($CLAM_ENDPOINT) and model name ($model)."
+            fi
+            ;;
+        rate_limit) echo "Rate limited or out of quota: wait and retry, or check your ${provider} plan and billing." ;;
+        server) echo "Server error (status $status_code): the provider is having trouble, try again shortly." ;;
+        *) echo "Unexpected status code $status_code received." ;;
+    esac
+}
+
+normalize_endpoint() {
+    local endpoint="$1"
+    local provider="${2:-$CLAM_PROVIDER}"
+
+    while [[ "$endpoint" == */ ]]; do
+        endpoint="${endpoint%/}"
     done
+    [[ -z "$endpoint" ]] && return
 
-    if [[ $status_code -ne 200 ]]; then
-        case $status_code in
-            400) echo_error "Bad Request: The API request was invalid or malformed." ;;
-            401) echo_error "Unauthorized: The provided API key is invalid or missing." ;;
-            429) echo_error "Too Many Requests: The API rate limit has been exceeded." ;;
-            500) echo_error "Internal Server Error: An unexpected error occurred on the API server." ;;
-            *) echo_error "Unknown Error: Unexpected status code $status_code received. Response: $response_body" ;;
-        esac
+    local path=""
+    [[ "$endpoint" =~ ^[a-zA-Z][a-zA-Z0-9+.-]*://[^/]+(/.*)?$ ]] && path="${BASH_REMATCH[1]}"
+
+    case "${provider^^}" in
+        "OLLAMA")
+            case "$path" in
+                "") endpoint+="/api/chat" ;;
+                "/api") endpoint+="/chat" ;;
+            esac
+            ;;
+        "ANTHROPIC")
+            case "$path" in
+                "") endpoint+="/v1/messages" ;;
+                */v1) endpoint+="/messages" ;;
+            esac
+            ;;
+        *)
+            case "$path" in
+                "") endpoint+="/v1/chat/completions" ;;
+                */v1) endpoint+="/chat/completions" ;;
+            esac
+            ;;
+    esac
+    echo "$endpoint"
+}
+
+get_endpoint() {
+    normalize_endpoint "${CLAM_ENDPOINT:-https://api.openai.com/v1/chat/completions}"
+}
+
+validate_endpoint() {
+    local provider="${1:-openai}"
+    local url="$2"
+
+    if [[ ! "$url" =~ ^([a-zA-Z][a-zA-Z0-9+.-]*):// ]]; then
+        echo "Endpoint '$url' is missing its scheme: expected http:// or https://"
+        return 1
+    fi
+    local scheme="${BASH_REMATCH[1]}"
+    if [[ "${scheme,,}" != "http" && "${scheme,,}" != "https" ]]; then
+        echo "Endpoint '$url' has an unknown scheme '$scheme://': expected http:// or https://"
+        return 1
+    fi
+    if [[ ! "$url" =~ ^[a-zA-Z]+://[^/[:space:]]+ ]]; then
+        echo "Endpoint '$url' has no host"
+        return 1
+    fi
+
+    local endpoint=$(normalize_endpoint "$url" "$provider")
+    local expected
+    case "${provider^^}" in
+        "OLLAMA") expected="/api/chat" ;;
+        "ANTHROPIC") expected="/messages" ;;
+        "CUSTOM"|"MOCK") return 0 ;;
+        *) expected="/chat/completions" ;;
+    esac
+    [[ "$endpoint" == *"$expected" ]] && return 0
+    echo "Endpoint '$url' does not look like a ${provider,,} chat endpoint: expected a path ending in $expected"
+    return 1
+}
+
+provider_requires_api_key() {
+    [[ "${CLAM_PROVIDER^^}" != "OLLAMA" && "${CLAM_PROVIDER^^}" != "CUSTOM" && "${CLAM_PROVIDER^^}" != "MOCK" ]]
+}
+
+keep_alive_seconds() {
+    local value="${1:-5m}"
+    [[ "$value" =~ ^(-?[0-9]+)(ms|s|m|h)?$ ]] || { echo 300; return; }
+
+    local number="${BASH_REMATCH[1]}"
+    case "${BASH_REMATCH[2]}" in
+        ms) echo $(( number / 1000 )) ;;
+        m) echo $(( number * 60 )) ;;
+        h) echo $(( number * 3600 )) ;;
+        *) echo "$number" ;;
+    esac
+}
+
+# A cold Ollama model also has to load, so it gets three times the timeout.
+ollama_request_timeout() {
+    local timeout="$1"
+    local marker="$HOME/.clam/ollama_last_request"
+    local keep_alive=$(keep_alive_seconds "${CLAM_OLLAMA_KEEP_ALIVE:-5m}")
+
+    if [[ -f "$marker" ]] && { (( keep_alive < 0 )) || { (( keep_alive > 0 )) && ! cache_entry_expired "$marker" "$keep_alive"; }; }; then
+        echo "$timeout"
+    else
+        awk -v timeout="$timeout" 'BEGIN { timeout *= 3; print (timeout > 600) ? 600 : timeout }'
+    fi
+}
+
+mock_chat_response() {
+    local payload="$1"
+    echo "$payload" | jq -c '
+        (.messages | map(select(.role == "user")) | last | .content // "") as $prompt
+        | (([$prompt | capture("User command: `(?<input>[^`\n]*)`") | .input] | first) // "") as $input
+        | (([$prompt | capture("Command: (?<command>[^\n]*)") | .command] | first) // "") as $command
+        | (.tool_choice.function.name // "") as $tool
+        | (if $tool == "bash_completions" then
+              {suggestions: [{command: (if $input == "" then "echo mock" else $input end),
+                              explanation: "Mock suggestion"}]}
+           elif $tool == "harm_assessment" then
+              ($command | test("\\brm\\b")) as $harmful
+              | {is_harmful: $harmful,
+                 explanation: (if $harmful then "Mock: the command runs rm" else "Mock: no rm in the command" end)}
+           else null end) as $arguments
+        | {choices: [{message: ({role: "assistant",
+                                 content: ({summary: "Mock summary", side_effects: [], next_steps: [],
+                                            command: "echo mock", explanation: "Mock response",
+                                            suggestions: [{command: "echo mock", explanation: "Mock suggestion"}],
+                                            fixes: [{recommended_command: "echo mock", explanation: "Mock fix", confidence: 1}]}
+                                           | tojson)}
+                                + (if $arguments == null then {}
+                                   else {tool_calls: [{type: "function",
+                                                       function: {name: $tool, arguments: ($arguments | tojson)}}]} end))}],
+           usage: {prompt_tokens: 0, completion_tokens: 0}}'
+    printf '\n200'
+}
+
+is_privacy_mode() {
+    [[ "$CLAM_PRIVACY_MODE" == "true" ]]
+}
+
+skip_for_privacy() {
+    echo_error "$1 skipped: privacy mode is on. Run clam privacy off to allow model requests."
+    return "$CLAM_EXIT_CONFIG"
+}
+
+call_api() {
+    local endpoint="$1"
+    local payload="$2"
+    local api_key="$3"
+    local timeout="$4"
+    is_privacy_mode && return 1
+    if [[ "${CLAM_PROVIDER^^}" == "MOCK" ]]; then
+        mock_chat_response "$payload"
         return
     fi
+    [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]] && timeout=$(ollama_request_timeout "$timeout")
+    local curl_args=(-s -m "$timeout" -w "\n%{http_code}")
+
+    if [[ -n "$CLAM_PROXY_URL" ]]; then
+        if ! is_valid_proxy_url "$CLAM_PROXY_URL"; then
+            echo_error "Invalid proxy_url '$CLAM_PROXY_URL': expected http://, https://, socks5:// or socks5h:// followed by a host"
+            return 1
+        fi
+        curl_args+=(--proxy "$CLAM_PROXY_URL")
+    elif [[ "$CLAM_RESPECT_SYSTEM_PROXY" == "false" ]]; then
+        # curl reads HTTPS_PROXY, ALL_PROXY and friends unless told not to.
+        curl_args+=(--noproxy '*')
+    fi
+
+    if [[ "${CLAM_PROVIDER^^}" == "CUSTOM" && -z "$CLAM_ENDPOINT" ]]; then
+        echo_error "The custom provider needs an endpoint. Set it with: clam config set endpoint <url>"
+        return 1
+    fi
 
-    local content
     if [[ "${CLAM_PROVIDER^^}" == "ANTHROPIC" ]]; then
-        content=$(echo "$response_body" | jq -r '.content[0].input.suggestions')
-    elif [[ "${CLAM_PROVIDER^^}" == "GROQ" ]]; then
-        content=$(echo "$response_body" | jq -r '.choices[0].message.content')
-        content=$(echo "$content" | jq -r '.suggestions // .completions')
+        run_with_timeout "$timeout" curl "${curl_args[@]}" "$endpoint" \
+            -H "content-type: application/json" \
+            -H "anthropic-version: 2023-06-01" \
+            -H "x-api-key: $api_key" \
+            --data "$payload"
     elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
-        content=$(echo "$response_body" | jq -r '.message.content')
-        content=$(echo "$content" | jq -r '.suggestions // .completions')
+        run_with_timeout "$timeout" curl "${curl_args[@]}" "$endpoint" --data "$payload" &&
+            touch "$HOME/.clam/ollama_last_request" 2>/dev/null
     else
-        content=$(echo "$response_body" | jq -r '.choices[0].message.tool_calls[0].function.arguments')
-        content=$(echo "$content" | jq -r '.suggestions // .commands')
+        [[ -n "$api_key" ]] && curl_args+=(-H "Authorization: Bearer $api_key")
+        run_with_timeout "$timeout" curl "${curl_args[@]}" "$endpoint" \
+            -H "Content-Type: application/json" \
+            -d "$payload"
     fi
+}
 
-    local completions
-    if echo "$content" | jq -e 'type == "array"' &>/dev/null; then
-        completions=$(echo "$content" | jq -r '.[] | .command + "|||" + .explanation' | grep -v '^$')
-    else
-        completions=$(echo "$content" | jq -r '.[]' | grep -v '^$' | sed 's/$/|||/')
+get_circuit_file() {
+    local provider="${1:-${CLAM_PROVIDER:-openai}}"
+    echo "${CLAM_CIRCUIT_DIR:-$HOME/.clam/circuit}/${provider,,}.json"
+}
+
+get_circuit_state() {
+    local provider="${1:-${CLAM_PROVIDER:-openai}}"
+    local circuit_file=$(get_circuit_file "$provider")
+    local threshold="${CLAM_CIRCUIT_BREAKER_THRESHOLD:-3}"
+    local cooldown="${CLAM_CIRCUIT_BREAKER_COOLDOWN:-30}"
+    local circuit='{}'
+    [[ -f "$circuit_file" ]] && circuit=$(jq -c '.' "$circuit_file" 2>/dev/null || echo '{}')
+
+    jq -nc --arg provider "${provider,,}" --argjson circuit "$circuit" \
+        --argjson threshold "$threshold" --argjson cooldown "$cooldown" --argjson now "$(date +%s)" '
+        ($circuit.failures // 0) as $failures
+        | (($circuit.opened_at // 0) + $cooldown - $now) as $remaining
+        | {provider: $provider,
+           state: (if $threshold == 0 or $failures < $threshold then "closed"
+                   elif $remaining > 0 then "open" else "half_open" end),
+           failures: $failures,
+           last_error: ($circuit.error // null)}
+        | . + {retry_in: (if .state == "open" then $remaining else 0 end)}'
+}
+
+record_circuit_result() {
+    local status_code="$1"
+    local error="$2"
+    local circuit_file=$(get_circuit_file)
+
+    if [[ $status_code -eq 200 ]]; then
+        command rm -f "$circuit_file"
+        return
     fi
 
-    echo -n "$completions"
-    log_api_request "$user_input" "$response_body"
+    local threshold="${CLAM_CIRCUIT_BREAKER_THRESHOLD:-3}"
+    (( threshold == 0 )) && return
+    local failures=$(( $(jq -r '.failures // 0' "$circuit_file" 2>/dev/null || echo 0) + 1 ))
+    local opened_at=0
+    (( failures >= threshold )) && opened_at=$(date +%s)
+
+    mkdir -p "$(dirname "$circuit_file")" 2>/dev/null
+    jq -nc --argjson failures "$failures" --argjson opened_at "$opened_at" \
+        --argjson exit_code "$(api_error_exit_code "$status_code")" --arg error "$error" \
+        '{failures: $failures, opened_at: $opened_at, exit_code: $exit_code, error: $error}' \
+        > "$circuit_file" 2>/dev/null || note_storage_failure "$circuit_file"
 }
 
-get_fep_completion() {
-    local user_context="$1"
-    local prompt=$(build_fep_prompt "$user_context")
-    local endpoint="${CLAM_ENDPOINT:-https://api.openai.com/v1/chat/completions}"
-    local timeout="${CLAM_TIMEOUT:-60}"
-    local api_key="$CLAM_ACTIVE_API_KEY"
+log_llm_debug() {
+    [[ "$CLAM_DEBUG_LOG_LLM" == "true" ]] || return 0
+    local label="$1"
+    local payload="$2"
+    local status_code="$3"
+    local response_body="$4"
+    local latency_ms="$5"
+    local log_file="$(dirname "$(get_config_path)")/llm_debug.log"
+    local max_bytes="${CLAM_DEBUG_LOG_MAX_BYTES:-1048576}"
+
+    local response_text=$(extract_message_content "$response_body" 2>/dev/null)
+    [[ -z "$response_text" ]] && response_text="$response_body"
+    local usage=$(extract_usage "$response_body")
+    local prompt_chars=$(echo "$payload" | jq '([.system // empty | strings] + [.messages[]?.content | strings]) | join("") | length' 2>/dev/null)
+
+    local record=$(jq -nc --arg feature "$label" --arg provider "${CLAM_PROVIDER:-openai}" --arg model "${CLAM_MODEL:-gpt-4o}" \
+        --argjson prompt_chars "${prompt_chars:-0}" --argjson latency_ms "$latency_ms" --arg status "$status_code" \
+        --arg response_text "$(sanitize_text "$(truncate_middle "$response_text" 4000)")" --argjson usage "${usage:-null}" \
+        '{time: (now | floor), feature: $feature, provider: $provider, model: $model, prompt_chars: $prompt_chars,
+          latency_ms: $latency_ms, status: $status, response_text: $response_text, usage: $usage}') || return 0
+    [[ -n "$CLAM_ACTIVE_API_KEY" ]] && record="${record//"$CLAM_ACTIVE_API_KEY"/REDACTED_APIKEY}"
+
+    if [[ -f "$log_file" ]] && (( $(wc -c < "$log_file") > max_bytes )); then
+        mv -f "$log_file" "$log_file.1" 2>/dev/null
+    fi
+    echo "$record" 2>/dev/null >> "$log_file" || note_storage_failure "$log_file"
+}
 
-    if [[ -z "$api_key" && "${CLAM_PROVIDER^^}" != "OLLAMA" ]]; then
-        echo_error "CLAM_ACTIVE_API_KEY not set. Run: clam config (or set OPENAI_API_KEY)"
-        return 1
+send_chat() {
+    local label="$1"
+    local payload="$2"
+    local timeout="$3"
+    local max_attempts="${4:-1}"
+
+    is_privacy_mode && { skip_for_privacy "$label"; return; }
+
+    local circuit=$(get_circuit_state)
+    if [[ $(echo "$circuit" | jq -r '.state') == "open" ]]; then
+        echo_error "$label skipped: ${CLAM_PROVIDER:-openai} is temporarily unavailable after $(echo "$circuit" | jq -r '.failures') failed requests ($(echo "$circuit" | jq -r '.last_error')). Retrying in $(echo "$circuit" | jq -r '.retry_in')s."
+        return "$(jq -r --argjson fallback "$CLAM_EXIT_PROVIDER" '.exit_code // $fallback' "$(get_circuit_file)")"
     fi
 
-    local payload=$(build_fep_payload "$prompt")
-    local max_attempts=2
+    local endpoint=$(get_endpoint)
     local attempt=1
-    local response status_code response_body
+    local response curl_status status_code response_body
 
-    while [[ $attempt -le $max_attempts ]]; do
-        response=$(call_api "$endpoint" "$payload" "$api_key" "$timeout")
+    while true; do
+        local started_ms=$(date +%s%3N)
+        response=$(call_api "$endpoint" "$payload" "$CLAM_ACTIVE_API_KEY" "$timeout")
+        curl_status=$?
         status_code=$(echo "$response" | tail -n1)
         response_body=$(echo "$response" | sed '$d')
-
-        if [[ $status_code -eq 200 ]]; then
-            break
+        log_llm_debug "$label" "$payload" "$status_code" "$response_body" "$(( $(date +%s%3N) - started_ms ))"
+
+        [[ $status_code -eq 200 ]] && break
+        if (( attempt >= max_attempts )); then
+            local reason="$(describe_api_failure "$status_code")${response_body:+ - $(extract_api_error "$response_body")}"
+            local exit_code=$(api_error_exit_code "$status_code")
+            # curl exits 28 at its own deadline and timeout(1) exits 124;
+            # anything else with no status is a connection failure.
+            if [[ "$exit_code" -eq $CLAM_EXIT_NETWORK ]] && (( curl_status == 28 || curl_status == 124 )); then
+                reason="Timed out after ${timeout}s with no response from ${CLAM_ENDPOINT:-the API}."
+                exit_code=$CLAM_EXIT_TIMEOUT
+            fi
+            record_circuit_result "$status_code" "$reason"
+            echo_error "$label failed. $reason"
+            return "$exit_code"
         fi
-        echo_error "API call failed with status $status_code. Retrying... (Attempt $attempt of $max_attempts)" >&2
+        echo_error "API call failed with status $status_code. Retrying... (Attempt $attempt of $max_attempts)"
         sleep 1
         attempt=$((attempt + 1))
     done
 
-    if [[ $status_code -ne 200 ]]; then
-        echo_error "FEP request failed. Status: $status_code"
+    record_circuit_result "$status_code"
+    echo "$response_body"
+}
+
+extract_usage() {
+    local response_body="$1"
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC") echo "$response_body" | jq -c '{prompt_tokens: .usage.input_tokens, completion_tokens: .usage.output_tokens}' 2>/dev/null ;;
+        "OLLAMA") echo "$response_body" | jq -c '{prompt_tokens: .prompt_eval_count, completion_tokens: .eval_count}' 2>/dev/null ;;
+        *) echo "$response_body" | jq -c '{prompt_tokens: .usage.prompt_tokens, completion_tokens: .usage.completion_tokens}' 2>/dev/null ;;
+    esac
+}
+
+call_api_get() {
+    local url="$1"
+    local timeout="$2"
+    local connect_timeout="$3"
+    is_privacy_mode && return 1
+    local curl_args=(-s -m "$timeout" -w "\n%{http_code}")
+    [[ -n "$connect_timeout" ]] && curl_args+=(--connect-timeout "$connect_timeout")
+
+    if [[ -n "$CLAM_PROXY_URL" ]]; then
+        if ! is_valid_proxy_url "$CLAM_PROXY_URL"; then
+            echo_error "Invalid proxy_url '$CLAM_PROXY_URL': expected http://, https://, socks5:// or socks5h:// followed by a host"
+            return 1
+        fi
+        curl_args+=(--proxy "$CLAM_PROXY_URL")
+    elif [[ "$CLAM_RESPECT_SYSTEM_PROXY" == "false" ]]; then
+        curl_args+=(--noproxy '*')
+    fi
+
+    command curl "${curl_args[@]}" "$url"
+}
+
+get_ollama_base_url() {
+    if [[ "${CLAM_PROVIDER^^}" == "OLLAMA" && -n "$CLAM_ENDPOINT" ]]; then
+        local endpoint=$(get_endpoint)
+        echo "${endpoint%%/api/*}"
+    else
+        echo "http://localhost:11434"
+    fi
+}
+
+list_ollama_models() {
+    local base_url="${1:-$(get_ollama_base_url)}"
+    if is_privacy_mode; then
+        echo_error "Not listing Ollama models at $base_url: privacy mode is on"
+        return 1
+    fi
+    local response=$(call_api_get "$base_url/api/tags" 2 1)
+    local status_code=$(echo "$response" | tail -n1)
+    local response_body=$(echo "$response" | sed '$d')
+
+    if [[ "$status_code" != "200" ]]; then
+        echo_error "Could not list Ollama models at $base_url: $(describe_api_failure "$status_code")"
+        return 1
+    fi
+    echo "$response_body" | jq -r '.models[]?.name // empty' 2>/dev/null
+}
+
+register_ollama_models() {
+    local base_url=$(get_ollama_base_url)
+    local name
+    while IFS= read -r name; do
+        [[ -z "$name" || -n "${CLAM_MODELS["ollama:	$name"]}" ]] && continue
+        CLAM_MODELS["ollama:	$name"]=$(jq -nc --arg model "$name" --arg endpoint "$base_url/api/chat" \
+            '{completion_cost: 0, prompt_cost: 0, endpoint: $endpoint, model: $model, provider: "ollama"}')
+    done <<< "$(list_ollama_models "$base_url" 2>/dev/null)"
+}
+
+test_connection() {
+    local endpoint=$(get_endpoint)
+    local timeout=$(resolve_timeout "${CLAM_COMPLETION_TIMEOUT:-$CLAM_TIMEOUT}" 30)
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local start_ms=$(date +%s%3N)
+    local response ok=false reason=""
+
+    if is_privacy_mode; then
+        reason="Privacy mode is on, so no request was sent. Run clam privacy off first."
+    elif [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        reason="API key not set. Configure with: clam config set ${CLAM_PROVIDER:-openai}_api_key <key>"
+    elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
+        response=$(call_api_get "$(get_ollama_base_url)/api/tags" "$timeout")
+    else
+        response=$(call_api "$endpoint" "$(build_ping_payload)" "$CLAM_ACTIVE_API_KEY" "$timeout")
+    fi
+    local latency_ms=$(( $(date +%s%3N) - start_ms ))
+
+    if [[ -z "$reason" ]]; then
+        local status_code=$(echo "$response" | tail -n1)
+        local response_body=$(echo "$response" | sed '$d')
+
+        if [[ "$status_code" != "200" ]]; then
+            reason="$(describe_api_failure "$status_code")${response_body:+ ($(extract_api_error "$response_body"))}"
+        elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]] &&
+             ! echo "$response_body" | jq -e --arg model "$model" \
+                 'any(.models[]?.name; . == $model or . == ($model + ":latest"))' &>/dev/null; then
+            reason="Ollama is running but model '$model' is not available: run \`ollama pull $model\`."
+        else
+            ok=true
+        fi
+    fi
+
+    jq -n --argjson ok "$ok" --argjson latency_ms "$latency_ms" --arg reason "$reason" \
+        '{ok: $ok, latency_ms: $latency_ms, reason: $reason}'
+}
+
+meets_min_input_length() {
+    local input="$1"
+    local min_length="${CLAM_MIN_INPUT_LEN:-2}"
+
+    [[ "$min_length" =~ ^[0-9]+$ ]] || min_length=2
+    input="${input#"${input%%[![:space:]]*}"}"
+    input="${input%"${input##*[![:space:]]}"}"
+    (( ${#input} >= min_length ))
+}
+
+CLAM_COMMON_COMMANDS=(
+    git ls cd cat grep make cargo docker kubectl npm yarn node python python3 pip
+    sudo ssh scp curl wget vim nano less head tail find mkdir rm mv cp chmod
+    chown echo tar man top ps kill clear history
+)
+
+correct_typo() {
+    local input="$1"
+
+    if [[ "$input" =~ ^cd\.\.(/.*)?$ ]]; then
+        echo "cd ${input#cd}"
+        return 0
+    fi
+
+    local word="${input%% *}"
+    local rest="${input#"$word"}"
+    [[ "$word" =~ ^[a-z0-9]{2,}$ ]] || return 1
+    type -t "$word" &>/dev/null && return 1
+
+    local fix=$(printf '%s\n' "${CLAM_COMMON_COMMANDS[@]}" | awk -v word="$word" '
+        function edit_distance(a, b,    i, j, cost, d, best) {
+            for (i = 0; i <= length(a); i++) d[i, 0] = i
+            for (j = 0; j <= length(b); j++) d[0, j] = j
+            for (i = 1; i <= length(a); i++) {
+                for (j = 1; j <= length(b); j++) {
+                    cost = (substr(a, i, 1) == substr(b, j, 1)) ? 0 : 1
+                    best = d[i - 1, j] + 1
+                    if (d[i, j - 1] + 1 < best) best = d[i, j - 1] + 1
+                    if (d[i - 1, j - 1] + cost < best) best = d[i - 1, j - 1] + cost
+                    if (i > 1 && j > 1 && substr(a, i, 1) == substr(b, j - 1, 1) &&
+                        substr(a, i - 1, 1) == substr(b, j, 1) && d[i - 2, j - 2] + 1 < best) {
+                        best = d[i - 2, j - 2] + 1
+                    }
+                    d[i, j] = best
+                }
+            }
+            return d[length(a), length(b)]
+        }
+        {
+            if (edit_distance(word, $0) != 1) next
+            if (length(word) <= 2 && !(length($0) == 2 && substr(word, 1, 1) == substr($0, 2, 1) && substr(word, 2, 1) == substr($0, 1, 1))) next
+            matches++
+            candidate = $0
+        }
+        END { if (matches == 1) print candidate }')
+
+    [[ -n "$fix" ]] || return 1
+    echo "$fix$rest"
+}
+
+get_completion() {
+    local timeout=$(resolve_timeout "${CLAM_COMPLETION_TIMEOUT:-$CLAM_TIMEOUT}" 30)
+    local default_input="Write two to six most likely commands given the provided information"
+    local user_input=${*:-$default_input}
+
+    if [[ -n "$*" ]] && ! meets_min_input_length "$*"; then
+        return
+    fi
+
+    local typo_fix=""
+    [[ -n "$*" ]] && typo_fix=$(correct_typo "$*") && echo "$typo_fix|||Fixes a typo in the command"
+    is_privacy_mode && return 0
+
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        echo_error "CLAM_ACTIVE_API_KEY not set. Please set it with: export ${CLAM_PROVIDER^^}_API_KEY=<your-api-key>"
+        return "$CLAM_EXIT_CONFIG"
+    fi
+
+    local payload=$(build_completion_payload "$user_input")
+    local response_body status=0
+    local started_ms=$(date +%s%3N)
+    response_body=$(send_chat "Completion request" "$payload" "$timeout" 2) || status=$?
+    record_completion_latency "$(( $(date +%s%3N) - started_ms ))"
+    (( status == 0 )) || return "$status"
+
+    echo -n "$(parse_completion_response "$response_body" | awk -F'[|][|][|]' -v fix="$typo_fix" 'fix == "" || $1 != fix')"
+    log_api_request "$user_input" "$response_body"
+}
+
+parse_completion_response() {
+    local response_body="$1"
+    local content
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC")
+            content=$(echo "$response_body" | jq -r '.content[0].input.suggestions')
+            ;;
+        "GROQ"|"MISTRAL")
+            content=$(echo "$response_body" | jq -r '.choices[0].message.content')
+            content=$(echo "$content" | jq -r '.suggestions // .completions')
+            ;;
+        "OLLAMA")
+            content=$(echo "$response_body" | jq -r '.message.content')
+            content=$(echo "$content" | jq -r '.suggestions // .completions')
+            ;;
+        *)
+            content=$(echo "$response_body" | jq -r '.choices[0].message.tool_calls[0].function.arguments // .choices[0].message.content')
+            content=$(extract_json "$content" | jq -r '.suggestions // .completions // .commands')
+            ;;
+    esac
+
+    if echo "$content" | jq -e 'type == "array"' &>/dev/null; then
+        echo "$content" | jq -r '.[] | .command + "|||" + .explanation' | grep -v '^$'
+    else
+        echo "$content" | jq -r '.[]' | grep -v '^$' | sed 's/$/|||/'
+    fi
+}
+
+get_fep_completion() {
+    local user_context="$1"
+    is_privacy_mode && { skip_for_privacy "FEP request"; return; }
+
+    local prompt=$(build_fep_prompt "$user_context")
+    local timeout=$(resolve_timeout "${CLAM_FEP_TIMEOUT:-$CLAM_TIMEOUT}" 60)
+
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        echo_error "CLAM_ACTIVE_API_KEY not set. Run: clam config (or set OPENAI_API_KEY)"
         return 1
     fi
 
+    local payload=$(build_fep_payload "$prompt")
+    local response_body
+    response_body=$(send_chat "FEP request" "$payload" "$timeout" 2) || return
+
+    if [[ -z "$(parse_fep_fixes "$(extract_message_content "$response_body")")" ]]; then
+        echo_error "FEP response was not valid JSON, retrying once."
+        payload=$(echo "$payload" | add_json_reminder)
+        response_body=$(send_chat "FEP request" "$payload" "$timeout") || return
+    fi
     echo "$response_body"
 }
 
+record_harm_timeout() {
+    local timeout_file=${CLAM_HARM_TIMEOUT_LOG_FILE:-"$HOME/.clam/harm_timeouts.log"}
+    mkdir -p "$(dirname "$timeout_file")"
+    date +%s >> "$timeout_file"
+}
+
+get_harm_timeout_count() {
+    local timeout_file=${CLAM_HARM_TIMEOUT_LOG_FILE:-"$HOME/.clam/harm_timeouts.log"}
+    if [[ -f "$timeout_file" ]]; then
+        wc -l < "$timeout_file"
+    else
+        echo 0
+    fi
+}
+
+# Persisted caches depend on this staying stable; tests/test_cache.sh pins
+# known outputs.
+cache_key() {
+    local input="$1"
+    local provider="${2:-${CLAM_PROVIDER:-openai}}"
+    local model="${3:-${CLAM_MODEL:-gpt-4o}}"
+    printf '%s|%s|%s' "$provider" "$model" "$input" | sha256sum | cut -d ' ' -f 1
+}
+
+CLAM_QUICK_SAFE_COMMANDS=(
+    ls cd id which type cat head tail wc du df free file stat
+    "git status" "git log" "git diff" "git show"
+)
+
+# These change state when given arguments (date -s, hostname NAME, history -c).
+CLAM_QUICK_SAFE_BARE_COMMANDS=(pwd whoami date cal uptime hostname clear history)
+
+quick_safe_check() {
+    local command="$1"
+
+    [[ -n "$command" ]] || return 1
+    [[ "$command" == *[\;\&\|\<\>\$\`\(\)\{\}]* || "$command" == *$'\n'* ]] && return 1
+
+    local -a words
+    read -ra words <<< "$command"
+    local safe_command word
+    for safe_command in "${CLAM_QUICK_SAFE_BARE_COMMANDS[@]}"; do
+        [[ "${words[0]}" == "$safe_command" ]] && return $(( ${#words[@]} > 1 ))
+    done
+    for safe_command in "${CLAM_QUICK_SAFE_COMMANDS[@]}"; do
+        if [[ "$safe_command" == *" "* ]]; then
+            [[ "${words[0]} ${words[1]}" == "$safe_command" ]] || continue
+            for word in "${words[@]:2}"; do
+                [[ "$word" == --output* || "$word" == "--ext-diff" || "$word" == "-c" ]] && return 1
+            done
+            return 0
+        elif [[ "${words[0]}" == "$safe_command" ]]; then
+            return 0
+        fi
+    done
+    return 1
+}
+
 detect_command_harm() {
     local command="$1"
+
+    if quick_safe_check "$command"; then
+        echo '{"is_harmful":false,"explanation":"Read-only command - no check needed"}'
+        return 0
+    fi
     load_config
 
-    local command_hash=$(echo -n "$command" | md5sum | cut -d ' ' -f 1)
+    if is_privacy_mode; then
+        echo '{"is_harmful":false,"explanation":"Privacy mode - only local patterns checked"}'
+        return 0
+    fi
+
+    local command_hash=$(cache_key "$command")
     local cache_dir="${CLAM_HARM_CACHE_DIR:-$HOME/.clam/harm_cache}"
     local cache_file="$cache_dir/harm-$command_hash.json"
 
-    if [[ -d "$cache_dir" && -f "$cache_file" ]]; then
-        cat "$cache_file"
+    if [[ -f "$cache_file" ]] && cache_entry_expired "$cache_file" "${CLAM_HARM_CACHE_TTL:-0}"; then
+        command rm -f "$cache_file"
+    fi
+
+    local cache_size="${CLAM_HARM_CACHE_SIZE:-100}"
+    if [[ -d "$cache_dir" && "$cache_size" -gt 0 && -f "$cache_file" ]] &&
+       jq -ce --arg command "$command" 'select(.command == $command) | .verdict' "$cache_file" 2>/dev/null; then
+        return 0
+    fi
+
+    if [[ $(get_circuit_state | jq -r '.state') == "open" ]]; then
+        echo_error "Harm detection skipped: ${CLAM_PROVIDER:-openai} is temporarily unavailable. Allowing command execution."
+        echo '{"is_harmful":false,"explanation":"Provider temporarily unavailable - defaulting to safe"}'
         return 0
     fi
 
-    local endpoint=${CLAM_ENDPOINT:-"https://api.openai.com/v1/chat/completions"}
-    local timeout=${CLAM_HARM_TIMEOUT:-3}
-    local api_key="$CLAM_ACTIVE_API_KEY"
+    local timeout=$(resolve_timeout "$CLAM_HARM_TIMEOUT" 3)
     local payload=$(build_harm_detection_payload "$command")
+    local response_body status=0
+    response_body=$(send_chat "Harm detection" "$payload" "$timeout") || status=$?
 
-    local response=$(call_api "$endpoint" "$payload" "$api_key" "$timeout")
-    local status_code=$(echo "$response" | tail -n1)
-    local response_body=$(echo "$response" | sed '$d')
+    if [[ $status -eq $CLAM_EXIT_TIMEOUT ]]; then
+        record_harm_timeout
+        echo_error "Harm detection timed out after ${timeout}s. Allowing command execution."
+        echo '{"is_harmful":false,"explanation":"Harm check timed out - defaulting to safe"}'
+        return 0
+    elif [[ $status -ne 0 ]]; then
+        echo_error "Allowing command execution."
+        echo '{"is_harmful":false,"explanation":"API unavailable - defaulting to safe"}'
+        return 0
+    fi
+
+    local harm_data=$(parse_harm_response "$(extract_harm_reply "$response_body")")
+
+    if [[ -z "$harm_data" ]]; then
+        echo_error "Harm detection response was not valid JSON, retrying once."
+        payload=$(echo "$payload" | add_json_reminder)
+        response_body=$(send_chat "Harm detection" "$payload" "$timeout") &&
+            harm_data=$(parse_harm_response "$(extract_harm_reply "$response_body")")
+    fi
+
+    if [[ -z "$harm_data" ]]; then
+        echo_error "Malformed harm detection response. Allowing command execution." >&2
+        echo '{"is_harmful":false,"explanation":"Malformed response - defaulting to safe"}'
+        return 0
+    fi
+
+    if [[ "$cache_size" -gt 0 ]]; then
+        mkdir -p "$cache_dir"
+        jq -nc --arg command "$command" --argjson verdict "$harm_data" '{command: $command, verdict: $verdict}' > "$cache_file"
+        evict_harm_cache "$cache_dir" "$cache_size"
+    fi
+    echo "$harm_data"
+}
+
+extract_harm_reply() {
+    local response_body="$1"
+    if [[ "${CLAM_PROVIDER^^}" == "ANTHROPIC" ]]; then
+        echo "$response_body" | jq -c '.content[0].input'
+    elif [[ "${CLAM_PROVIDER^^}" == "GROQ" || "${CLAM_PROVIDER^^}" == "MISTRAL" ]]; then
+        echo "$response_body" | jq -r '.choices[0].message.content'
+    elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
+        echo "$response_body" | jq -r '.message.content'
+    else
+        echo "$response_body" | jq -r '.choices[0].message.tool_calls[0].function.arguments // .choices[0].message.content'
+    fi
+}
+
+evict_harm_cache() {
+    evict_oldest_files "$1" "harm-*.json" "$2"
+}
+
+evict_oldest_files() {
+    local cache_dir="$1"
+    local pattern="$2"
+    local cache_size="$3"
+    local excess=$(( $(find "$cache_dir" -maxdepth 1 -type f -name "$pattern" | wc -l) - cache_size ))
+
+    (( excess <= 0 )) && return
+    find "$cache_dir" -maxdepth 1 -type f -name "$pattern" -printf '%T+ %p\n' | sort \
+        | head -n "$excess" | cut -d ' ' -f 2- | xargs -r rm -f
+}
+
+extract_json() {
+    local text="$1"
+    local filter="${2:-.}"
+    local accept="select(type == \"object\" or type == \"array\") | $filter"
+    local candidate
+
+    echo "$text" | jq -sce ".[0] | $accept" 2>/dev/null && return 0
+
+    local sources=("$text")
+    if [[ "$text" == *'```'* ]]; then
+        local fenced=$(echo "$text" | awk '/^[[:space:]]*```/ { if (inside) exit; inside = 1; next } inside')
+        echo "$fenced" | jq -sce ".[0] | $accept" 2>/dev/null && return 0
+        sources=("$fenced" "$text")
+    fi
+
+    local source
+    for source in "${sources[@]}"; do
+        while IFS= read -r -d $'\001' candidate; do
+            echo "$candidate" | jq -ce "$accept" 2>/dev/null && return 0
+        done < <(printf '%s' "$source" | awk 'BEGIN { RS = "\001" } {
+            text = $0; n = length(text); depth = 0; in_string = 0
+            for (i = 1; i <= n; i++) {
+                c = substr(text, i, 1)
+                if (in_string) {
+                    if (c == "\\") i++
+                    else if (c == "\"") in_string = 0
+                } else if (c == "\"" && depth > 0) {
+                    in_string = 1
+                } else if (c == "{" || c == "[") {
+                    if (depth == 0) start = i
+                    depth++
+                } else if ((c == "}" || c == "]") && depth > 0) {
+                    if (--depth == 0) printf "%s\001", substr(text, start, i - start + 1)
+                }
+            }
+        }')
+    done
+    return 1
+}
+
+parse_harm_response() {
+    extract_json "$1" 'select(type == "object" and has("is_harmful"))'
+}
+
+extract_message_content() {
+    local response_body="$1"
+    case "${CLAM_PROVIDER^^}" in
+        "ANTHROPIC") echo "$response_body" | jq -r '.content[0].text // empty' ;;
+        "OLLAMA") echo "$response_body" | jq -r '.message.content // empty' ;;
+        *) echo "$response_body" | jq -r '.choices[0].message.content // empty' ;;
+    esac
+}
+
+parse_explain_response() {
+    local content="$1"
+    echo "$content" | jq -c '
+        select(type == "object" and (.summary // "") != "")
+        | {summary, side_effects: ((.side_effects // []) | map(tostring))}' 2>/dev/null
+}
+
+get_command_explanation() {
+    local command="$1"
+    load_config
+    is_privacy_mode && { skip_for_privacy "Explain request"; return; }
+
+    local command_hash=$(cache_key "$command")
+    local cache_dir="${CLAM_EXPLAIN_CACHE_DIR:-$HOME/.clam/explain_cache}"
+    local cache_file="$cache_dir/explain-$command_hash.json"
+    local cache_size="${CLAM_EXPLAIN_CACHE_SIZE:-100}"
+
+    if [[ -f "$cache_file" ]] && cache_entry_expired "$cache_file" "${CLAM_EXPLAIN_CACHE_TTL:-0}"; then
+        command rm -f "$cache_file"
+    fi
+
+    if [[ "$cache_size" -gt 0 && -f "$cache_file" ]]; then
+        cat "$cache_file"
+        return 0
+    fi
+
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        echo_error "CLAM_ACTIVE_API_KEY not set. Run: clam config (or set OPENAI_API_KEY)"
+        return 1
+    fi
+
+    local timeout=$(resolve_timeout "${CLAM_COMPLETION_TIMEOUT:-$CLAM_TIMEOUT}" 30)
+    local payload=$(build_explain_payload "$command")
+    local response_body
+    response_body=$(send_chat "Explain request" "$payload" "$timeout") || return
+
+    local explanation=$(parse_explain_response "$(extract_message_content "$response_body")")
+    if [[ -z "$explanation" ]]; then
+        echo_error "Could not parse explanation from response"
+        return 1
+    fi
+
+    if [[ "$cache_size" -gt 0 ]]; then
+        mkdir -p "$cache_dir"
+        echo "$explanation" > "$cache_file"
+        evict_oldest_files "$cache_dir" "explain-*.json" "$cache_size"
+    fi
+    echo "$explanation"
+}
+
+parse_generate_response() {
+    local content="$1"
+    echo "$content" | jq -c '
+        select(type == "object" and (.command // "") != "")
+        | {command, explanation: (.explanation // "")}' 2>/dev/null
+}
+
+generate_command() {
+    local description="$1"
+    load_config
+    is_privacy_mode && { skip_for_privacy "Generate request"; return; }
+
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        echo_error "CLAM_ACTIVE_API_KEY not set. Run: clam config (or set OPENAI_API_KEY)"
+        return 1
+    fi
+
+    local timeout=$(resolve_timeout "${CLAM_COMPLETION_TIMEOUT:-$CLAM_TIMEOUT}" 30)
+    local payload=$(build_generate_payload "$description")
+    local response_body
+    response_body=$(send_chat "Generate request" "$payload" "$timeout") || return
+
+    local generated=$(parse_generate_response "$(extract_message_content "$response_body")")
+    if [[ -z "$generated" ]]; then
+        echo_error "Could not parse a command from the response"
+        return 1
+    fi
+
+    local safeguard=$(check_command_safeguard "$(echo "$generated" | jq -r '.command')")
+    echo "$generated" | jq -c --argjson safeguard "$safeguard" \
+        '. + {warning: (if $safeguard.matched then {severity: $safeguard.severity, description: $safeguard.description} else null end)}'
+}
+
+parse_next_command_response() {
+    local content="$1"
+    echo "$content" | jq -c '
+        (if type == "array" then . elif type == "object" and has("suggestions") then .suggestions else [.] end)
+        | map(select(type == "object" and (.command // "") != ""))
+        | map({command, explanation: (.explanation // "")})
+        | .[:5]' 2>/dev/null
+}
+
+suggest_next_command() {
+    local output
+    output=$(get_recent_output "${CLAM_RECENT_OUTPUT_LINES:-20}")
+    if [[ -z "${output//[[:space:]]/}" ]]; then
+        echo '[]'
+        return 0
+    fi
+    load_config
+    is_privacy_mode && { skip_for_privacy "Next command request"; return; }
+
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        echo_error "CLAM_ACTIVE_API_KEY not set. Run: clam config (or set OPENAI_API_KEY)"
+        return 1
+    fi
+
+    local timeout=$(resolve_timeout "${CLAM_COMPLETION_TIMEOUT:-$CLAM_TIMEOUT}" 30)
+    local payload=$(build_next_command_payload "$(sanitize_text "$CLAM_LAST_COMMAND")" \
+        "$(sanitize_text "$(truncate_middle "$output" 2000)")")
+    local response_body
+    response_body=$(send_chat "Next command request" "$payload" "$timeout") || return
+
+    local suggestions=$(parse_next_command_response "$(extract_message_content "$response_body")")
+    if [[ -z "$suggestions" || "$suggestions" == "[]" ]]; then
+        echo_error "Could not parse any suggestions from the response"
+        return 1
+    fi
+    echo "$suggestions"
+}
 
-    if [[ $status_code -ne 200 ]]; then
-        echo_error "Harm detection API call failed with status $status_code. Allowing command execution." >&2
-        echo '{"is_harmful":false,"explanation":"API unavailable - defaulting to safe"}'
+parse_recap_response() {
+    local content="$1"
+    echo "$content" | jq -c '
+        select(type == "object" and (.summary // "") != "")
+        | {summary, next_steps: ((.next_steps // []) | map(tostring))}' 2>/dev/null
+}
+
+summarize_session() {
+    local session=$(sanitize_text "$CLAM_SESSION_COMMANDS")
+    if [[ -z "$session" ]]; then
+        echo '{"summary":"","next_steps":[]}'
         return 0
     fi
+    load_config
+    is_privacy_mode && { skip_for_privacy "Recap request"; return; }
 
-    local harm_data
-    if [[ "${CLAM_PROVIDER^^}" == "ANTHROPIC" ]]; then
-        harm_data=$(echo "$response_body" | jq -r '.content[0].input')
-    elif [[ "${CLAM_PROVIDER^^}" == "GROQ" ]]; then
-        harm_data=$(echo "$response_body" | jq -r '.choices[0].message.content')
-    elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
-        harm_data=$(echo "$response_body" | jq -r '.message.content')
-    else
-        local arguments_string=$(echo "$response_body" | jq -r '.choices[0].message.tool_calls[0].function.arguments // .choices[0].message.content')
-        harm_data=$(echo "$arguments_string" | jq -c '.')
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
+        echo_error "CLAM_ACTIVE_API_KEY not set. Run: clam config (or set OPENAI_API_KEY)"
+        return 1
     fi
 
-    if ! echo "$harm_data" | jq -e 'has("is_harmful")' &>/dev/null; then
-        echo_error "Malformed harm detection response. Allowing command execution." >&2
-        echo '{"is_harmful":false,"explanation":"Malformed response - defaulting to safe"}'
-        return 0
-    fi
+    local timeout=$(resolve_timeout "${CLAM_COMPLETION_TIMEOUT:-$CLAM_TIMEOUT}" 30)
+    local payload=$(build_recap_payload "$session")
+    local response_body
+    response_body=$(send_chat "Recap request" "$payload" "$timeout") || return
 
-    mkdir -p "$cache_dir"
-    echo "$harm_data" > "$cache_file"
-    echo "$harm_data"
+    local recap=$(parse_recap_response "$(extract_message_content "$response_body")")
+    if [[ -z "$recap" ]]; then
+        echo_error "Could not parse a recap from the response"
+        return 1
+    fi
+    echo "$recap"
 }
 
 # === Bash Completion ===
@@ -725,6 +2303,128 @@ list_cache() {
     find "$cache_dir" -maxdepth 1 -type f -name "acsh-*" -printf '%T+ %p\n' | sort
 }
 
+pin_cache_entry() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash=$(cache_key "$1")
+
+    mkdir -p "$cache_dir"
+    grep -qx "$input_hash" "$cache_dir/pinned" 2>/dev/null || echo "$input_hash" >> "$cache_dir/pinned"
+}
+
+unpin_cache_entry() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash=$(cache_key "$1")
+
+    [[ -f "$cache_dir/pinned" ]] && sed -i "/^$input_hash\$/d" "$cache_dir/pinned"
+}
+
+cache_entry_expired() {
+    local file="$1"
+    local ttl="${2:-0}"
+    local created="$3"
+
+    [[ "$ttl" =~ ^[0-9]+$ ]] && (( ttl > 0 )) || return 1
+    [[ "$created" =~ ^[0-9]+$ ]] || created=$(stat -c %Y "$file")
+    (( $(date +%s) - created >= ttl ))
+}
+
+get_cache_hits() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash="$1"
+
+    [[ -f "$cache_dir/hits" ]] || { echo 0; return; }
+    awk -v hash="$input_hash" '$1 == hash { count = $2 } END { print count + 0 }' "$cache_dir/hits"
+}
+
+# The hits file also keeps when each entry was written, because hits touch the
+# entry for LRU eviction and its mtime no longer says how old it is.
+get_cache_created() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash="$1"
+
+    [[ -f "$cache_dir/hits" ]] || return 0
+    awk -v hash="$input_hash" '$1 == hash { created = $3 } END { print created }' "$cache_dir/hits"
+}
+
+record_cache_entry() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash="$1"
+
+    reset_cache_hits "$input_hash"
+    echo "$input_hash 0 $(date +%s)" >> "$cache_dir/hits"
+}
+
+record_cache_hit() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash="$1"
+    local hits=$(( $(get_cache_hits "$input_hash") + 1 ))
+    local created=$(get_cache_created "$input_hash")
+
+    [[ -n "$created" ]] || created=$(stat -c %Y "$cache_dir/acsh-$input_hash.txt" 2>/dev/null)
+    reset_cache_hits "$input_hash"
+    echo "$input_hash $hits $created" >> "$cache_dir/hits"
+}
+
+reset_cache_hits() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash="$1"
+
+    [[ -f "$cache_dir/hits" ]] || return 0
+    grep -v "^$input_hash " "$cache_dir/hits" > "$cache_dir/hits.tmp"
+    mv "$cache_dir/hits.tmp" "$cache_dir/hits"
+}
+
+effective_cache_ttl() {
+    local ttl="$1"
+    local hits="$2"
+
+    if (( ttl == 0 )); then
+        echo 0
+    elif (( hits == 0 )); then
+        echo $(( (ttl + 1) / 2 ))
+    else
+        # Counted from when the entry was written, so 8x is a hard cap on how
+        # long a hot key lives however often it is hit.
+        local doublings=$(( hits > 4 ? 3 : hits - 1 ))
+        echo $(( ttl << doublings ))
+    fi
+}
+
+completion_cache_expired() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local input_hash="$1"
+    local cache_file="$cache_dir/acsh-$input_hash.txt"
+
+    [[ -f "$cache_file" ]] || return 1
+    grep -qx "$input_hash" "$cache_dir/pinned" 2>/dev/null && return 1
+    cache_entry_expired "$cache_file" "$(effective_cache_ttl "${CLAM_CACHE_TTL:-0}" "$(get_cache_hits "$input_hash")")" \
+        "$(get_cache_created "$input_hash")"
+}
+
+evict_completion_cache() {
+    local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
+    local cache_size=${CLAM_CACHE_SIZE:-100}
+    local pinned_file="$cache_dir/pinned"
+    local excess=$(( $(list_cache | wc -l) - cache_size ))
+
+    if (( excess > 0 )); then
+        list_cache | cut -d ' ' -f 2- | while read -r file; do
+            local input_hash=$(basename "$file" .txt)
+            input_hash="${input_hash#acsh-}"
+            grep -qx "$input_hash" "$pinned_file" 2>/dev/null && continue
+            echo "$file"
+        done | head -n "$excess" | xargs -r rm -f
+    fi
+
+    if [[ -f "$cache_dir/hits" ]]; then
+        local input_hash fields
+        while read -r input_hash fields; do
+            [[ -f "$cache_dir/acsh-$input_hash.txt" ]] && echo "$input_hash $fields"
+        done < "$cache_dir/hits" > "$cache_dir/hits.tmp"
+        mv "$cache_dir/hits.tmp" "$cache_dir/hits"
+    fi
+}
+
 clam_completion() {
     _init_completion || return
     run_default_completion
@@ -732,52 +2432,64 @@ clam_completion() {
 
 interactive_clam_widget() {
     local user_input="${READLINE_LINE}"
-    local show_explanations=false
 
     if [[ -z "$user_input" ]]; then
         return
     fi
 
+    load_config
+    meets_min_input_length "$user_input" || return
+    local show_explanations="${CLAM_SHOW_EXPLANATIONS:-false}"
+
     if [[ "$user_input" == *"--explain"* ]]; then
         show_explanations=true
         user_input="${user_input%%--explain*}"
         user_input="${user_input%% }"
     fi
 
-    load_config
-
-    if [[ -z "$CLAM_ACTIVE_API_KEY" && ${CLAM_PROVIDER^^} != "OLLAMA" ]]; then
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
         echo
         echo_error "API key not set. Configure with: clam config"
         return
     fi
 
-    local input_hash=$(echo -n "$user_input" | md5sum | cut -d ' ' -f 1)
+    local input_hash=$(cache_key "$user_input")
     local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
     local cache_size=${CLAM_CACHE_SIZE:-100}
     local cache_file="$cache_dir/acsh-$input_hash.txt"
     local completions
 
+    if completion_cache_expired "$input_hash"; then
+        command rm -f "$cache_file"
+        reset_cache_hits "$input_hash"
+    fi
+
     if [[ -d "$cache_dir" && "$cache_size" -gt 0 && -f "$cache_file" ]]; then
         completions=$(cat "$cache_file" || true)
+        record_cache_hit "$input_hash"
         touch "$cache_file"
     else
         echo
         start_spinner "Generating suggestions..."
-        completions=$(get_completion "$user_input" || true)
+        local status=0
+        completions=$(run_cancellable suggestion get_completion "$user_input") || status=$?
         stop_spinner
 
-        if [[ -z "$completions" ]]; then
+        if [[ $status -eq $CLAM_EXIT_CANCELLED ]]; then
+            echo -e "\e[90mCancelled.\e[0m"
+            return
+        elif [[ -z "$completions" ]]; then
             echo_error "Failed to generate completions"
             return
         fi
 
         if [[ -d "$cache_dir" && "$cache_size" -gt 0 ]]; then
-            echo "$completions" > "$cache_file"
-            while [[ $(list_cache | wc -l) -gt "$cache_size" ]]; do
-                local oldest=$(list_cache | head -n 1 | cut -d ' ' -f 2-)
-                rm "$oldest" || true
-            done
+            if echo "$completions" 2>/dev/null > "$cache_file"; then
+                record_cache_entry "$input_hash"
+                evict_completion_cache
+            else
+                note_storage_failure "$cache_dir"
+            fi
         fi
     fi
 
@@ -798,8 +2510,114 @@ is_being_sourced() {
     [[ "${BASH_SOURCE[0]}" != "${0}" ]]
 }
 
+# Validation rules for config keys, as "type[:min:max]" or "enum:a,b,c".
+unset CLAM_CONFIG_RULES
+declare -A CLAM_CONFIG_RULES=(
+    [provider]='enum:openai,anthropic,groq,mistral,ollama,custom,mock'
+    [model]='string'
+    [temperature]='number:0:2'
+    [show_explanations]='boolean'
+    [completion_timeout]='number:1:600'
+    [fep_timeout]='number:1:600'
+    [fep_max_output_chars]='integer:200:100000'
+    [max_capture_bytes]='integer:1024:104857600'
+    [completion_max_tokens]='integer:1:8192'
+    [harm_max_tokens]='integer:1:8192'
+    [fep_max_tokens]='integer:1:8192'
+    [harm_timeout]='number:1:600'
+    [proxy_url]='proxy'
+    [respect_system_proxy]='boolean'
+    [ollama_keep_alive]='duration'
+    [circuit_breaker_threshold]='integer:0:100'
+    [circuit_breaker_cooldown]='integer:1:3600'
+    [max_history_commands]='integer:0:1000'
+    [rank_history_by_relevance]='boolean'
+    [max_recent_files]='integer:0:1000'
+    [include_dir_listing]='boolean'
+    [include_recent_output]='boolean'
+    [recent_output_lines]='integer:1:200'
+    [max_dir_entries]='integer:1:1000'
+    [max_env_vars]='integer:1:500'
+    [min_input_len]='integer:1:100'
+    [prompt_template]='string'
+    [redaction_patterns]='regex_list'
+    [sensitive_env_patterns]='regex_list'
+    [cache_size]='integer:0:10000'
+    [cache_ttl]='integer:0:31536000'
+    [harm_detection_enabled]='boolean'
+    [privacy_mode]='boolean'
+    [debug_log_llm]='boolean'
+    [harm_cache_size]='integer:0:10000'
+    [harm_cache_ttl]='integer:0:31536000'
+    [explain_cache_size]='integer:0:10000'
+    [explain_cache_ttl]='integer:0:31536000'
+    [block_dangerous]='boolean'
+    [block_severity]='enum:low,medium,high,critical'
+)
+
+declare -A _CLAM_SESSION_CONFIG=()
+
+validate_config_value() {
+    local key="$1"
+    local value="$2"
+    local rule="${CLAM_CONFIG_RULES[$key]}"
+    [[ -z "$rule" ]] && return 0
+
+    local rule_type="${rule%%:*}"
+    local min max
+    IFS=':' read -r _ min max <<< "$rule"
+
+    case "$rule_type" in
+        string)
+            [[ -n "$value" ]] && return 0
+            echo "$key must not be empty"
+            ;;
+        enum)
+            [[ ",${rule#enum:}," == *",$value,"* ]] && return 0
+            echo "$key must be one of: ${rule#enum:} (got '$value')"
+            ;;
+        boolean)
+            [[ "$value" == "true" || "$value" == "false" ]] && return 0
+            echo "$key must be true or false (got '$value')"
+            ;;
+        integer|number)
+            local number_regex='^[0-9]+$'
+            local description="an integer"
+            if [[ "$rule_type" == "number" ]]; then
+                number_regex='^[0-9]+(\.[0-9]+)?$'
+                description="a number"
+            fi
+            if [[ "$value" =~ $number_regex ]] && awk -v v="$value" -v lo="$min" -v hi="$max" 'BEGIN { exit !(v >= lo && v <= hi) }'; then
+                return 0
+            fi
+            echo "$key must be $description between $min and $max (got '$value')"
+            ;;
+        regex_list)
+            local pattern
+            while IFS= read -r pattern; do
+                if [[ -n "$pattern" ]] && ! is_valid_regex "$pattern"; then
+                    echo "$key contains an invalid regular expression: $pattern"
+                    return 1
+                fi
+            done <<< "$(split_patterns "$value")"
+            return 0
+            ;;
+        duration)
+            [[ "$value" =~ ^-?[0-9]+(ms|s|m|h)?$ ]] && return 0
+            echo "$key must be a duration such as 30s, 5m or 1h, or a number of seconds (got '$value')"
+            ;;
+        proxy)
+            if [[ -z "$value" ]] || is_valid_proxy_url "$value"; then
+                return 0
+            fi
+            echo "Invalid proxy_url '$value': expected http://, https://, socks5:// or socks5h:// followed by a host"
+            ;;
+    esac
+    return 1
+}
+
 load_config() {
-    local config_file="$HOME/.clam/config"
+    local config_file=$(get_config_path)
 
     if [ -f "$config_file" ]; then
         while IFS=':' read -r key value; do
@@ -808,15 +2626,29 @@ load_config() {
             fi
             key=$(echo "$key" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
             value=$(echo "$value" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+            if [[ -n $value ]]; then
+                local validation_error
+                if ! validation_error=$(validate_config_value "$key" "$value"); then
+                    echo_error "Ignoring invalid config value: $validation_error"
+                    continue
+                fi
+            fi
             key=$(echo "$key" | tr '[:lower:]' '[:upper:]' | sed 's/[^A-Z0-9]/_/g')
             if [[ -n $value ]]; then
                 export "CLAM_$key"="$value"
             fi
         done < "$config_file"
 
+        local session_key
+        for session_key in "${!_CLAM_SESSION_CONFIG[@]}"; do
+            key=$(echo "$session_key" | tr '[:lower:]' '[:upper:]' | sed 's/[^A-Z0-9]/_/g')
+            export "CLAM_$key"="${_CLAM_SESSION_CONFIG[$session_key]}"
+        done
+
         [[ -z "$CLAM_OPENAI_API_KEY" && -n "$OPENAI_API_KEY" ]] && export CLAM_OPENAI_API_KEY="$OPENAI_API_KEY"
         [[ -z "$CLAM_ANTHROPIC_API_KEY" && -n "$ANTHROPIC_API_KEY" ]] && export CLAM_ANTHROPIC_API_KEY="$ANTHROPIC_API_KEY"
         [[ -z "$CLAM_GROQ_API_KEY" && -n "$GROQ_API_KEY" ]] && export CLAM_GROQ_API_KEY="$GROQ_API_KEY"
+        [[ -z "$CLAM_MISTRAL_API_KEY" && -n "$MISTRAL_API_KEY" ]] && export CLAM_MISTRAL_API_KEY="$MISTRAL_API_KEY"
         [[ -z "$CLAM_OLLAMA_API_KEY" && -n "$LLM_API_KEY" ]] && export CLAM_OLLAMA_API_KEY="$LLM_API_KEY"
         [[ -z "$CLAM_OLLAMA_API_KEY" && -n "$CLAM_CUSTOM_API_KEY" ]] && export CLAM_OLLAMA_API_KEY="$CLAM_CUSTOM_API_KEY"
 
@@ -824,7 +2656,10 @@ load_config() {
             "openai") export CLAM_ACTIVE_API_KEY="$CLAM_OPENAI_API_KEY" ;;
             "anthropic") export CLAM_ACTIVE_API_KEY="$CLAM_ANTHROPIC_API_KEY" ;;
             "groq") export CLAM_ACTIVE_API_KEY="$CLAM_GROQ_API_KEY" ;;
+            "mistral") export CLAM_ACTIVE_API_KEY="$CLAM_MISTRAL_API_KEY" ;;
             "ollama") export CLAM_ACTIVE_API_KEY="$CLAM_OLLAMA_API_KEY" ;;
+            "custom") export CLAM_ACTIVE_API_KEY="$CLAM_CUSTOM_API_KEY" ;;
+            "mock") export CLAM_ACTIVE_API_KEY="" ;;
             *) echo_error "Unknown provider: $CLAM_PROVIDER" ;;
         esac
     else
@@ -832,12 +2667,22 @@ load_config() {
     fi
 }
 
+get_config_path() {
+    echo "${CLAM_CONFIG_FILE:-$HOME/.clam/config}"
+}
+
 create_default_config() {
-    local config_file="$HOME/.clam/config"
+    local config_file=$(get_config_path)
 
     if [ ! -f "$config_file" ]; then
-        echo "Creating default configuration file at ~/.clam/config"
-        cat > "$config_file" <<EOF
+        echo "Creating default configuration file at $config_file"
+        mkdir -p "$(dirname "$config_file")"
+        default_config_contents > "$config_file"
+    fi
+}
+
+default_config_contents() {
+    cat <<EOF
 # ~/.clam/config
 
 # OpenAI API Key
@@ -849,41 +2694,162 @@ anthropic_api_key: $ANTHROPIC_API_KEY
 # Groq API Key
 groq_api_key: $GROQ_API_KEY
 
-# Custom API Key for Ollama
+# Mistral API Key
+mistral_api_key: $MISTRAL_API_KEY
+
+# Custom API Key for Ollama or an OpenAI-compatible gateway (provider: custom)
 custom_api_key: $LLM_API_KEY
 
 # Model configuration
 provider: openai
 model: gpt-4o
 temperature: 0.0
+show_explanations: false
+
+# Privacy mode: send nothing to any model (toggle with: clam privacy)
+privacy_mode: false
+
+# Request timeouts in seconds (minimum 1)
+completion_timeout: 30
+fep_timeout: 60
+
+# Longest command output sent to fep; the middle of longer output is cut
+fep_max_output_chars: 4000
+
+# Most recent command output kept on disk for fep, in bytes
+max_capture_bytes: 1048576
+
+# Response token limits per request type (1 to 8192)
+completion_max_tokens: 1024
+harm_max_tokens: 512
+fep_max_tokens: 1024
+
+# Proxy for API requests (http://, https://, socks5:// or socks5h://)
+proxy_url:
+# Use HTTPS_PROXY/ALL_PROXY from the environment when proxy_url is empty
+respect_system_proxy: true
 endpoint: https://api.openai.com/v1/chat/completions
 api_prompt_cost: 0.000005
 api_completion_cost: 0.000015
 
+# How long Ollama keeps the model loaded after a request (30s, 5m, 1h; -1 = forever)
+ollama_keep_alive: 5m
+
+# Stop sending to a provider after this many failed requests in a row, for
+# circuit_breaker_cooldown seconds (0 = never stop)
+circuit_breaker_threshold: 3
+circuit_breaker_cooldown: 30
+
 # Max history and recent files
 max_history_commands: 20
+# Pick the history entries most similar to the input instead of the latest
+rank_history_by_relevance: false
 max_recent_files: 20
+include_dir_listing: true
+include_recent_output: false
+recent_output_lines: 20
+max_dir_entries: 50
+max_env_vars: 30
+
+# Shortest input (in characters) that asks the model for suggestions
+min_input_len: 2
+
+# Completion prompt template: default, or a file in ~/.clam/templates/<name>.txt
+prompt_template: default
+
+# Extra redaction rules (comma-separated extended regexes)
+redaction_patterns:
+sensitive_env_patterns:
 
 # Cache settings
 cache_dir: $HOME/.clam/cache
 cache_size: 10
+# Seconds before an unused suggestion expires (0 = never; pinned never expire).
+# One-off inputs get half of this; each reuse doubles it, up to 8x.
+cache_ttl: 0
 
 # Logging settings
 log_file: $HOME/.clam/clam.log
+# Record each model request and its redacted response in llm_debug.log next
+# to this file, for debugging poor suggestions
+debug_log_llm: false
 
 # Harm detection settings
 harm_detection_enabled: true
 harm_cache_dir: $HOME/.clam/harm_cache
 harm_cache_size: 100
+# Seconds before a cached harm verdict is re-checked (0 = never)
+harm_cache_ttl: 0
 harm_timeout: 3
+
+# Explanation cache settings
+explain_cache_size: 100
+# Seconds before a cached explanation is fetched again (0 = never)
+explain_cache_ttl: 0
+
+# Refuse (rather than just warn about) commands matching a local safeguard
+# pattern at or above block_severity
+block_dangerous: false
+block_severity: critical
 EOF
-    fi
+}
+
+config_file_to_json() {
+    local config_file="$1"
+    local key value
+
+    [[ -f "$config_file" ]] || { echo '{}'; return; }
+    while IFS=':' read -r key value; do
+        [[ -z "$key" || "$key" =~ ^# ]] && continue
+        key=$(echo "$key" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+        value=$(echo "$value" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+        printf '%s\t%s\n' "$key" "$value"
+    done < "$config_file" | jq -Rn '[inputs | split("\t") | {(.[0]): (.[1:] | join("\t"))}] | add // {}'
+}
+
+diff_config() {
+    jq -nc --argjson old "$(config_file_to_json "$1")" --argjson new "$(config_file_to_json "$2")" '
+        [($old + $new | keys[]) as $key
+         | select($old[$key] != $new[$key])
+         | {key: $key, old: $old[$key], new: $new[$key]}]'
+}
+
+get_config_schema() {
+    local key value
+    while IFS=':' read -r key value; do
+        [[ -z "$key" || "$key" =~ ^# ]] && continue
+        key=$(echo "$key" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+        value=$(echo "$value" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+        [[ "$key" == *_api_key ]] && value=""
+        printf '%s\t%s\t%s\n' "$key" "${CLAM_CONFIG_RULES[$key]}" "$value"
+    done <<< "$(default_config_contents)" | jq -Rn '
+        def property($rule; $default):
+            ($rule | split(":")) as $parts
+            | (if $parts[0] == "enum" then {type: "string", enum: ($rule | ltrimstr("enum:") | split(","))}
+               elif $parts[0] == "boolean" then {type: "boolean"}
+               elif $parts[0] == "integer" or $parts[0] == "number" then
+                   {type: $parts[0], minimum: ($parts[1] | tonumber), maximum: ($parts[2] | tonumber)}
+               elif $parts[0] == "string" then {type: "string", minLength: 1}
+               elif $parts[0] == "regex_list" then {type: "string", description: "Comma-separated extended regular expressions"}
+               elif $parts[0] == "duration" then {type: ["string", "integer"], pattern: "^-?[0-9]+(ms|s|m|h)?$"}
+               elif $parts[0] == "proxy" then {type: "string", pattern: "^((http|https|socks5|socks5h)://[^/\\s]+(/.*)?)?$"}
+               else {type: "string"} end) as $schema
+            | if $default == "" then $schema
+              elif $schema.type == "boolean" then $schema + {default: ($default == "true")}
+              elif $schema.type == "integer" or $schema.type == "number" then $schema + {default: ($default | tonumber)}
+              else $schema + {default: $default} end;
+        {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            title: "clam config",
+            type: "object",
+            properties: (reduce (inputs | split("\t")) as [$key, $rule, $default] ({}; . + {($key): property($rule; $default)}))
+        }'
 }
 
 set_config_value() {
     local key="$1"
     local value="$2"
-    local config_file="$HOME/.clam/config"
+    local config_file=$(get_config_path)
 
     key=$(echo "$key" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
     if [ -z "$key" ]; then
@@ -894,8 +2860,149 @@ set_config_value() {
         echo_error "Configuration file not found: $config_file. Run clam install."
         return
     fi
-    sed -i "s|^\($key:\).*|\1 $value|" "$config_file"
+
+    local validation_error
+    if ! validation_error=$(validate_config_value "$key" "$value"); then
+        echo_error "$validation_error"
+        return 1
+    fi
+    if [[ "$key" == "endpoint" && -n "$value" ]]; then
+        # CLAM_PROVIDER is stale in a fresh 'clam' process or in a shell
+        # enabled before the provider was changed, so read the saved one.
+        local provider=$(config_file_to_json "$config_file" | jq -r '.provider // empty')
+        provider="${_CLAM_SESSION_CONFIG[provider]:-$provider}"
+        if ! validation_error=$(validate_endpoint "$provider" "$value"); then
+            echo_error "$validation_error"
+            return 1
+        fi
+        value=$(normalize_endpoint "$value" "${provider:-openai}")
+    fi
+
+    local saved=true
+    if write_config_value "$key" "$value" "$config_file" 2>/dev/null; then
+        unset '_CLAM_SESSION_CONFIG[$key]'
+    else
+        note_storage_failure "$config_file"
+        _CLAM_SESSION_CONFIG[$key]="$value"
+        saved=false
+    fi
+
+    case "$key" in
+        *_api_key) command rm -f "$(get_circuit_file "${key%_api_key}")" ;;
+        endpoint|model) command rm -f "$(get_circuit_file)" ;;
+    esac
+
+    local new_provider=""
+    [[ "$key" == "provider" ]] && new_provider="$value"
     load_config
+    [[ -n "$new_provider" ]] && correct_model_for_provider "$new_provider"
+    # 2 tells callers the value only lives in this shell.
+    [[ "$saved" == true ]] || return 2
+    return 0
+}
+
+get_app_paths() {
+    local name path entry
+    local -a entries=(
+        "config=$(get_config_path)"
+        "usage_log=${CLAM_LOG_FILE:-$HOME/.clam/clam.log}"
+        "cache=${CLAM_CACHE_DIR:-$HOME/.clam/cache}"
+        "harm_cache=${CLAM_HARM_CACHE_DIR:-$HOME/.clam/harm_cache}"
+        "explain_cache=${CLAM_EXPLAIN_CACHE_DIR:-$HOME/.clam/explain_cache}"
+        "outcome_log=${CLAM_OUTCOME_LOG_FILE:-$HOME/.clam/outcomes.log}"
+        "suggestion_log=${CLAM_SUGGESTION_LOG_FILE:-$HOME/.clam/suggestions.log}"
+        "latency_log=${CLAM_LATENCY_LOG_FILE:-$HOME/.clam/latency.log}"
+        "harm_timeout_log=${CLAM_HARM_TIMEOUT_LOG_FILE:-$HOME/.clam/harm_timeouts.log}"
+        "last_output=${CLAM_LAST_OUTPUT_FILE:-$HOME/.clam/last_output.txt}"
+        "circuit=${CLAM_CIRCUIT_DIR:-$HOME/.clam/circuit}"
+        "llm_debug_log=$(dirname "$(get_config_path)")/llm_debug.log"
+        "templates=$HOME/.clam/templates"
+    )
+    for entry in "${entries[@]}"; do
+        name="${entry%%=*}"
+        path="${entry#*=}"
+        local exists=false
+        [[ -e "$path" ]] && exists=true
+        jq -nc --arg name "$name" --arg path "$path" --argjson exists "$exists" \
+            '{($name): {path: $path, exists: $exists}}'
+    done | jq -sc 'add'
+}
+
+default_model_for() {
+    case "${1,,}" in
+        openai) echo "gpt-4o" ;;
+        anthropic) echo "claude-3-5-sonnet-20241022" ;;
+        groq) echo "llama-3.3-70b-versatile" ;;
+        mistral) echo "mistral-small-latest" ;;
+        ollama) echo "codellama" ;;
+        mock) echo "mock" ;;
+        *) return 1 ;;
+    esac
+}
+
+get_providers() {
+    local provider providers
+    IFS=',' read -ra providers <<< "${CLAM_CONFIG_RULES[provider]#enum:}"
+    for provider in "${providers[@]}"; do
+        local requires_api_key=false
+        CLAM_PROVIDER="$provider" provider_requires_api_key && requires_api_key=true
+        local default_model=$(default_model_for "$provider")
+        local default_entry=""
+        [[ -n "$default_model" ]] && default_entry=$(find_models_by_name "$default_model" \
+            | jq -c --arg provider "$provider" 'select(.provider == $provider)')
+
+        jq -nc --arg name "$provider" --argjson requires_api_key "$requires_api_key" \
+            --arg default_model "$default_model" --argjson entry "${default_entry:-null}" '{
+                name: $name,
+                requires_api_key: $requires_api_key,
+                local: ($name == "ollama" or $name == "mock"),
+                default_model: (if $default_model == "" then null else $default_model end),
+                default_endpoint: ($entry.endpoint // null)
+            }'
+    done | jq -sc '.'
+}
+
+correct_model_for_provider() {
+    local provider="${1,,}"
+    local model="${CLAM_MODEL:-gpt-4o}"
+    local default_model
+    default_model=$(default_model_for "$provider") || return 0
+
+    local offered_by=$(find_models_by_name "$model")
+    [[ -z "$offered_by" ]] && return 0
+    jq -e --arg provider "$provider" 'select(.provider == $provider)' <<< "$offered_by" > /dev/null && return 0
+
+    local default_entry=$(find_models_by_name "$default_model" | jq -c --arg provider "$provider" 'select(.provider == $provider)')
+    echo "Model '$model' is not offered by $provider; switched to $default_model"
+    set_config_value "model" "$default_model"
+    if [[ -n "$default_entry" ]] && [[ "$(get_endpoint)" == "$(head -n 1 <<< "$offered_by" | jq -r '.endpoint')" ]]; then
+        set_config_value "endpoint" "$(jq -r '.endpoint' <<< "$default_entry")"
+    fi
+}
+
+write_config_value() {
+    local key="$1"
+    local value="$2"
+    local config_file="$3"
+
+    if grep -q "^$key:" "$config_file"; then
+        local escaped_value=$(printf '%s' "$value" | sed 's/[\\&|]/\\&/g')
+        sed -i "s|^\($key:\).*|\1 $escaped_value|" "$config_file"
+    else
+        echo "$key: $value" >> "$config_file"
+    fi
+}
+
+note_storage_failure() {
+    local path="$1"
+    if [[ "$CLAM_STORAGE_STATUS" != "read-only" ]]; then
+        export CLAM_STORAGE_STATUS="read-only"
+        if is_being_sourced; then
+            echo_error "Warning: cannot write $path - changes are kept for this session only"
+        else
+            echo_error "Warning: cannot write $path - changes are not saved"
+        fi
+    fi
 }
 
 # === UI Components ===
@@ -958,6 +3065,22 @@ read_key() {
     fi
 }
 
+parse_suggestion_line() {
+    local line="$1"
+    local max_length=${CLAM_MAX_EXPLANATION_LENGTH:-80}
+    local command="$line"
+    local explanation=""
+
+    if [[ "$line" == *"|||"* ]]; then
+        command="${line%%|||*}"
+        explanation="${line#*|||}"
+    fi
+    if [[ ${#explanation} -gt $max_length ]]; then
+        explanation="${explanation:0:$((max_length - 3))}..."
+    fi
+    printf '%s\n%s\n' "$command" "$explanation"
+}
+
 show_interactive_menu() {
     local completions_str="$1"
     local show_explanations="${2:-false}"
@@ -966,13 +3089,10 @@ show_interactive_menu() {
 
     while IFS= read -r line; do
         if [[ -n "$line" ]]; then
-            if [[ "$line" == *"|||"* ]]; then
-                options+=("${line%%|||*}")
-                explanations+=("${line#*|||}")
-            else
-                options+=("$line")
-                explanations+=("")
-            fi
+            local option explanation
+            { IFS= read -r option; IFS= read -r explanation; } < <(parse_suggestion_line "$line")
+            options+=("$option")
+            explanations+=("$explanation")
         fi
     done <<< "$completions_str"
 
@@ -1044,37 +3164,26 @@ show_interactive_menu() {
                 ;;
             q|$'\x1b')
                 clear_menu
+                record_suggestion_feedback rejected
                 echo -e "\e[90mCanceled.\e[0m"
                 return 1
                 ;;
             "")
                 clear_menu
                 local selected_cmd="${options[selected]}"
+                record_suggestion_feedback accepted
 
-                local config_file="$HOME/.clam/config"
+                local config_file=$(get_config_path)
                 local safeguards_enabled="true"
                 if [ -f "$config_file" ]; then
                     safeguards_enabled=$(grep "^harm_detection_enabled:" "$config_file" | awk '{print $2}' | tr -d ' ')
                     [[ -z "$safeguards_enabled" ]] && safeguards_enabled="true"
                 fi
 
+                confirm_dangerous_command "$selected_cmd" || return 1
+
                 if [[ "$safeguards_enabled" == "true" ]]; then
-                    local harm_result=$(detect_command_harm "$selected_cmd")
-                    local is_harmful=$(echo "$harm_result" | jq -r '.is_harmful')
-                    local explanation=$(echo "$harm_result" | jq -r '.explanation')
-
-                    if [[ "$is_harmful" == "true" ]]; then
-                        echo -e "\e[1;33m⚠ WARNING: Potentially harmful command detected!\e[0m"
-                        echo -e "\e[1;32m▶ Command:\e[0m $selected_cmd"
-                        echo -e "\e[1;90m▶ Reason:\e[0m $explanation"
-                        echo
-                        read -p "Are you sure you want to continue? (y/N): " -n 1 -r
-                        echo
-                        if [[ ! $REPLY =~ ^[Yy]$ ]]; then
-                            echo -e "\e[90mCommand cancelled.\e[0m"
-                            return 1
-                        fi
-                    fi
+                    confirm_risky_command "$selected_cmd" "$(assess_command "$selected_cmd")" || return 1
                 fi
 
                 echo -e "\e[1;32m▶ Executing:\e[0m $selected_cmd"
@@ -1133,12 +3242,358 @@ show_model_selector() {
 
 # === Safeguard System ===
 
+# "severity|pattern|description". A pattern starting with "re:" is an
+# extended regex; anything else is a literal substring.
+CLAM_SAFEGUARD_PATTERNS=(
+    "critical|rm -rf ~|Recursively deletes the home directory"
+    "critical|rm -rf *|Recursively deletes everything in the current directory"
+    "critical|re:(^|[;&|(][[:space:]]*)([^[:space:];&|]*/)?mkfs(\.[a-z0-9]+)?([[:space:]]|$)|Formats a filesystem, destroying its contents"
+    "critical|> /dev/sd|Overwrites a disk device"
+    "critical|:(){|Defines a fork bomb"
+    "critical|> /etc/passwd|Overwrites the user account database"
+    "critical|re:rm[[:space:]]+(-[a-zA-Z]+[[:space:]]+)*-[a-zA-Z]*[rR][a-zA-Z]*[[:space:]]+(-[a-zA-Z]+[[:space:]]+)*/([[:space:]*]|$)|Recursively deletes from the filesystem root"
+    "critical|re:dd[[:space:]].*of=/dev/(sd|hd|vd|xvd|nvme|disk|mmcblk)|Writes raw data over a disk device"
+    "high|chmod -R 777|Makes a directory tree world-writable"
+    "high|re:\|[[:space:]]*(sudo[[:space:]]+)?(sh|bash|zsh)([[:space:]]|$)|Pipes downloaded or generated text into a shell"
+    "high|re:(^|[;&|(][[:space:]]*)(systemctl[[:space:]]+)?([^[:space:];&|]*/)?shutdown([[:space:]]|$)|Shuts the machine down"
+    "high|re:(^|[;&|(][[:space:]]*)(systemctl[[:space:]]+)?([^[:space:];&|]*/)?reboot([[:space:]]|$)|Reboots the machine"
+    "high|re:(^|[;&|][[:space:]]*)rm[[:space:]][^;&|]*(;|&&|\|\|)[[:space:]]*rm[[:space:]]|Chains several deletes together"
+    "medium|rm -rf|Recursively force-deletes files"
+    "medium|rm -r |Recursively deletes files"
+    "medium|chown -R|Changes ownership of a directory tree"
+    "medium|git push --force|Overwrites remote history"
+    "medium|git reset --hard|Discards uncommitted changes"
+)
+
+declare -A CLAM_SAFEGUARD_SUGGESTIONS=(
+    ["Recursively deletes the home directory"]="Name the exact directory to delete, or use rm -ri to confirm each file"
+    ["Recursively deletes everything in the current directory"]="Run ls first, or use rm -ri to confirm each file"
+    ["Formats a filesystem, destroying its contents"]="Check the target device with lsblk before formatting"
+    ["Overwrites a disk device"]="Check the target device with lsblk first"
+    ["Overwrites the user account database"]="Use vipw to edit it, or >> to append"
+    ["Recursively deletes from the filesystem root"]="Check the path; it resolves to /"
+    ["Writes raw data over a disk device"]="Confirm the of= device with lsblk and add status=progress"
+    ["Makes a directory tree world-writable"]="Grant only what is needed, e.g. chmod -R u+rwX,go+rX"
+    ["Pipes downloaded or generated text into a shell"]="Save the script and read it before running it"
+    ["Chains several deletes together"]="Run the deletes one at a time"
+    ["Recursively force-deletes files"]="Add -i to confirm each deletion"
+    ["Recursively deletes files"]="Add -i to confirm each deletion"
+    ["Changes ownership of a directory tree"]="Run it on a single path first to check the result"
+    ["Overwrites remote history"]="Use git push --force-with-lease so others' commits are not lost"
+    ["Discards uncommitted changes"]="Run git stash first to keep a copy of the changes"
+)
+
+# "weight|regex|name". sudo/doas is not listed: it already raises the
+# match's severity.
+CLAM_RISK_SIGNALS=(
+    "1|(^|[[:space:]])(-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)([[:space:]]|$)|recursive"
+    "1|(^|[[:space:]])(-[a-zA-Z]*f[a-zA-Z]*|--force)([[:space:]]|$)|forced"
+    "1|[*?]|wildcard"
+    "1|(^|[[:space:]])(/|~/?|[$]HOME/?)[*]?([[:space:]]|$)|root or home directory"
+    "1|(&&|\|\||;)|several commands"
+)
+
+severity_rank() {
+    case "$1" in
+        low) echo 1 ;;
+        medium) echo 2 ;;
+        high) echo 3 ;;
+        critical) echo 4 ;;
+        *) echo 0 ;;
+    esac
+}
+
+severity_name() {
+    case "$1" in
+        1) echo "low" ;;
+        2) echo "medium" ;;
+        3) echo "high" ;;
+        4) echo "critical" ;;
+    esac
+}
+
+strip_privilege_prefix() {
+    local command="$1"
+    local stripped="$command"
+    local prefix_regex='^[[:space:]]*(sudo|doas)([[:space:]]+(-[ugCDprtU][[:space:]]+[^-[:space:]][^[:space:]]*|-[^[:space:]]+))*[[:space:]]+'
+
+    while [[ "$stripped" =~ $prefix_regex ]]; do
+        stripped="${stripped:${#BASH_REMATCH[0]}}"
+    done
+    echo "$stripped"
+    [[ "$stripped" != "$command" ]]
+}
+
+check_command_safeguard() {
+    local command="$1"
+    local top=$(check_all_matches "$command" | jq -c '.[0] // {matched: false}')
+    if [[ $(echo "$top" | jq -r '.matched') != "true" ]]; then
+        echo "$top"
+        return 0
+    fi
+
+    local risk=$(score_command_risk "$command" "$(echo "$top" | jq -r '.severity')")
+    echo "$top" | jq -c --argjson risk "$risk" '
+        {low: 1, medium: 2, high: 3, critical: 4} as $rank
+        | . + {score: $risk.score, signals: $risk.signals}
+        | if $rank[$risk.severity] > $rank[.severity] then .severity = $risk.severity else . end'
+}
+
+score_command_risk() {
+    local command="$1"
+    local score=$(severity_rank "$2")
+    local signals=()
+    local entry weight rest regex
+
+    for entry in "${CLAM_RISK_SIGNALS[@]}"; do
+        weight="${entry%%|*}"
+        rest="${entry#*|}"
+        regex="${rest%|*}"
+        if [[ "$command" =~ $regex ]]; then
+            score=$((score + weight))
+            signals+=("${rest##*|}")
+        fi
+    done
+
+    local severity="none"
+    if (( score >= 7 )); then severity="critical"
+    elif (( score >= 5 )); then severity="high"
+    elif (( score >= 3 )); then severity="medium"
+    elif (( score >= 1 )); then severity="low"
+    fi
+
+    printf '%s\n' "${signals[@]}" | jq -Rsc --argjson score "$score" --arg severity "$severity" \
+        '{score: $score, severity: $severity, signals: (split("\n") | map(select(. != "")))}'
+}
+
+check_all_matches() {
+    safeguard_matches "$1" | jq -sc '
+        {low: 1, medium: 2, high: 3, critical: 4} as $rank
+        | sort_by(-($rank[.severity] // 0), -(.matched_text | length))'
+}
+
+safeguard_matches() {
+    local original="$1"
+    local command
+    local elevated=false
+    local entry severity rest pattern description matched_text
+    local LC_ALL=C
+
+    command=$(strip_privilege_prefix "$original") && elevated=true
+
+    for entry in "${CLAM_SAFEGUARD_PATTERNS[@]}"; do
+        severity="${entry%%|*}"
+        rest="${entry#*|}"
+        pattern="${rest%|*}"
+        description="${rest##*|}"
+        if [[ "$pattern" == re:* ]]; then
+            [[ "$command" =~ ${pattern#re:} ]] || continue
+            matched_text="${BASH_REMATCH[0]}"
+        elif [[ "$command" == *"$pattern"* ]]; then
+            matched_text="$pattern"
+        else
+            continue
+        fi
+
+        local suggestion="${CLAM_SAFEGUARD_SUGGESTIONS[$description]}"
+        local before_match="${command%%"$matched_text"*}"
+        local match_start=$(( ${#original} - ${#command} + ${#before_match} ))
+        local match_end=$(( match_start + ${#matched_text} ))
+
+        if [[ "$elevated" == "true" ]]; then
+            local rank=$(severity_rank "$severity")
+            (( rank < 4 )) && severity=$(severity_name $(( rank + 1 )))
+            description="$description with elevated privileges"
+        fi
+        jq -nc --arg severity "$severity" --arg pattern "$pattern" --arg description "$description" \
+            --arg matched_text "$matched_text" --argjson match_start "$match_start" --argjson match_end "$match_end" \
+            --arg suggestion "$suggestion" \
+            '{matched: true, severity: $severity, pattern: $pattern, description: $description,
+              matched_text: $matched_text, match_start: $match_start, match_end: $match_end,
+              suggestion: (if $suggestion == "" then null else $suggestion end)}'
+    done
+}
+
+check_paste() {
+    local text="$1"
+    local line line_number=0 executable_lines=0
+    local matches=()
+
+    while IFS= read -r line || [[ -n "$line" ]]; do
+        line_number=$((line_number + 1))
+        line=$(echo "$line" | sed 's/^[[:space:]]*//;s/[[:space:]]*$//')
+        [[ -z "$line" || "$line" == \#* ]] && continue
+        executable_lines=$((executable_lines + 1))
+
+        local safeguard=$(check_command_safeguard "$line")
+        if [[ $(echo "$safeguard" | jq -r '.matched') == "true" ]]; then
+            matches+=("$(echo "$safeguard" | jq -c --arg command "$line" --argjson line "$line_number" \
+                '{line: $line, command: $command, severity, description}')")
+        fi
+    done <<< "$text"
+
+    printf '%s\n' "${matches[@]}" | jq -sc --argjson executable_lines "$executable_lines" '
+        . as $dangerous
+        | {
+            warning: (($dangerous | length) > 0 or $executable_lines > 1),
+            executable_lines: $executable_lines,
+            dangerous: $dangerous,
+            reason: ([
+                (if $executable_lines > 1 then "Paste runs \($executable_lines) commands" else empty end),
+                ($dangerous[] | "line \(.line): \(.description) (\(.severity))")
+            ] | join("; "))
+          }'
+}
+
+split_script_lines() {
+    awk '
+        function strip(text,   out, i, c, quote) {
+            out = ""; quote = ""; continued = 0
+            for (i = 1; i <= length(text); i++) {
+                c = substr(text, i, 1)
+                if (quote == "" && c == "#" && (i == 1 || substr(text, i - 1, 1) ~ /[ \t;&|(]/)) break
+                if (c == "\\" && quote != "\047") {
+                    if (i == length(text)) { continued = 1; break }
+                    out = out c substr(text, i + 1, 1); i++; continue
+                }
+                if (quote == "" && (c == "\"" || c == "\047")) quote = c
+                else if (c == quote) quote = ""
+                out = out c
+            }
+            return out
+        }
+        function flush() {
+            gsub(/^[ \t]+|[ \t]+$/, "", pending)
+            if (pending != "") printf "%d\t%s\n", start, pending
+            pending = ""; start = 0
+        }
+        {
+            if (!start) start = NR
+            pending = pending strip($0)
+            if (!continued) flush()
+        }
+        END { flush() }'
+}
+
+lint_script() {
+    local content="$1"
+    local line_number command
+    local findings=()
+
+    while IFS=$'\t' read -r line_number command; do
+        local safeguard=$(check_command_safeguard "$command")
+        if [[ $(echo "$safeguard" | jq -r '.matched') == "true" ]]; then
+            findings+=("$(echo "$safeguard" | jq -c --arg command "$command" --argjson line "$line_number" \
+                '{line: $line, command: $command, severity, description}')")
+        fi
+    done < <(printf '%s\n' "$content" | split_script_lines)
+
+    printf '%s\n' "${findings[@]}" | jq -sc 'map(select(. != null))'
+}
+
+assess_command() {
+    local command="$1"
+    local use_llm="${2:-true}"
+    local pattern_result=$(check_command_safeguard "$command")
+    local harm_result='{"is_harmful":false}'
+
+    if [[ "$use_llm" == "true" ]]; then
+        harm_result=$(detect_command_harm "$command")
+        echo "$harm_result" | jq -e 'type == "object"' &>/dev/null || harm_result='{"is_harmful":false}'
+    fi
+
+    jq -nc --argjson pattern "$pattern_result" --argjson harm "$harm_result" '
+        def rank: {"low": 1, "medium": 2, "high": 3, "critical": 4}[.] // 0;
+        [
+            (if $pattern.matched then {source: "pattern", severity: $pattern.severity, reason: $pattern.description, suggestion: $pattern.suggestion} else empty end),
+            (if $harm.is_harmful == true then {source: "llm", severity: "high", reason: $harm.explanation} else empty end)
+        ] as $hits
+        | {
+            is_risky: ($hits | length > 0),
+            severity: (if ($hits | length) > 0 then ($hits | max_by(.severity | rank) | .severity) else "none" end),
+            sources: ($hits | map(.source)),
+            reason: ($hits | map(.reason) | join("; ")),
+            suggestion: ([$hits[].suggestion // empty] | first // null)
+          }'
+}
+
+confirm_risky_command() {
+    local command="$1"
+    local assessment="$2"
+
+    [[ $(echo "$assessment" | jq -r '.is_risky') != "true" ]] && return 0
+
+    echo -e "\e[1;33m⚠ WARNING: Potentially harmful command detected! ($(echo "$assessment" | jq -r '.severity') risk)\e[0m"
+    echo -e "\e[1;32m▶ Command:\e[0m $command"
+    echo -e "\e[1;90m▶ Reason:\e[0m $(echo "$assessment" | jq -r '.reason')"
+    local suggestion
+    suggestion=$(echo "$assessment" | jq -r '.suggestion // empty')
+    [[ -n "$suggestion" ]] && echo -e "\e[1;36m▶ Suggestion:\e[0m $suggestion"
+    echo
+    read -p "Are you sure you want to continue? (y/N): " -n 1 -r
+    echo
+    if [[ ! $REPLY =~ ^[Yy]$ ]]; then
+        echo -e "\e[90mCommand cancelled.\e[0m"
+        return 1
+    fi
+}
+
+guard_dangerous_command() {
+    local command="$1"
+    local override="$2"
+
+    local config_file=$(get_config_path)
+    local block_dangerous="${CLAM_BLOCK_DANGEROUS:-false}"
+    local block_severity="${CLAM_BLOCK_SEVERITY:-critical}"
+    if [ -f "$config_file" ]; then
+        local saved_value
+        saved_value=$(grep "^block_dangerous:" "$config_file" | awk '{print $2}' | tr -d ' ')
+        [[ -n "$saved_value" ]] && block_dangerous="$saved_value"
+        saved_value=$(grep "^block_severity:" "$config_file" | awk '{print $2}' | tr -d ' ')
+        [[ -n "$saved_value" ]] && block_severity="$saved_value"
+    fi
+    [[ "$block_dangerous" != "true" ]] && return 0
+
+    local result=$(check_command_safeguard "$command")
+    [[ $(echo "$result" | jq -r '.matched') != "true" ]] && return 0
+
+    local severity=$(echo "$result" | jq -r '.severity')
+    if (( $(severity_rank "$severity") < $(severity_rank "$block_severity") )); then
+        return 0
+    fi
+    [[ "$override" == "override" ]] && return 0
+
+    echo -e "\e[1;31m✖ BLOCKED: $severity-risk command\e[0m"
+    echo -e "\e[1;32m▶ Command:\e[0m $command"
+    echo -e "\e[1;90m▶ Reason:\e[0m $(echo "$result" | jq -r '.description')"
+    return 1
+}
+
+confirm_dangerous_command() {
+    local command="$1"
+
+    guard_dangerous_command "$command" && return 0
+
+    local token
+    read -r -p "Type 'override' to run it once anyway: " token
+    if ! guard_dangerous_command "$command" "$token" > /dev/null; then
+        echo -e "\e[90mCommand cancelled.\e[0m"
+        return 1
+    fi
+}
+
 check_command_safety() {
     local cmd_name="$1"
     shift
     local full_cmd="$cmd_name $*"
 
-    local config_file="$HOME/.clam/config"
+    if [[ -n "${_CLAM_IN_SAFEGUARD:-}" ]]; then
+        return 0
+    fi
+
+    confirm_dangerous_command "$full_cmd" || return 1
+
+    local config_file=$(get_config_path)
     local safeguards_enabled="true"
     if [ -f "$config_file" ]; then
         safeguards_enabled=$(grep "^harm_detection_enabled:" "$config_file" | awk '{print $2}' | tr -d ' ')
@@ -1149,10 +3604,6 @@ check_command_safety() {
         return 0
     fi
 
-    if [[ -n "${_CLAM_IN_SAFEGUARD:-}" ]]; then
-        return 0
-    fi
-
     export _CLAM_IN_SAFEGUARD=1
 
     if ! type -t detect_command_harm &>/dev/null; then
@@ -1160,27 +3611,30 @@ check_command_safety() {
         return 0
     fi
 
-    local harm_result=$(detect_command_harm "$full_cmd" 2>/dev/null)
-    local is_harmful=$(echo "$harm_result" | jq -r '.is_harmful')
-    local explanation=$(echo "$harm_result" | jq -r '.explanation')
+    local assessment=$(assess_command "$full_cmd" 2>/dev/null)
 
     unset _CLAM_IN_SAFEGUARD
 
-    if [[ "$is_harmful" == "true" ]]; then
-        echo -e "\e[1;33m⚠ WARNING: Potentially harmful command detected!\e[0m"
-        echo -e "\e[1;32m▶ Command:\e[0m $full_cmd"
-        echo -e "\e[1;90m▶ Reason:\e[0m $explanation"
-        echo
-        read -p "Are you sure you want to continue? (y/N): " -n 1 -r
-        echo
-        if [[ ! $REPLY =~ ^[Yy]$ ]]; then
-            echo -e "\e[90mCommand cancelled.\e[0m"
-            return 1
-        fi
-    fi
-    return 0
+    confirm_risky_command "$full_cmd" "$assessment"
 }
 
+CLAM_SAFEGUARD_HELPERS=(
+    check_command_safety check_command_safeguard check_all_matches safeguard_matches
+    score_command_risk severity_rank severity_name strip_privilege_prefix
+    guard_dangerous_command confirm_dangerous_command assess_command confirm_risky_command
+    detect_command_harm quick_safe_check record_harm_timeout cache_key cache_entry_expired
+    evict_harm_cache evict_oldest_files load_config get_config_path validate_config_value
+    is_valid_regex is_valid_proxy_url split_patterns apply_redaction_patterns sanitize_text
+    truncate_middle is_being_sourced is_privacy_mode skip_for_privacy note_storage_failure
+    echo_error build_base_payload build_harm_detection_payload get_harm_system_prompt
+    get_os_name anthropic_payload with_max_tokens get_max_tokens is_reasoning_model
+    add_json_reminder send_chat call_api run_with_timeout resolve_timeout get_endpoint
+    normalize_endpoint keep_alive_seconds ollama_request_timeout mock_chat_response
+    get_circuit_file get_circuit_state record_circuit_result log_llm_debug extract_usage
+    classify_api_error api_error_exit_code describe_api_failure extract_api_error
+    extract_harm_reply extract_message_content extract_json parse_harm_response
+)
+
 enable_safeguards() {
     local risky_commands=("rm" "dd" "mkfs" "shutdown" "reboot" "chmod" "chown" "curl" "wget")
 
@@ -1199,11 +3653,14 @@ enable_safeguards() {
         fi
     done
 
-    export -f check_command_safety
-    export -f detect_command_harm
-    export -f load_config
-    export -f build_harm_detection_payload
-    export -f echo_error
+    # Child shells get the wrappers but not the pattern tables, which are
+    # arrays and cannot be exported. There every command goes to the model.
+    local helper
+    for helper in "${CLAM_SAFEGUARD_HELPERS[@]}"; do
+        export -f "$helper"
+    done
+    export CLAM_EXIT_CONFIG CLAM_EXIT_NETWORK CLAM_EXIT_AUTH CLAM_EXIT_RATE_LIMIT \
+        CLAM_EXIT_PROVIDER CLAM_EXIT_TIMEOUT CLAM_EXIT_CANCELLED
 
     for cmd in "${risky_commands[@]}"; do
         [[ $(type -t "$cmd") == "function" ]] && export -f "$cmd"
@@ -1221,6 +3678,10 @@ disable_safeguards() {
         fi
     done
 
+    local helper
+    for helper in "${CLAM_SAFEGUARD_HELPERS[@]}"; do
+        [[ $(type -t "$helper") == "function" ]] && export -fn "$helper"
+    done
     [[ $(type -t check_command_safety) == "function" ]] && unset -f check_command_safety
 }
 
@@ -1234,7 +3695,7 @@ is_enabled() {
 show_help() {
     echo_green "Clam.sh - LLM Powered Bash Completion"
     echo "Usage: clam [options] command"
-    echo "       clam [options] install|remove|config|model|enable|disable|safeguard|clear|usage|system|command|fep|--help"
+    echo "       clam [options] install|remove|config|model|enable|disable|safeguard|clear|usage|system|command|fep|explain|generate|recap|next|paste|lint|history|providers|circuit|privacy|paths|cancel|output|pin|unpin|test|--help"
     echo
     echo "Clam.sh enhances bash completion with LLM capabilities."
     echo
@@ -1242,20 +3703,40 @@ show_help() {
     echo "  - Press Tab twice for suggestions (standard completion)"
     echo "  - Press Ctrl+Space for interactive menu (navigate with ↑/↓, Enter to execute)"
     echo "  - Add '--explain' to your command to show explanations in the interactive menu"
+    echo "    (or always show them with: clam config set show_explanations true)"
     echo "  - AI-powered safeguards detect harmful commands and require confirmation"
     echo "  - Harm assessments are cached for instant feedback on repeated commands"
     echo
     echo "Commands:"
     echo "  demo                Show feature overview and usage examples"
     echo "  command             Run clam (simulate double Tab)"
-    echo "  command --dry-run   Show prompt without executing"
+    echo "  command --dry-run   Show the system and user prompt without sending them"
     echo "  fep [context]       Fix error please - analyze last failed command and suggest fix"
-    echo "  model               Change language model"
+    echo "  explain <command>   Describe what a command does and its side effects"
+    echo "  generate <request>  Write a command from a plain-English description"
+    echo "  recap               Summarize this shell session and suggest next steps"
+    echo "  next                Suggest what to run next from the last command's output"
+    echo "  paste [text]        Check text (or stdin) for dangerous or multiple commands before pasting"
+    echo "  lint [file...]      Check every line of shell scripts (or stdin) against the safeguard patterns"
+    echo "  history [query]     Fuzzy-search command history (e.g. 'gco' finds 'git checkout')"
+    echo "  output [lines]      Show the last captured command output (default 20 lines)"
+    echo "  pin <input>         Keep cached suggestions for an input forever"
+    echo "  unpin <input>       Let a pinned input be evicted again"
+    echo "  model [model]       Change language model (provider and endpoint follow the model)"
+    echo "  providers           List supported providers, their default models and endpoints"
+    echo "  privacy [on|off]    Toggle privacy mode: no requests to any model"
+    echo "  paths               Show where clam keeps its config, logs and caches"
+    echo "  circuit [reset]     Show whether the provider is paused after repeated failures, or un-pause it"
+    echo "  cancel [kind]       Stop an in-flight suggestion or fep request (default both)"
+    echo "  test                Check that the configured provider, key and model respond"
     echo "  usage               Display usage stats"
     echo "  system              Display system information"
     echo "  config              Show or set configuration values"
     echo "    config set <key> <value>  Set a config value"
-    echo "    config reset             Reset config to defaults"
+    echo "    config reset             Reset config to defaults, keeping API keys"
+    echo "    config reset --clear-keys  Reset config and clear API keys"
+    echo "    config schema            Print a JSON Schema for the config file"
+    echo "    config diff [file]       Show what a config file would change (default: changes from defaults)"
     echo "  install             Install clam to .bashrc"
     echo "  remove              Remove installation from .bashrc"
     echo "  enable              Enable clam"
@@ -1264,12 +3745,24 @@ show_help() {
     echo "    enable            Enable safeguards"
     echo "    disable           Disable safeguards"
     echo "    status            Show safeguard status"
-    echo "  clear               Clear cache and log files"
+    echo "  clear [target]      Clear all caches and the log, or just completion|harm|explain"
     echo "  --help              Show this help message"
 }
 
+mask_api_key() {
+    local key="$1"
+
+    if (( ${#key} <= 4 )); then
+        echo "****"
+    elif (( ${#key} < 12 )); then
+        echo "...${key: -4}"
+    else
+        echo "${key:0:3}...${key: -4}"
+    fi
+}
+
 cmd_show_config() {
-    local config_file="$HOME/.clam/config"
+    local config_file=$(get_config_path)
     echo_green "Clam.sh - Configuration and Settings - Version $CLAM_VERSION"
 
     if ! is_being_sourced; then
@@ -1290,6 +3783,10 @@ cmd_show_config() {
         return
     fi
 
+    show_config_values
+}
+
+show_config_values() {
     load_config
     local term_width=$(tput cols)
     local small_table=0
@@ -1297,7 +3794,7 @@ cmd_show_config() {
     [[ $term_width -lt 40 ]] && term_width=70 && small_table=1
 
     for config_var in $(compgen -v | grep CLAM_); do
-        if [[ $config_var == "CLAM_INPUT" || $config_var == "CLAM_PROMPT" || $config_var == "CLAM_RESPONSE" ]]; then
+        if [[ $config_var == _* || $config_var == "CLAM_INPUT" || $config_var == "CLAM_PROMPT" || $config_var == "CLAM_RESPONSE" ]]; then
             continue
         fi
         local config_value="${!config_var}"
@@ -1316,7 +3813,7 @@ cmd_show_config() {
     echo -e "  ===================================================================="
 
     for config_var in $(compgen -v | grep CLAM_); do
-        if [[ $config_var == "CLAM_INPUT" || $config_var == "CLAM_PROMPT" || $config_var == "CLAM_RESPONSE" ]]; then
+        if [[ $config_var == _* || $config_var == "CLAM_INPUT" || $config_var == "CLAM_PROMPT" || $config_var == "CLAM_RESPONSE" ]]; then
             continue
         fi
         if [[ ${config_var: -8} != "_API_KEY" ]]; then
@@ -1328,8 +3825,7 @@ cmd_show_config() {
             config_value="UNSET"
             echo -en "\e[31m"
         else
-            local key_suffix=${!config_var:4}
-            config_value="${!config_var:0:4}...${key_suffix: -4}"
+            config_value=$(mask_api_key "${!config_var}")
             echo -en "\e[32m"
         fi
         if [[ $small_table -eq 1 ]]; then
@@ -1341,6 +3837,37 @@ cmd_show_config() {
     done
 }
 
+reset_config() {
+    local clear_keys="${1:-false}"
+    local config_file=$(get_config_path)
+    local saved_keys=""
+
+    if [[ "$clear_keys" != "true" && -f "$config_file" ]]; then
+        saved_keys=$(grep -E '^[a-z]+_api_key:[[:space:]]*[^[:space:]]' "$config_file")
+    fi
+
+    # Built beside the old file and moved over it, so a failed write leaves
+    # the previous config in place.
+    mkdir -p "$(dirname "$config_file")"
+    local tmp_file line key saved_line
+    tmp_file=$(mktemp "$config_file.XXXXXX" 2>/dev/null) || { note_storage_failure "$config_file"; return 1; }
+    while IFS= read -r line; do
+        key="${line%%:*}"
+        if [[ "$key" =~ ^[a-z]+_api_key$ ]]; then
+            if [[ "$clear_keys" == "true" ]]; then
+                line="$key:"
+            elif saved_line=$(grep -m 1 "^$key:" <<< "$saved_keys"); then
+                line="$saved_line"
+            fi
+        fi
+        printf '%s\n' "$line"
+    done < <(default_config_contents) > "$tmp_file" && mv -f "$tmp_file" "$config_file" && return 0
+
+    command rm -f "$tmp_file"
+    note_storage_failure "$config_file"
+    return 1
+}
+
 cmd_config() {
     local subcommand="${*:2}"
 
@@ -1354,18 +3881,67 @@ cmd_config() {
         local value="$4"
         echo "Setting configuration key '$key' to '$value'"
         set_config_value "$key" "$value"
+        local status=$?
+        if [[ $status -eq 2 ]]; then
+            echo_error "Configuration not saved: '$key' is unchanged."
+            return 1
+        fi
+        [[ $status -eq 0 ]] || return 1
         echo_green "Configuration updated. Run 'clam config' to view changes."
         return
     fi
 
-    if [[ "$subcommand" == "reset" ]]; then
-        echo "Resetting configuration to default values."
-        rm "$HOME/.clam/config" || true
-        create_default_config
+    if [[ "$2" == "schema" ]]; then
+        get_config_schema
+        return
+    fi
+
+    if [[ "$2" == "diff" ]]; then
+        cmd_config_diff "$3"
+        return
+    fi
+
+    if [[ "$2" == "reset" ]]; then
+        if [[ "$3" == "--clear-keys" ]]; then
+            echo "Resetting configuration to default values and clearing API keys."
+            reset_config true || return 1
+        else
+            echo "Resetting configuration to default values (API keys kept)."
+            reset_config || return 1
+        fi
+        show_config_values
         return
     fi
 
-    echo_error "SyntaxError: expected 'clam config set <key> <value>' or 'clam config reset'"
+    echo_error "SyntaxError: expected 'clam config set <key> <value>', 'clam config schema', 'clam config diff [file]' or 'clam config reset [--clear-keys]'"
+}
+
+cmd_config_diff() {
+    local other_file="$1"
+    local config_file=$(get_config_path)
+    local changes
+
+    if [[ -z "$other_file" ]]; then
+        local defaults_file=$(mktemp)
+        default_config_contents > "$defaults_file"
+        changes=$(diff_config "$defaults_file" "$config_file")
+        command rm -f "$defaults_file"
+    elif [[ ! -f "$other_file" ]]; then
+        echo_error "Config file not found: $other_file"
+        return 1
+    else
+        changes=$(diff_config "$config_file" "$other_file")
+    fi
+
+    if [[ "$changes" == "[]" ]]; then
+        echo "No differences."
+        return 0
+    fi
+    echo "$changes" | jq -r '.[]
+        | if (.key | endswith("_api_key")) then .old = (if (.old // "") == "" then .old else "********" end)
+                                             | .new = (if (.new // "") == "" then .new else "********" end)
+          else . end
+        | "  \(.key): \(.old // "(unset)") → \(.new // "(unset)")"'
 }
 
 cmd_install() {
@@ -1410,16 +3986,16 @@ cmd_install() {
 }
 
 cmd_remove() {
-    local config_file="$HOME/.clam/config"
+    local config_file=$(get_config_path)
     local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
     local log_file=${CLAM_LOG_FILE:-"$HOME/.clam/clam.log"}
     local bashrc_file="$HOME/.bashrc"
 
     echo_green "Removing Clam.sh installation..."
 
-    [ -f "$config_file" ] && { rm "$config_file"; echo "Removed: $config_file"; }
-    [ -d "$cache_dir" ] && { rm -rf "$cache_dir"; echo "Removed: $cache_dir"; }
-    [ -f "$log_file" ] && { rm "$log_file"; echo "Removed: $log_file"; }
+    [ -f "$config_file" ] && { command rm "$config_file"; echo "Removed: $config_file"; }
+    [ -d "$cache_dir" ] && { command rm -rf "$cache_dir"; echo "Removed: $cache_dir"; }
+    [ -f "$log_file" ] && { command rm "$log_file"; echo "Removed: $log_file"; }
 
     if [ -d "$HOME/.clam" ]; then
         if [ -z "$(ls -A "$HOME/.clam")" ]; then
@@ -1442,12 +4018,12 @@ cmd_remove() {
     if [ -n "$clam_script" ]; then
         echo "Clam script is at: $clam_script"
         if [ "$1" == "-y" ]; then
-            rm "$clam_script"
+            command rm "$clam_script"
             echo "Removed: $clam_script"
         else
             read -r -p "Remove the clam script? (y/n): " confirm
             if [[ $confirm == "y" ]]; then
-                rm "$clam_script"
+                command rm "$clam_script"
                 echo "Removed: $clam_script"
             fi
         fi
@@ -1483,6 +4059,13 @@ disable
 status"
             return
             ;;
+        clear)
+            readarray -t COMPREPLY <<< "all
+completion
+harm
+explain"
+            return
+            ;;
     esac
 
     if [[ -z "$current_word" ]]; then
@@ -1498,7 +4081,23 @@ usage
 system
 command
 fep
+explain
+generate
+recap
+next
+paste
+lint
+history
+output
+pin
+unpin
 model
+providers
+circuit
+privacy
+paths
+cancel
+test
 --help"
     fi
 }
@@ -1509,6 +4108,7 @@ cmd_enable() {
     complete -D -E -F clam_completion -o nospace
     bind -x '"\C-@": interactive_clam_widget'
 
+    _CLAM_LAST_HISTORY_NUMBER=$(history 1 | awk '{print $1}')
     if [[ "$PROMPT_COMMAND" != *"capture_command_result"* ]]; then
         if [[ -n "$PROMPT_COMMAND" ]]; then
             PROMPT_COMMAND="capture_command_result; $PROMPT_COMMAND"
@@ -1520,6 +4120,15 @@ cmd_enable() {
 
     fep() { clam fep "$@"; }
     export -f fep
+    clam() {
+        if [[ "$1" == "recap" ]]; then
+            local session=""
+            (( ${#_CLAM_SESSION_COMMANDS[@]} )) && printf -v session '%s\n' "${_CLAM_SESSION_COMMANDS[@]}"
+            CLAM_SESSION_COMMANDS="${session%$'\n'}" command clam "$@"
+        else
+            command clam "$@"
+        fi
+    }
 
     enable_safeguards
 
@@ -1544,7 +4153,7 @@ cmd_disable() {
         export PROMPT_COMMAND
     fi
 
-    unset -f fep 2>/dev/null
+    unset -f fep clam 2>/dev/null
     disable_safeguards
 }
 
@@ -1554,24 +4163,75 @@ cmd_command() {
     for idx in "${!args[@]}"; do
         if [ "${args[idx]}" == "--dry-run" ]; then
             args[idx]=""
-            build_prompt "${args[@]}"
+            local preview=$(preview_prompt "${args[@]}")
+            local estimate=$(estimate_request_cost "$(echo "$preview" | jq -r '.system + .user')")
+            echo "# SYSTEM PROMPT"
+            echo "$preview" | jq -r '.system'
+            echo "# USER MESSAGE"
+            echo "$preview" | jq -r '.user'
+            echo
+            echo -e "\e[90mEstimated prompt size: $(echo "$estimate" | jq -r '.prompt_tokens') tokens," \
+                "~\$$(echo "$estimate" | jq -r '.cost_usd' | awk '{printf "%.6f", $1}') (chars/4 estimate)\e[0m"
             return
         fi
     done
 
-    get_completion "$@" || true
+    local status=0
+    get_completion "$@" || status=$?
     echo
+    return "$status"
+}
+
+get_cache_stats() {
+    local cache_dir="$1"
+    local pattern="$2"
+
+    if [[ ! -d "$cache_dir" ]]; then
+        echo "0 0"
+        return
+    fi
+    find "$cache_dir" -maxdepth 1 -type f -name "$pattern" -printf '%s\n' \
+        | awk '{count++; bytes += $1} END {print count + 0, bytes + 0}'
+}
+
+clear_cache_files() {
+    local cache_dir="$1"
+    local pattern="$2"
+    local label="$3"
+    local entries bytes
+    read -r entries bytes <<< "$(get_cache_stats "$cache_dir" "$pattern")"
+
+    if [[ "$entries" -gt 0 ]]; then
+        find "$cache_dir" -maxdepth 1 -type f -name "$pattern" -delete
+        echo "Cleared $entries $label entries from: $cache_dir"
+    else
+        echo "${label^} is empty."
+    fi
 }
 
 cmd_clear() {
+    local target="${2:-all}"
     local cache_dir=${CLAM_CACHE_DIR:-"$HOME/.clam/cache"}
     local harm_cache_dir=${CLAM_HARM_CACHE_DIR:-"$HOME/.clam/harm_cache"}
+    local explain_cache_dir=${CLAM_EXPLAIN_CACHE_DIR:-"$HOME/.clam/explain_cache"}
     local log_file=${CLAM_LOG_FILE:-"$HOME/.clam/clam.log"}
 
-    echo "This will clear the cache, harm detection cache, and log file."
-    echo -e "Completion cache: \e[31m$cache_dir\e[0m"
-    echo -e "Harm cache: \e[31m$harm_cache_dir\e[0m"
-    echo -e "Log file: \e[31m$log_file\e[0m"
+    case "$target" in
+        all)
+            echo "This will clear the cache, harm detection cache, explanation cache, and log file."
+            echo -e "Completion cache: \e[31m$cache_dir\e[0m"
+            echo -e "Harm cache: \e[31m$harm_cache_dir\e[0m"
+            echo -e "Explanation cache: \e[31m$explain_cache_dir\e[0m"
+            echo -e "Log file: \e[31m$log_file\e[0m"
+            ;;
+        completion) echo -e "This will clear the completion cache: \e[31m$cache_dir\e[0m" ;;
+        harm) echo -e "This will clear the harm detection cache: \e[31m$harm_cache_dir\e[0m" ;;
+        explain) echo -e "This will clear the explanation cache: \e[31m$explain_cache_dir\e[0m" ;;
+        *)
+            echo "Usage: clam clear [all|completion|harm|explain]"
+            return 1
+            ;;
+    esac
     read -r -p "Are you sure? (y/n): " confirm
 
     if [[ $confirm != "y" ]]; then
@@ -1579,36 +4239,28 @@ cmd_clear() {
         return
     fi
 
-    if [ -d "$cache_dir" ]; then
-        local cache_files=$(list_cache)
-        if [ -n "$cache_files" ]; then
-            while read -r line; do
-                local file=$(echo "$line" | cut -d ' ' -f 2-)
-                rm "$file"
-                echo "Removed: $file"
-            done <<< "$cache_files"
-            echo "Cleared cache in: $cache_dir"
-        else
-            echo "Cache is empty."
-        fi
-    fi
-
-    if [ -d "$harm_cache_dir" ]; then
-        local harm_cache_count=$(find "$harm_cache_dir" -name "harm-*.json" 2>/dev/null | wc -l)
-        if [ "$harm_cache_count" -gt 0 ]; then
-            rm "$harm_cache_dir"/harm-*.json 2>/dev/null
-            echo "Cleared $harm_cache_count harm detection cache entries from: $harm_cache_dir"
-        else
-            echo "Harm detection cache is empty."
-        fi
+    [[ "$target" == "all" || "$target" == "completion" ]] &&
+        clear_cache_files "$cache_dir" "acsh-*" "completion cache" && command rm -f "$cache_dir/hits"
+    [[ "$target" == "all" || "$target" == "harm" ]] &&
+        clear_cache_files "$harm_cache_dir" "harm-*.json" "harm detection cache"
+    [[ "$target" == "all" || "$target" == "explain" ]] &&
+        clear_cache_files "$explain_cache_dir" "explain-*.json" "explanation cache"
+
+    if [[ "$target" == "all" ]]; then
+        local outcome_file=${CLAM_OUTCOME_LOG_FILE:-"$HOME/.clam/outcomes.log"}
+        local feedback_file=${CLAM_SUGGESTION_LOG_FILE:-"$HOME/.clam/suggestions.log"}
+        local latency_file=${CLAM_LATENCY_LOG_FILE:-"$HOME/.clam/latency.log"}
+        [ -f "$log_file" ] && { command rm "$log_file"; echo "Removed: $log_file"; }
+        [ -f "$outcome_file" ] && { command rm "$outcome_file"; echo "Removed: $outcome_file"; }
+        [ -f "$feedback_file" ] && { command rm "$feedback_file"; echo "Removed: $feedback_file"; }
+        [ -f "$latency_file" ] && { command rm "$latency_file"; echo "Removed: $latency_file"; }
     fi
-
-    [ -f "$log_file" ] && { rm "$log_file"; echo "Removed: $log_file"; }
+    return 0
 }
 
 cmd_safeguard() {
     local action="$1"
-    local config_file="$HOME/.clam/config"
+    local config_file=$(get_config_path)
 
     case "$action" in
         enable)
@@ -1655,11 +4307,21 @@ cmd_safeguard() {
                 echo -e "Safeguards: \e[1;31mdisabled\e[0m"
             fi
             ;;
+        check)
+            local command="${*:2}"
+            local matches=$(check_all_matches "$command")
+            if [[ $(echo "$matches" | jq 'length') -eq 0 ]]; then
+                echo -e "\e[32m✓ No safeguard pattern matches\e[0m"
+                return 0
+            fi
+            echo "$matches" | jq -r '.[] | "\u001b[1;31m▶ \(.severity):\u001b[0m \(.description) \u001b[90m(\(.matched_text))\u001b[0m"'
+            ;;
         *)
-            echo "Usage: clam safeguard <enable|disable|status>"
+            echo "Usage: clam safeguard <enable|disable|status|check <command>>"
             echo "  enable  - Enable harmful command detection"
             echo "  disable - Disable harmful command detection"
             echo "  status  - Show current safeguard status"
+            echo "  check   - List every safeguard pattern a command matches"
             ;;
     esac
 }
@@ -1688,9 +4350,41 @@ cmd_usage() {
     echo -e "\tUsage count:\t\e[32m$line_count\e[0m"
     echo -e "\tAvg Cost:\t\$$(printf "%.4f" "$avg_cost")"
     echo -e "\tTotal Cost:\t\e[31m\$$(printf "%.4f" "$api_cost")\e[0m"
+
+    local outcomes=$(get_command_outcome_stats)
+    local total=$(echo "$outcomes" | jq -r '.total')
+    local failures=$(echo "$outcomes" | jq -r '.failures')
+    echo -e "\tCommands run:\t\e[32m$total\e[0m"
+    echo -e "\tFailed:\t\t\e[31m$failures\e[0m"
+    if [[ "$failures" -gt 0 ]]; then
+        echo -e "\tTop exit codes:\t$(echo "$outcomes" | jq -r '.exit_codes | to_entries | map(select(.key != "0")) | sort_by(-.value) | .[:3] | map("\(.key) (\(.value)x)") | join(", ")')"
+    fi
+    local acceptance=$(get_suggestion_acceptance_stats)
+    echo -e "\tSuggestions:\t$(echo "$acceptance" | jq -r '"\(.accepted) of \(.accepted + .rejected) accepted" + (if .rate == null then "" else " (\(.rate * 100 | round)%)" end)')"
+    local latency=$(get_latency_stats)
+    if [[ $(echo "$latency" | jq -r '.count') -gt 0 ]]; then
+        echo -e "\tLatency:\t$(echo "$latency" | jq -r '"p50 \(.p50_ms) ms, p95 \(.p95_ms) ms, p99 \(.p99_ms) ms (avg \(.average_ms) ms over \(.count))"')"
+    fi
     echo
     echo -n "Cache Size: $cache_count of ${CLAM_CACHE_SIZE:-10} in "; echo -e "\e[90m$cache_dir\e[0m"
-    echo "To clear log and cache, run: clam clear"
+
+    local harm_cache_dir=${CLAM_HARM_CACHE_DIR:-"$HOME/.clam/harm_cache"}
+    local explain_cache_dir=${CLAM_EXPLAIN_CACHE_DIR:-"$HOME/.clam/explain_cache"}
+    local entries bytes
+    read -r entries bytes <<< "$(get_cache_stats "$harm_cache_dir" "harm-*.json")"
+    echo -n "Harm Cache: $entries entries ($bytes bytes) in "; echo -e "\e[90m$harm_cache_dir\e[0m"
+    read -r entries bytes <<< "$(get_cache_stats "$explain_cache_dir" "explain-*.json")"
+    echo -n "Explanation Cache: $entries entries ($bytes bytes) in "; echo -e "\e[90m$explain_cache_dir\e[0m"
+    echo "Harm checks timed out: $(get_harm_timeout_count)"
+    echo "To clear log and cache, run: clam clear [all|completion|harm|explain]"
+}
+
+find_models_by_name() {
+    local model_name="$1"
+    local key
+    for key in "${!CLAM_MODELS[@]}"; do
+        jq -c --arg name "$model_name" 'select(.model == $name)' <<< "${CLAM_MODELS[$key]}"
+    done
 }
 
 cmd_model() {
@@ -1698,7 +4392,24 @@ cmd_model() {
     local selected_model
     local options=()
 
-    if [[ $# -ne 3 ]]; then
+    # Only ask the Ollama server for pulled models when Ollama is in use or
+    # named, so switching between hosted models never waits on localhost.
+    if [[ "${CLAM_PROVIDER^^}" == "OLLAMA" || "${2^^}" == "OLLAMA" ]]; then
+        register_ollama_models
+    fi
+
+    if [[ $# -eq 2 ]]; then
+        local selected_value=$(find_models_by_name "$2")
+        local match_count=$(grep -c . <<< "$selected_value")
+
+        if [[ "$match_count" -eq 0 ]]; then
+            echo "ERROR: Unknown model '$2'. Run 'clam model' to list the available models."
+            return 1
+        elif [[ "$match_count" -gt 1 ]]; then
+            echo "ERROR: '$2' is offered by $(jq -r '.provider' <<< "$selected_value" | sort | paste -sd ' ' -). Use: clam model <provider> $2"
+            return 1
+        fi
+    elif [[ $# -ne 3 ]]; then
         mapfile -t sorted_keys < <(for key in "${!CLAM_MODELS[@]}"; do echo "$key"; done | sort)
         for key in "${sorted_keys[@]}"; do
             options+=("$key")
@@ -1726,8 +4437,9 @@ cmd_model() {
     fi
 
     set_config_value "model" "$(echo "$selected_value" | jq -r '.model')"
-    set_config_value "endpoint" "$(echo "$selected_value" | jq -r '.endpoint')"
+    # The provider goes first so the endpoint is validated against it.
     set_config_value "provider" "$(echo "$selected_value" | jq -r '.provider')"
+    set_config_value "endpoint" "$(echo "$selected_value" | jq -r '.endpoint')"
 
     local prompt_cost=$(echo "$selected_value" | jq -r '.prompt_cost' | awk '{printf "%.8f", $1}')
     local completion_cost=$(echo "$selected_value" | jq -r '.completion_cost' | awk '{printf "%.8f", $1}')
@@ -1736,14 +4448,15 @@ cmd_model() {
 
     load_config
 
-    if [[ -z "$CLAM_ACTIVE_API_KEY" && ${CLAM_PROVIDER^^} != "OLLAMA" ]]; then
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
         echo -e "\e[34mSet ${CLAM_PROVIDER^^}_API_KEY\e[0m"
-        echo "Stored in ~/.clam/config"
+        echo "Stored in $(get_config_path)"
 
         case "${CLAM_PROVIDER^^}" in
             OPENAI) echo "Create a new one: https://platform.openai.com/settings/profile?tab=api-keys" ;;
             ANTHROPIC) echo "Create a new one: https://console.anthropic.com/settings/keys" ;;
             GROQ) echo "Create a new one: https://console.groq.com/keys" ;;
+            MISTRAL) echo "Create a new one: https://console.mistral.ai/api-keys" ;;
         esac
 
         echo -n "Enter your ${CLAM_PROVIDER^^} API Key: "
@@ -1769,24 +4482,22 @@ cmd_model() {
     echo -n "API Key:"
 
     if [[ -z $CLAM_ACTIVE_API_KEY ]]; then
-        if [[ ${CLAM_PROVIDER^^} == "OLLAMA" ]]; then
+        if ! provider_requires_api_key; then
             echo -e "\t\e[90mNot Used\e[0m"
         else
             echo -e "\t\e[31mUNSET\e[0m"
         fi
     else
-        local key_suffix=${CLAM_ACTIVE_API_KEY:4}
-        local masked_key="${CLAM_ACTIVE_API_KEY:0:4}...${key_suffix: -4}"
-        echo -e "\t\e[32m$masked_key\e[0m"
+        echo -e "\t\e[32m$(mask_api_key "$CLAM_ACTIVE_API_KEY")\e[0m"
     fi
 
-    if [[ -z $CLAM_ACTIVE_API_KEY && ${CLAM_PROVIDER^^} != "OLLAMA" ]]; then
+    if [[ -z "$CLAM_ACTIVE_API_KEY" ]] && provider_requires_api_key; then
         echo "To set the API Key, run:"
         echo -e "\t\e[31mclam config set api_key <your-api-key>\e[0m"
         echo -e "\t\e[31mexport ${CLAM_PROVIDER^^}_API_KEY=<your-api-key>\e[0m"
     fi
 
-    if [[ ${CLAM_PROVIDER^^} == "OLLAMA" ]]; then
+    if ! provider_requires_api_key; then
         echo "To set a custom endpoint:"
         echo -e "\t\e[34mclam config set endpoint <your-url>\e[0m"
         echo "Other models can be set with:"
@@ -1798,53 +4509,83 @@ cmd_model() {
     echo
 }
 
+parse_fep_fixes() {
+    local content="$1"
+    extract_json "$content" 'select(type == "array" or has("fixes") or has("recommended_command"))' | jq -c '
+        (if type == "array" then . elif type == "object" and has("fixes") then .fixes else [.] end)
+        | map(select(type == "object" and (.recommended_command // "") != ""))
+        | map({recommended_command, explanation: (.explanation // ""), confidence: (.confidence // null)})
+        | .[:3]' 2>/dev/null
+}
+
 cmd_fep() {
     load_config
+    is_privacy_mode && { skip_for_privacy "clam fep"; return; }
     local user_context="${*:2}"
 
     echo
     start_spinner "Analyzing error and generating fix..."
-    local response=$(get_fep_completion "$user_context")
+    local response status=0
+    response=$(run_cancellable fep get_fep_completion "$user_context") || status=$?
     stop_spinner
 
-    if [[ -z "$response" ]]; then
+    if [[ $status -eq $CLAM_EXIT_CANCELLED ]]; then
+        echo "Cancelled."
+        return "$CLAM_EXIT_CANCELLED"
+    elif [[ -z "$response" ]]; then
         echo_error "Failed to get response from API. Check config and API key (clam config)."
         return 1
     fi
 
-    local content
-    if [[ "${CLAM_PROVIDER^^}" == "ANTHROPIC" ]]; then
-        content=$(echo "$response" | jq -r '.content[0].text // empty')
-    elif [[ "${CLAM_PROVIDER^^}" == "OLLAMA" ]]; then
-        content=$(echo "$response" | jq -r '.message.content // empty')
-    else
-        content=$(echo "$response" | jq -r '.choices[0].message.content // empty')
-    fi
+    local content=$(extract_message_content "$response")
 
     if [[ -z "$content" ]]; then
         echo_error "Empty response from model"
         return 1
     fi
 
-    local recommended_cmd=$(echo "$content" | jq -r '.recommended_command // empty')
-    local explanation=$(echo "$content" | jq -r '.explanation // empty')
+    local fixes=$(parse_fep_fixes "$content")
+    local fix_count=$(echo "${fixes:-[]}" | jq 'length')
 
-    if [[ -z "$recommended_cmd" ]]; then
+    if [[ "$fix_count" -eq 0 ]]; then
         echo_error "Could not parse recommendation from response"
         echo "Raw response: $content"
         return 1
     fi
 
-    echo -e "\e[32m━━━ Recommended Command ━━━\e[0m"
+    local recommended_cmd=$(echo "$fixes" | jq -r '.[0].recommended_command')
+    local explanation=$(echo "$fixes" | jq -r '.[0].explanation')
+    local confidence=$(echo "$fixes" | jq -r '.[0].confidence // empty')
+
+    echo -e "\e[32m━━━ Recommended Command ━━━\e[0m${confidence:+ \e[90m(confidence: $confidence)\e[0m}"
     echo -e "\e[1m$recommended_cmd\e[0m"
     echo
     echo -e "\e[32m━━━ Explanation ━━━\e[0m"
     echo "$explanation"
     echo
-    echo -e "\e[33mRun this command? [Y/n]\e[0m"
+
+    local run_prompt="Run this command? [Y/n]"
+    if [[ "$fix_count" -gt 1 ]]; then
+        echo -e "\e[32m━━━ Alternatives ━━━\e[0m"
+        for ((idx = 1; idx < fix_count; idx++)); do
+            echo -e "  $((idx + 1))) \e[1m$(echo "$fixes" | jq -r ".[$idx].recommended_command")\e[0m"
+            echo -e "     \e[90m$(echo "$fixes" | jq -r ".[$idx].explanation")\e[0m"
+        done
+        echo
+        local alternative_range="2"
+        [[ "$fix_count" -gt 2 ]] && alternative_range="2-$fix_count"
+        run_prompt="Run this command? [Y/n, or $alternative_range for an alternative]"
+    fi
+
+    echo -e "\e[33m$run_prompt\e[0m"
     read -r -n 1 confirm
     echo
 
+    if [[ "$confirm" =~ ^[0-9]$ && "$confirm" -ge 2 && "$confirm" -le "$fix_count" ]]; then
+        recommended_cmd=$(echo "$fixes" | jq -r ".[$((confirm - 1))].recommended_command")
+        confirm="y"
+    fi
+
     if [[ "$confirm" =~ ^[Yy]$ ]] || [[ -z "$confirm" ]]; then
         echo -e "\e[90mExecuting: $recommended_cmd\e[0m"
         eval "$recommended_cmd"
@@ -1853,11 +4594,330 @@ cmd_fep() {
     fi
 }
 
+cmd_explain() {
+    local command="${*:2}"
+
+    load_config > /dev/null
+    is_privacy_mode && { skip_for_privacy "clam explain"; return; }
+
+    if [[ -z "$command" ]]; then
+        echo "Usage: clam explain <command>"
+        return 1
+    fi
+
+    echo
+    start_spinner "Explaining command..."
+    local explanation=$(get_command_explanation "$command")
+    stop_spinner
+
+    [[ -z "$explanation" ]] && return 1
+
+    echo -e "\e[32m━━━ What it does ━━━\e[0m"
+    echo "$explanation" | jq -r '.summary'
+    echo
+    echo -e "\e[32m━━━ Side effects ━━━\e[0m"
+    if [[ $(echo "$explanation" | jq '.side_effects | length') -eq 0 ]]; then
+        echo -e "\e[90mNone\e[0m"
+    else
+        echo "$explanation" | jq -r '.side_effects[] | "  • " + .'
+    fi
+}
+
+cmd_generate() {
+    local description="${*:2}"
+
+    load_config > /dev/null
+    is_privacy_mode && { skip_for_privacy "clam generate"; return; }
+
+    if [[ -z "$description" ]]; then
+        echo "Usage: clam generate <description>"
+        return 1
+    fi
+
+    echo
+    start_spinner "Generating command..."
+    local generated=$(generate_command "$description")
+    stop_spinner
+
+    [[ -z "$generated" ]] && return 1
+
+    echo -e "\e[32m━━━ Command ━━━\e[0m"
+    echo -e "\e[1m$(echo "$generated" | jq -r '.command')\e[0m"
+    echo
+    echo -e "\e[32m━━━ Explanation ━━━\e[0m"
+    echo "$generated" | jq -r '.explanation'
+
+    if [[ $(echo "$generated" | jq -r '.warning != null') == "true" ]]; then
+        echo
+        echo -e "\e[1;31m⚠️  $(echo "$generated" | jq -r '.warning.severity | ascii_upcase'): $(echo "$generated" | jq -r '.warning.description')\e[0m"
+    fi
+}
+
+cmd_recap() {
+    load_config > /dev/null
+    is_privacy_mode && { skip_for_privacy "clam recap"; return; }
+
+    if [[ -z "$CLAM_SESSION_COMMANDS" ]]; then
+        echo -e "\e[90mNo commands recorded in this session yet.\e[0m"
+        return 0
+    fi
+
+    echo
+    start_spinner "Recapping session..."
+    local recap=$(summarize_session)
+    stop_spinner
+
+    [[ -z "$recap" ]] && return 1
+
+    echo -e "\e[32m━━━ Session recap ━━━\e[0m"
+    echo "$recap" | jq -r '.summary'
+    echo
+    echo -e "\e[32m━━━ Next steps ━━━\e[0m"
+    if [[ $(echo "$recap" | jq '.next_steps | length') -eq 0 ]]; then
+        echo -e "\e[90mNone\e[0m"
+    else
+        echo "$recap" | jq -r '.next_steps[] | "  • " + .'
+    fi
+}
+
+cmd_next() {
+    load_config > /dev/null
+    is_privacy_mode && { skip_for_privacy "clam next"; return; }
+
+    echo
+    start_spinner "Suggesting next commands..."
+    local suggestions=$(suggest_next_command)
+    stop_spinner
+
+    [[ -z "$suggestions" ]] && return 1
+    if [[ "$suggestions" == "[]" ]]; then
+        echo -e "\e[90mNo captured output in $CLAM_LAST_OUTPUT_FILE to suggest from.\e[0m"
+        return 0
+    fi
+
+    echo -e "\e[32m━━━ Next commands ━━━\e[0m"
+    echo "$suggestions" | jq -r 'to_entries[] | "  \(.key + 1). \(.value.command)\n     \(.value.explanation)"'
+}
+
+cmd_providers() {
+    echo -e "\e[1;32mClam.sh - Supported Providers\e[0m"
+    get_providers | jq -r '.[] | [
+            .name,
+            (if .requires_api_key then "API key" else "no key needed" end),
+            (.default_model // "-"),
+            (.default_endpoint // "set endpoint in config")
+        ] | @tsv' | while IFS=$'\t' read -r name key_note model endpoint; do
+        printf '  %-10s %-14s %-28s \e[90m%s\e[0m\n' "$name" "$key_note" "$model" "$endpoint"
+    done
+}
+
+toggle_privacy_mode() {
+    local value
+    case "$1" in
+        on) value=true ;;
+        off) value=false ;;
+        "") [[ "$CLAM_PRIVACY_MODE" == "true" ]] && value=false || value=true ;;
+        *) echo_error "Usage: clam privacy [on|off|status]"; return 1 ;;
+    esac
+    set_config_value "privacy_mode" "$value" > /dev/null || return
+    echo "$value"
+}
+
+cmd_privacy() {
+    load_config > /dev/null
+    local state="${CLAM_PRIVACY_MODE:-false}"
+    if [[ "$1" != "status" ]]; then
+        state=$(toggle_privacy_mode "$1") || return
+    fi
+    if [[ "$state" == "true" ]]; then
+        echo -e "Privacy mode: \e[1;32mon\e[0m \e[90m(no requests are sent; typo fixes and local safeguards still work)\e[0m"
+    else
+        echo -e "Privacy mode: \e[1;33moff\e[0m"
+    fi
+}
+
+cmd_paths() {
+    load_config > /dev/null
+    echo -e "\e[1;32mClam.sh - File Locations\e[0m"
+    get_app_paths | jq -r 'to_entries[] | [.key, .value.path, (if .value.exists then "" else "(missing)" end)] | @tsv' \
+        | while IFS=$'\t' read -r name path note; do
+            printf '  %-17s %s \e[90m%s\e[0m\n' "$name" "$path" "$note"
+        done
+}
+
+cmd_circuit() {
+    load_config > /dev/null
+    if [[ "$1" == "reset" ]]; then
+        command rm -f "$(get_circuit_file)"
+        echo -e "\e[32m✓ ${CLAM_PROVIDER:-openai} circuit reset\e[0m"
+        return 0
+    fi
+
+    local circuit=$(get_circuit_state)
+    case "$(echo "$circuit" | jq -r '.state')" in
+        open)
+            echo -e "\e[1;33m⚠ ${CLAM_PROVIDER:-openai} temporarily unavailable\e[0m (retrying in $(echo "$circuit" | jq -r '.retry_in')s)"
+            echo -e "  \e[90mLast error:\e[0m $(echo "$circuit" | jq -r '.last_error')"
+            ;;
+        half_open)
+            echo -e "\e[33m${CLAM_PROVIDER:-openai} is being retried\e[0m after $(echo "$circuit" | jq -r '.failures') failed requests"
+            ;;
+        *)
+            echo -e "\e[32m✓ ${CLAM_PROVIDER:-openai} available\e[0m"
+            ;;
+    esac
+}
+
+cmd_cancel() {
+    local kinds=("${1:-suggestion}")
+    [[ -z "$1" ]] && kinds+=(fep)
+
+    local kind cancelled=0
+    for kind in "${kinds[@]}"; do
+        case "$kind" in
+            suggestion|fep) ;;
+            *)
+                echo "Usage: clam cancel [suggestion|fep]"
+                return 1
+                ;;
+        esac
+        if cancel_request "$kind"; then
+            echo -e "\e[32m✓ Cancelled the $kind request\e[0m"
+            cancelled=1
+        fi
+    done
+    [[ $cancelled -eq 1 ]] || echo "No request in flight"
+}
+
+cmd_paste() {
+    local text="${*:2}"
+    [[ -z "$text" && ! -t 0 ]] && text=$(cat)
+
+    local result=$(check_paste "$text")
+    if [[ $(echo "$result" | jq -r '.warning') != "true" ]]; then
+        echo -e "\e[32m✓ Looks safe to paste\e[0m"
+        return 0
+    fi
+
+    echo -e "\e[1;33m⚠ WARNING: Check this paste before running it\e[0m"
+    if (( $(echo "$result" | jq '.executable_lines') > 1 )); then
+        echo -e "\e[1;90m▶\e[0m It runs $(echo "$result" | jq '.executable_lines') commands"
+    fi
+    echo "$result" | jq -r '.dangerous[] | "\u001b[1;31m▶ Line \(.line) (\(.severity)):\u001b[0m \(.command)\n  \(.description)"'
+    return 1
+}
+
+cmd_lint() {
+    local files=("${@:2}")
+    [[ ${#files[@]} -eq 0 ]] && files=("-")
+
+    local file label content found=0
+    for file in "${files[@]}"; do
+        label="$file"
+        if [[ "$file" == "-" ]]; then
+            label="stdin"
+            content=$(cat)
+        elif [[ -f "$file" ]]; then
+            content=$(cat "$file")
+        else
+            echo_error "No such file: $file"
+            return 1
+        fi
+
+        local findings=$(lint_script "$content")
+        [[ $(echo "$findings" | jq 'length') -gt 0 ]] && found=1
+        echo "$findings" | jq -r --arg file "$label" \
+            '.[] | "\($file):\(.line): \(.severity): \(.description)\n    \(.command)"'
+    done
+
+    if [[ $found -eq 0 ]]; then
+        echo -e "\e[32m✓ No dangerous commands found\e[0m"
+        return 0
+    fi
+    return 1
+}
+
+cmd_test() {
+    load_config > /dev/null
+
+    echo -e "Testing \e[90m${CLAM_PROVIDER:-openai} / ${CLAM_MODEL:-gpt-4o}\e[0m at \e[90m$(get_endpoint)\e[0m"
+    start_spinner "Connecting..."
+    local result=$(test_connection)
+    stop_spinner
+
+    local latency_ms=$(echo "$result" | jq -r '.latency_ms')
+    if [[ $(echo "$result" | jq -r '.ok') == "true" ]]; then
+        echo_green "Connection OK (${latency_ms} ms)"
+    else
+        echo_error "Connection failed after ${latency_ms} ms: $(echo "$result" | jq -r '.reason')"
+        return 1
+    fi
+}
+
+cmd_pin() {
+    local action="$1"
+    local user_input="${*:2}"
+
+    if [[ -z "$user_input" ]]; then
+        echo "Usage: clam $action <input>"
+        return 1
+    fi
+
+    if [[ "$action" == "pin" ]]; then
+        pin_cache_entry "$user_input"
+        echo_green "Pinned: suggestions for '$user_input' will stay cached."
+    else
+        unpin_cache_entry "$user_input"
+        echo_green "Unpinned: '$user_input' can be evicted again."
+    fi
+}
+
+cmd_history() {
+    local query="${*:2}"
+    local results=$(search_history "$query")
+
+    if [[ -z "$results" ]]; then
+        echo -e "\e[90mNo matching commands in history.\e[0m"
+        return 1
+    fi
+
+    while IFS=$'\t' read -r index command; do
+        printf "\e[90m%6s\e[0m  %s\n" "$index" "$command"
+    done <<< "$results"
+}
+
+cmd_output() {
+    local line_count="${2:-20}"
+
+    if [[ ! "$line_count" =~ ^[0-9]+$ ]]; then
+        echo_error "SyntaxError: expected 'clam output [lines]'"
+        return 1
+    fi
+    if ! get_recent_output "$line_count"; then
+        echo -e "\e[90mNo captured output in $CLAM_LAST_OUTPUT_FILE\e[0m"
+        return 1
+    fi
+}
+
 run_with_capture() {
     local cmd="$*"
+    local max_bytes="${CLAM_MAX_CAPTURE_BYTES:-1048576}"
+    local exit_code
     export CLAM_LAST_COMMAND="$cmd"
-    eval "$cmd" 2>&1 | tee "$CLAM_LAST_OUTPUT_FILE"
-    export CLAM_LAST_EXIT_CODE="${PIPESTATUS[0]}"
+
+    {
+        eval "$cmd" 2>&1 | tee /dev/fd/3 | tail -c "$((max_bytes + 1))" > "$CLAM_LAST_OUTPUT_FILE"
+        exit_code="${PIPESTATUS[0]}"
+    } 3>&1
+    export CLAM_LAST_EXIT_CODE="$exit_code"
+
+    if (( $(wc -c < "$CLAM_LAST_OUTPUT_FILE") > max_bytes )); then
+        tail -c "$max_bytes" "$CLAM_LAST_OUTPUT_FILE" > "$CLAM_LAST_OUTPUT_FILE.tmp" &&
+            mv "$CLAM_LAST_OUTPUT_FILE.tmp" "$CLAM_LAST_OUTPUT_FILE"
+        export CLAM_LAST_OUTPUT_TRUNCATED=true
+    else
+        export CLAM_LAST_OUTPUT_TRUNCATED=false
+    fi
 }
 
 cmd_demo() {
@@ -1889,7 +4949,7 @@ cmd_demo() {
     echo
     echo -e "\e[1;33m━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━\e[0m"
     echo
-    echo -e "  \e[90mSupports: OpenAI, Anthropic, Groq, and local Ollama models\e[0m"
+    echo -e "  \e[90mSupports: OpenAI, Anthropic, Groq, Mistral, and local Ollama models\e[0m"
     echo -e "  \e[90mRun \e[0mclam model\e[90m to configure your preferred LLM\e[0m"
     echo
 }
@@ -1901,15 +4961,30 @@ case "$1" in
     system) show_system_info ;;
     install) cmd_install ;;
     remove) cmd_remove "$@" ;;
-    clear) cmd_clear ;;
-    safeguard) cmd_safeguard "$2" ;;
+    clear) cmd_clear "$@" ;;
+    safeguard) cmd_safeguard "${@:2}" ;;
     usage) cmd_usage ;;
     model) cmd_model "$@" ;;
+    providers) cmd_providers ;;
+    circuit) cmd_circuit "$2" ;;
+    cancel) cmd_cancel "$2" ;;
+    paths) cmd_paths ;;
+    privacy) cmd_privacy "$2" ;;
     config) cmd_config "$@" ;;
     enable) cmd_enable ;;
     disable) cmd_disable ;;
     command) cmd_command "$@" ;;
     fep) cmd_fep "$@" ;;
+    explain) cmd_explain "$@" ;;
+    generate) cmd_generate "$@" ;;
+    recap) cmd_recap ;;
+    next) cmd_next ;;
+    paste) cmd_paste "$@" ;;
+    lint) cmd_lint "$@" ;;
+    history) cmd_history "$@" ;;
+    output) cmd_output "$@" ;;
+    pin|unpin) cmd_pin "$@" ;;
+    test) cmd_test ;;
     demo) cmd_demo ;;
     *)
         if [[ -n "$1" ]]; then
@@ -33,3 +33,13 @@ tests/test_final_verification.sh
 tests/test_harm_basic.sh
 tests/test_harm_detection.sh
 tests/test_rm.sh
+tests/test_usage.sh
+tests/test_context.sh
+tests/test_completion.sh
+tests/test_api.sh
+tests/test_config.sh
+tests/test_fep.sh
+tests/test_explain.sh
+tests/test_history.sh
+tests/test_cache.sh
+tests/test_safeguard.sh
@@ -0,0 +1,76 @@
+#!/bin/bash
+# Shared setup for the offline test scripts. Sources clam.sh against a
+# throwaway $HOME so tests never touch the real ~/.clam directory.
+
+script_dir="$(cd "$(dirname "${BASH_SOURCE[1]}")" && pwd)"
+test_home="$(mktemp -d)"
+trap 'rm -rf "$test_home"' EXIT
+export HOME="$test_home"
+
+source "$script_dir/../clam.sh" > /dev/null 2>&1
+
+failures=0
+
+pass() {
+    echo "✓ PASS: $1"
+}
+
+fail() {
+    echo "✗ FAIL: $1"
+    failures=$((failures + 1))
+}
+
+finish_tests() {
+    echo "================================"
+    echo "$1 Complete ($failures failed)"
+    echo "================================"
+    [ "$failures" -eq 0 ]
+}
+
+# Replaces curl on the PATH with a stub that records its arguments in
+# $HOME/curl_args and replies with the given body and HTTP status.
+use_fake_curl() {
+    local body="$1"
+    local status="${2:-200}"
+    mkdir -p "$HOME/bin"
+    printf '%s' "$body" > "$HOME/curl_body"
+    cat > "$HOME/bin/curl" <<STUB
+#!/bin/bash
+printf '%s\n' "\$@" >> "$HOME/curl_args"
+cat "$HOME/curl_body"
+printf '\n%s' "$status"
+STUB
+    chmod +x "$HOME/bin/curl"
+    rm -f "$HOME/curl_args"
+    [[ ":$PATH:" == *":$HOME/bin:"* ]] || export PATH="$HOME/bin:$PATH"
+}
+
+# Like use_fake_curl, but each call replies with the next body in turn; the
+# last body is repeated once they run out.
+use_fake_curl_replies() {
+    mkdir -p "$HOME/bin"
+    rm -f "$HOME"/curl_body.* "$HOME/curl_calls" "$HOME/curl_args"
+    local index=0 body
+    for body in "$@"; do
+        index=$((index + 1))
+        printf '%s' "$body" > "$HOME/curl_body.$index"
+    done
+    cat > "$HOME/bin/curl" <<STUB
+#!/bin/bash
+printf '%s\n' "\$@" >> "$HOME/curl_args"
+echo call >> "$HOME/curl_calls"
+call=\$(wc -l < "$HOME/curl_calls")
+[ "\$call" -le $index ] || call=$index
+cat "$HOME/curl_body.\$call"
+printf '\n200'
+STUB
+    chmod +x "$HOME/bin/curl"
+    [[ ":$PATH:" == *":$HOME/bin:"* ]] || export PATH="$HOME/bin:$PATH"
+}
+
+# Replaces the shell history with the given commands, one per argument.
+load_fake_history() {
+    printf '%s\n' "$@" > "$HOME/fake_history"
+    HISTFILE=/dev/null
+    set -o history; history -c; history -r "$HOME/fake_history"; set +o history
+}
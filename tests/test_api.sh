@@ -0,0 +1,708 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "API Request Tests"
+echo "================================"
+echo
+
+echo "Test 1: Timeouts fall back to defaults and are clamped"
+echo "---"
+if [ "$(resolve_timeout "" 30)" == "30" ] &&
+   [ "$(resolve_timeout "abc" 60)" == "60" ] &&
+   [ "$(resolve_timeout "0" 3)" == "1" ] &&
+   [ "$(resolve_timeout "0.2" 3 0.5)" == "0.5" ] &&
+   [ "$(resolve_timeout "2.5" 3)" == "2.5" ]; then
+    pass "Defaults, minimum clamp and fractional seconds resolved"
+else
+    fail "Unexpected timeout resolution"
+fi
+echo
+
+echo "Test 2: Configured completion timeout reaches curl"
+echo "---"
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[]}"}}]}}]}'
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key CLAM_COMPLETION_TIMEOUT=7 get_completion "ls" > /dev/null 2>&1
+if grep -qx -- "-m" "$HOME/curl_args" && [ "$(grep -x -A1 -- "-m" "$HOME/curl_args" | tail -n 1)" == "7" ]; then
+    pass "curl called with -m 7"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+echo
+
+echo "Test 3: A zero harm timeout is clamped instead of disabling the deadline"
+echo "---"
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"is_harmful\":false,\"explanation\":\"ok\"}"}}]}}]}'
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key CLAM_HARM_TIMEOUT=0 detect_command_harm "make clean" > /dev/null 2>&1
+if [ "$(grep -x -A1 -- "-m" "$HOME/curl_args" | tail -n 1)" == "1" ]; then
+    pass "curl called with -m 1"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+echo
+
+echo "Test 4: proxy_url is passed to curl"
+echo "---"
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[]}"}}]}}]}'
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key CLAM_PROXY_URL="socks5://proxy.corp:1080" get_completion "ls" > /dev/null 2>&1
+if [ "$(grep -x -A1 -- "--proxy" "$HOME/curl_args" | tail -n 1)" == "socks5://proxy.corp:1080" ]; then
+    pass "curl called with --proxy socks5://proxy.corp:1080"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+echo
+
+echo "Test 5: An invalid proxy_url produces a descriptive error"
+echo "---"
+use_fake_curl '{}'
+error_output=$(CLAM_PROVIDER=openai CLAM_PROXY_URL="proxy.corp:1080" call_api "https://example.com" "{}" "key" 5 2>&1 >/dev/null)
+if [[ "$error_output" == *"Invalid proxy_url 'proxy.corp:1080'"* && ! -f "$HOME/curl_args" ]]; then
+    pass "Request refused before reaching curl"
+else
+    fail "Unexpected result: $error_output"
+fi
+
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+if ! set_config_value "proxy_url" "ftp://proxy.corp" 2>/dev/null && set_config_value "proxy_url" "http://proxy.corp:3128" > /dev/null; then
+    pass "config set rejects ftp:// and accepts http://"
+else
+    fail "proxy_url validation on config set is wrong"
+fi
+echo
+
+echo "Test 6: The custom provider sends requests without a key or Authorization header"
+echo "---"
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[]}"}}]}}]}'
+CLAM_PROVIDER=custom CLAM_ACTIVE_API_KEY="" CLAM_ENDPOINT="http://localhost:1234/v1/chat/completions" get_completion "ls" > /dev/null 2>&1
+if grep -qx "http://localhost:1234/v1/chat/completions" "$HOME/curl_args" 2>/dev/null &&
+   ! grep -q "Authorization" "$HOME/curl_args"; then
+    pass "Request sent to the gateway with no Authorization header"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args" 2>/dev/null)"
+fi
+
+use_fake_curl '{}'
+error_output=$(CLAM_PROVIDER=custom CLAM_ENDPOINT="" call_api "https://api.openai.com/v1/chat/completions" "{}" "" 5 2>&1 >/dev/null)
+if [[ "$error_output" == *"custom provider needs an endpoint"* && ! -f "$HOME/curl_args" ]]; then
+    pass "Missing endpoint refused before reaching curl"
+else
+    fail "Unexpected result: $error_output"
+fi
+echo
+
+echo "Test 7: Provider error envelopes are reduced to type and message"
+echo "---"
+anthropic_error='{"type":"error","error":{"type":"rate_limit_error","message":"Number of request tokens has exceeded your per-minute rate limit"}}'
+if [ "$(extract_api_error "$anthropic_error")" == "rate_limit_error: Number of request tokens has exceeded your per-minute rate limit" ]; then
+    pass "Anthropic envelope parsed"
+else
+    fail "Anthropic envelope: $(extract_api_error "$anthropic_error")"
+fi
+
+if [ "$(extract_api_error '{"error":{"message":"Incorrect API key provided","type":"invalid_request_error"}}')" == "invalid_request_error: Incorrect API key provided" ] &&
+   [ "$(extract_api_error '{"error":"model \"llama3\" not found"}')" == 'model "llama3" not found' ]; then
+    pass "OpenAI-style and Ollama errors parsed"
+else
+    fail "OpenAI-style or Ollama error not parsed"
+fi
+
+if [ "$(extract_api_error "<html>502 Bad Gateway</html>")" == "<html>502 Bad Gateway</html>" ]; then
+    pass "Non-JSON body falls back to raw text"
+else
+    fail "Raw fallback: $(extract_api_error "<html>502 Bad Gateway</html>")"
+fi
+
+use_fake_curl "$anthropic_error" 429
+error_output=$(CLAM_PROVIDER=anthropic CLAM_ACTIVE_API_KEY=test-key CLAM_ENDPOINT=https://api.anthropic.com/v1/messages \
+    get_fep_completion "" 2>&1 >/dev/null)
+if [[ "$error_output" == *"rate_limit_error: Number of request tokens"* && "$error_output" != *'"type":"error"'* ]]; then
+    pass "FEP failure reports the structured message"
+else
+    fail "Unexpected FEP error: $error_output"
+fi
+echo
+
+echo "Test 8: Failed statuses map to actionable messages"
+echo "---"
+expect_failure_message() {
+    local status="$1"
+    local provider="$2"
+    local kind="$3"
+    local expected="$4"
+    local message=$(CLAM_PROVIDER="$provider" CLAM_MODEL=llama3 describe_api_failure "$status")
+
+    if [ "$(classify_api_error "$status")" == "$kind" ] && [[ "$message" == *"$expected"* ]]; then
+        pass "$provider $status is $kind: $message"
+    else
+        fail "$provider $status: kind $(classify_api_error "$status"), message '$message'"
+    fi
+}
+expect_failure_message 401 openai auth "clam config set openai_api_key"
+expect_failure_message 403 anthropic auth "clam config set anthropic_api_key"
+expect_failure_message 429 groq rate_limit "Rate limited or out of quota"
+expect_failure_message 404 ollama not_found "ollama pull llama3"
+expect_failure_message 404 openai not_found "check the endpoint"
+expect_failure_message 529 anthropic server "try again shortly"
+expect_failure_message 000 openai network "No response"
+expect_failure_message 418 openai unknown "Unexpected status code 418"
+echo
+
+echo "Test 9: Connection test reports success, auth failures and missing Ollama models"
+echo "---"
+use_fake_curl '{"choices":[{"message":{"content":"OK"}}]}'
+result=$(CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key CLAM_ENDPOINT=https://api.openai.com/v1/chat/completions test_connection)
+if [ "$(echo "$result" | jq -r '.ok')" == "true" ] && [ "$(echo "$result" | jq -r '.latency_ms | type')" == "number" ] &&
+   grep -q '"max_tokens": 1' "$HOME/curl_args"; then
+    pass "One-token ping succeeded in $(echo "$result" | jq -r '.latency_ms') ms"
+else
+    fail "Unexpected result: $result"
+fi
+
+use_fake_curl '{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}' 401
+result=$(CLAM_PROVIDER=anthropic CLAM_ACTIVE_API_KEY=bad-key CLAM_ENDPOINT=https://api.anthropic.com/v1/messages test_connection)
+if [ "$(echo "$result" | jq -r '.ok')" == "false" ] &&
+   [[ "$(echo "$result" | jq -r '.reason')" == *"Unauthorized"*"authentication_error: invalid x-api-key"* ]]; then
+    pass "401 reported with the provider message"
+else
+    fail "Unexpected result: $result"
+fi
+
+use_fake_curl '{"models":[{"name":"codellama:latest"},{"name":"llama3:8b"}]}'
+result=$(CLAM_PROVIDER=ollama CLAM_MODEL=codellama CLAM_ENDPOINT=http://localhost:11434/api/chat test_connection)
+if [ "$(echo "$result" | jq -r '.ok')" == "true" ] && grep -qx "http://localhost:11434/api/tags" "$HOME/curl_args"; then
+    pass "Ollama checked through /api/tags"
+else
+    fail "Unexpected result: $result"
+fi
+
+result=$(CLAM_PROVIDER=ollama CLAM_MODEL=mistral CLAM_ENDPOINT=http://localhost:11434/api/chat test_connection)
+if [ "$(echo "$result" | jq -r '.ok')" == "false" ] && [[ "$(echo "$result" | jq -r '.reason')" == *"ollama pull mistral"* ]]; then
+    pass "Missing Ollama model reported"
+else
+    fail "Unexpected result: $result"
+fi
+echo
+
+echo "Test 10: Pulled Ollama models are listed and registered"
+echo "---"
+use_fake_curl '{"models":[{"name":"codellama:latest","size":3825819519},{"name":"llama3:8b","size":4661224676}]}'
+models=$(CLAM_PROVIDER=ollama CLAM_ENDPOINT=http://gpu-box:11434/api/chat list_ollama_models)
+if [ "$models" == $'codellama:latest\nllama3:8b' ] && grep -qx "http://gpu-box:11434/api/tags" "$HOME/curl_args"; then
+    pass "Model names parsed from /api/tags"
+else
+    fail "Unexpected models: $models"
+fi
+
+CLAM_PROVIDER=ollama CLAM_ENDPOINT=http://gpu-box:11434/api/chat register_ollama_models
+if [ "$(echo "${CLAM_MODELS["ollama:	llama3:8b"]}" | jq -r '.endpoint')" == "http://gpu-box:11434/api/chat" ]; then
+    pass "llama3:8b selectable with the configured endpoint"
+else
+    fail "llama3:8b not registered: ${CLAM_MODELS["ollama:	llama3:8b"]}"
+fi
+
+use_fake_curl '' 000
+if ! models=$(list_ollama_models http://localhost:11434 2>/dev/null) && [ -z "$models" ]; then
+    pass "Server down yields an empty list and a failure status"
+else
+    fail "Unexpected result with server down: $models"
+fi
+echo
+
+echo "Test 11: Base URLs are completed with the provider's chat path"
+echo "---"
+expect_endpoint() {
+    local provider="$1"
+    local input="$2"
+    local expected="$3"
+    local resolved=$(normalize_endpoint "$input" "$provider")
+
+    if [ "$resolved" == "$expected" ]; then
+        pass "$provider: $input -> $resolved"
+    else
+        fail "$provider: $input resolved to '$resolved', expected $expected"
+    fi
+}
+expect_endpoint ollama "http://localhost:11434" "http://localhost:11434/api/chat"
+expect_endpoint ollama "http://localhost:11434/" "http://localhost:11434/api/chat"
+expect_endpoint ollama "http://localhost:11434/api/chat" "http://localhost:11434/api/chat"
+expect_endpoint anthropic "https://api.anthropic.com" "https://api.anthropic.com/v1/messages"
+expect_endpoint custom "http://localhost:8000/v1" "http://localhost:8000/v1/chat/completions"
+expect_endpoint groq "https://api.groq.com/openai/v1/chat/completions" "https://api.groq.com/openai/v1/chat/completions"
+
+use_fake_curl '{"message":{"content":"{\"suggestions\":[]}"}}'
+CLAM_PROVIDER=ollama CLAM_ENDPOINT=http://localhost:11434 CLAM_PROXY_URL="" get_completion "uptime" > /dev/null 2>&1
+if grep -qx "http://localhost:11434/api/chat" "$HOME/curl_args"; then
+    pass "Completion sent to the normalized Ollama endpoint"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+echo
+
+echo "Test 12: A slow harm check returns the safe default at the deadline"
+echo "---"
+mkdir -p "$HOME/bin"
+printf '#!/bin/bash\nsleep 5\necho "{}"\necho 200\n' > "$HOME/bin/curl"
+chmod +x "$HOME/bin/curl"
+sed -i 's/^harm_timeout:.*/harm_timeout: 1/' "$HOME/.clam/config"
+start_seconds=$SECONDS
+result=$(CLAM_OPENAI_API_KEY=test-key detect_command_harm "make deploy" 2>/dev/null)
+elapsed=$((SECONDS - start_seconds))
+if [ "$elapsed" -lt 3 ] && [ "$(echo "$result" | jq -r '.is_harmful')" == "false" ] &&
+   [[ "$(echo "$result" | jq -r '.explanation')" == *"timed out"* ]]; then
+    pass "Default returned after ${elapsed}s instead of waiting 5s"
+else
+    fail "Took ${elapsed}s, result: $result"
+fi
+
+if [ "$(get_harm_timeout_count)" -eq 1 ]; then
+    pass "Timeout recorded in stats"
+else
+    fail "Expected 1 recorded timeout, got $(get_harm_timeout_count)"
+fi
+
+printf '#!/bin/bash\nexit 7\n' > "$HOME/bin/curl"
+result=$(CLAM_OPENAI_API_KEY=test-key detect_command_harm "make install" 2>&1 >/dev/null)
+verdict=$(CLAM_OPENAI_API_KEY=test-key detect_command_harm "make install" 2>/dev/null)
+if [ "$(get_harm_timeout_count)" -eq 1 ] && [[ "$result" != *"timed out"* ]] &&
+   [ "$(echo "$verdict" | jq -r '.explanation')" == "API unavailable - defaulting to safe" ]; then
+    pass "A refused connection is reported as a network failure, not a timeout"
+else
+    fail "Connection failure counted as a timeout: $result $verdict"
+fi
+rm -rf "$HOME/.clam/circuit"
+echo
+
+echo "Test 13: Configured max_tokens reach each payload"
+echo "---"
+fep_tokens=$(CLAM_PROVIDER=openai CLAM_FEP_MAX_TOKENS=2000 build_fep_payload "fix it" | jq -r '.max_tokens')
+harm_tokens=$(CLAM_PROVIDER=anthropic build_harm_detection_payload "ls" | jq -r '.max_tokens')
+ollama_tokens=$(CLAM_PROVIDER=ollama CLAM_COMPLETION_MAX_TOKENS=300 build_completion_payload "git st" | jq -c '.options')
+if [ "$fep_tokens" == "2000" ] && [ "$harm_tokens" == "512" ] && [ "$ollama_tokens" == '{"temperature":0,"num_predict":300}' ]; then
+    pass "fep 2000, default harm 512, Ollama num_predict 300"
+else
+    fail "Unexpected limits: fep=$fep_tokens harm=$harm_tokens ollama=$ollama_tokens"
+fi
+
+if [ "$(CLAM_FEP_MAX_TOKENS=999999 get_max_tokens fep)" == "8192" ] && [ "$(CLAM_HARM_MAX_TOKENS=lots get_max_tokens harm)" == "512" ]; then
+    pass "Out-of-range values clamped, invalid values use the default"
+else
+    fail "Clamping failed"
+fi
+echo
+
+echo "Test 14: Completion failures exit with a status per error kind"
+echo "---"
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY="" get_completion "git st" > /dev/null 2>&1
+no_key_status=$?
+use_fake_curl '{"error":{"message":"Incorrect API key provided"}}' 401
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=bad-key get_completion "git st" > /dev/null 2>&1
+auth_status=$?
+use_fake_curl '{"error":{"message":"Rate limit reached"}}' 429
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key get_completion "git st" > /dev/null 2>&1
+rate_status=$?
+if [ "$no_key_status" -eq "$CLAM_EXIT_CONFIG" ] && [ "$auth_status" -eq "$CLAM_EXIT_AUTH" ] &&
+   [ "$rate_status" -eq "$CLAM_EXIT_RATE_LIMIT" ]; then
+    pass "Missing key, 401 and 429 exit with $no_key_status, $auth_status and $rate_status"
+else
+    fail "Got statuses: no key=$no_key_status auth=$auth_status rate limit=$rate_status"
+fi
+
+if [ "$(api_error_exit_code 000)" -eq "$CLAM_EXIT_NETWORK" ] && [ "$(api_error_exit_code 502)" -eq "$CLAM_EXIT_PROVIDER" ]; then
+    pass "No response maps to network, 502 to provider"
+else
+    fail "Unexpected mapping for 000/502"
+fi
+echo
+
+echo "Test 15: Reasoning models get their own payload shape"
+echo "---"
+payload=$(CLAM_PROVIDER=openai CLAM_MODEL=o1-mini build_completion_payload "git st")
+if [ "$(echo "$payload" | jq 'has("temperature") or has("max_tokens")')" == "false" ] &&
+   [ "$(echo "$payload" | jq 'has("tools") or has("tool_choice") or has("response_format")')" == "false" ] &&
+   [ "$(echo "$payload" | jq -r '.max_completion_tokens')" == "5120" ] &&
+   [ "$(echo "$payload" | jq -c '[.messages[].role]')" == '["user"]' ] &&
+   [[ "$(echo "$payload" | jq -r '.messages[0].content')" == "You are a helpful bash_completion script."*"User command: \`git st\`"* ]]; then
+    pass "o1-mini: no temperature, tools or response_format, 4096 extra tokens for reasoning, system prompt folded into the user message"
+else
+    fail "Unexpected o1-mini payload: $(echo "$payload" | jq -c 'del(.messages)')"
+fi
+
+o1_payload=$(CLAM_PROVIDER=openai CLAM_MODEL=o1 build_completion_payload "git st")
+suggestions=$(CLAM_PROVIDER=openai parse_completion_response \
+    '{"choices":[{"message":{"content":"Here you go:\n{\"suggestions\":[{\"command\":\"git status\",\"explanation\":\"Show status\"}]}"}}]}')
+if [ "$(echo "$o1_payload" | jq 'has("tools") and has("tool_choice")')" == "true" ] &&
+   [ "$suggestions" == "git status|||Show status" ]; then
+    pass "o1 keeps its tools; a plain-text JSON reply is parsed without them"
+else
+    fail "Unexpected o1 payload or parse: $(echo "$o1_payload" | jq -c 'keys') / $suggestions"
+fi
+
+explain_payload=$(CLAM_PROVIDER=openai CLAM_MODEL=o3-mini build_explain_payload "rm -rf build")
+ping_payload=$(CLAM_PROVIDER=openai CLAM_MODEL=o1 build_ping_payload)
+recap_tokens=$(CLAM_PROVIDER=anthropic build_recap_payload "0 ls" | jq -r '.max_tokens')
+if [ "$(echo "$explain_payload" | jq -c '[has("temperature"), has("max_tokens"), .max_completion_tokens, [.messages[].role]]')" == '[false,false,4608,["user"]]' ] &&
+   [ "$(echo "$ping_payload" | jq -c '[has("temperature"), .max_completion_tokens]')" == '[false,1]' ] &&
+   [ "$recap_tokens" == "512" ]; then
+    pass "explain and clam test use the reasoning shape; Anthropic recap keeps its 512 limit"
+else
+    fail "Unexpected payloads: explain=$(echo "$explain_payload" | jq -c 'del(.messages)') ping=$ping_payload recap=$recap_tokens"
+fi
+
+payload=$(CLAM_PROVIDER=openai CLAM_MODEL=gpt-4o-mini build_fep_payload "fix it")
+if [ "$(echo "$payload" | jq -c '[has("temperature"), .max_tokens, (.messages | length)]')" == '[true,1024,2]' ]; then
+    pass "gpt-4o-mini payload unchanged"
+else
+    fail "Regular model payload altered: $(echo "$payload" | jq -c 'del(.messages)')"
+fi
+echo
+
+echo "Test 16: Ollama payloads keep the model loaded and cold starts get more time"
+echo "---"
+configured=$(CLAM_PROVIDER=ollama CLAM_OLLAMA_KEEP_ALIVE=10m build_completion_payload "git st" | jq -c '.keep_alive')
+default=$(CLAM_PROVIDER=ollama CLAM_OLLAMA_KEEP_ALIVE="" build_harm_detection_payload "ls" | jq -c '.keep_alive')
+seconds=$(CLAM_PROVIDER=ollama CLAM_OLLAMA_KEEP_ALIVE=300 build_fep_payload "fix it" | jq -c '.keep_alive')
+other=$(CLAM_PROVIDER=openai CLAM_OLLAMA_KEEP_ALIVE=10m build_completion_payload "git st" | jq -c 'has("keep_alive")')
+if [ "$configured" == '"10m"' ] && [ "$default" == '"5m"' ] && [ "$seconds" == "300" ] && [ "$other" == "false" ]; then
+    pass "keep_alive 10m, default 5m, plain seconds as a number, absent for OpenAI"
+else
+    fail "Unexpected keep_alive: configured=$configured default=$default seconds=$seconds openai=$other"
+fi
+
+rm -f "$HOME/.clam/ollama_last_request"
+cold=$(CLAM_OLLAMA_KEEP_ALIVE=5m ollama_request_timeout 30)
+touch "$HOME/.clam/ollama_last_request"
+warm=$(CLAM_OLLAMA_KEEP_ALIVE=5m ollama_request_timeout 30)
+touch -d "-10 minutes" "$HOME/.clam/ollama_last_request"
+expired=$(CLAM_OLLAMA_KEEP_ALIVE=5m ollama_request_timeout 30)
+forever=$(CLAM_OLLAMA_KEEP_ALIVE=-1 ollama_request_timeout 30)
+if [ "$cold" == "90" ] && [ "$warm" == "30" ] && [ "$expired" == "90" ] && [ "$forever" == "30" ] &&
+   [ "$(ollama_request_timeout 300)" == "600" ]; then
+    pass "Cold model gets 3x (capped at 600s); warm within keep_alive keeps the timeout"
+else
+    fail "Unexpected timeouts: cold=$cold warm=$warm expired=$expired forever=$forever"
+fi
+
+if validate_config_value ollama_keep_alive 1h > /dev/null && ! validate_config_value ollama_keep_alive soon > /dev/null; then
+    pass "ollama_keep_alive accepts 1h and rejects 'soon'"
+else
+    fail "ollama_keep_alive validation wrong"
+fi
+echo
+
+echo "Test 17: Every feature sends through send_chat for each provider"
+echo "---"
+eval "original_send_chat() $(declare -f send_chat | tail -n +2)"
+send_chat() {
+    echo "$CLAM_PROVIDER:$1" >> "$HOME/send_chat_calls"
+    [[ "$CLAM_PROVIDER" == "anthropic" ]] &&
+        echo "$1 $(echo "$2" | jq -c '[(.system | type), [.messages[].role]]')" >> "$HOME/anthropic_shapes"
+    original_send_chat "$@"
+}
+rm -f "$HOME/send_chat_calls" "$HOME/anthropic_shapes"
+rm -rf "$HOME/.clam/circuit"
+sed -i 's/^harm_cache_size:.*/harm_cache_size: 0/' "$HOME/.clam/config"
+use_fake_curl '{}'
+for provider in openai anthropic ollama; do
+    sed -i "s/^provider:.*/provider: $provider/" "$HOME/.clam/config"
+    export CLAM_OPENAI_API_KEY=test-key CLAM_ANTHROPIC_API_KEY=test-key
+    load_config
+    get_completion "make deploy" > /dev/null 2>&1
+    get_fep_completion "fix it" > /dev/null 2>&1
+    detect_command_harm "make deploy" > /dev/null 2>&1
+    get_command_explanation "make deploy" > /dev/null 2>&1
+done
+unset -f send_chat
+eval "send_chat() $(declare -f original_send_chat | tail -n +2)"
+# The empty replies never parse, so FEP and harm detection each retry once.
+expected=""
+for provider in openai anthropic ollama; do
+    expected+="$provider:Completion request"$'\n'"$provider:FEP request"$'\n'"$provider:FEP request"$'\n'
+    expected+="$provider:Harm detection"$'\n'"$provider:Harm detection"$'\n'"$provider:Explain request"$'\n'
+done
+if [ "$(cat "$HOME/send_chat_calls")" == "${expected%$'\n'}" ]; then
+    pass "Completion, FEP, harm and explain route through send_chat for OpenAI, Anthropic and Ollama"
+else
+    fail "Unexpected send_chat calls: $(tr '\n' ',' < "$HOME/send_chat_calls")"
+fi
+
+if grep -q '^FEP request' "$HOME/anthropic_shapes" &&
+   [ "$(grep -vc ' \["string",\["user"\]\]$' "$HOME/anthropic_shapes")" -eq 0 ]; then
+    pass "Anthropic payloads, FEP included, carry the system prompt at the top level"
+else
+    fail "Unexpected Anthropic payload shapes: $(tr '\n' ',' < "$HOME/anthropic_shapes")"
+fi
+
+use_fake_curl '{"error":{"message":"Incorrect API key provided"}}' 401
+error=$(CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=bad-key send_chat "Recap request" '{}' 5 2>&1 >/dev/null)
+status=$?
+if [ "$status" -eq "$CLAM_EXIT_AUTH" ] && [[ "$error" == *"Recap request failed. Unauthorized"*"Incorrect API key provided"* ]] &&
+   [ "$(grep -c -- '-m' "$HOME/curl_args")" -eq 1 ]; then
+    pass "A failure is labelled, carries the provider error and is not retried by default"
+else
+    fail "Unexpected failure (status $status): $error"
+fi
+
+openai_usage=$(CLAM_PROVIDER=openai extract_usage '{"usage":{"prompt_tokens":12,"completion_tokens":3}}')
+anthropic_usage=$(CLAM_PROVIDER=anthropic extract_usage '{"usage":{"input_tokens":7,"output_tokens":2}}')
+ollama_usage=$(CLAM_PROVIDER=ollama extract_usage '{"message":{"content":"hi"}}')
+if [ "$openai_usage" == '{"prompt_tokens":12,"completion_tokens":3}' ] &&
+   [ "$anthropic_usage" == '{"prompt_tokens":7,"completion_tokens":2}' ] &&
+   [ "$ollama_usage" == '{"prompt_tokens":null,"completion_tokens":null}' ]; then
+    pass "Usage is read per provider and null when not reported"
+else
+    fail "Unexpected usage: openai=$openai_usage anthropic=$anthropic_usage ollama=$ollama_usage"
+fi
+echo
+
+echo "Test 18: A failing provider is paused after repeated failures and retried after the cooldown"
+echo "---"
+rm -rf "$HOME/.clam/circuit"
+use_fake_curl '{"error":{"message":"Incorrect API key provided"}}' 401
+for attempt in 1 2 3; do
+    CLAM_PROVIDER=openai CLAM_CIRCUIT_BREAKER_THRESHOLD=3 send_chat "Completion request" '{}' 5 > /dev/null 2>&1
+done
+state=$(CLAM_PROVIDER=openai CLAM_CIRCUIT_BREAKER_COOLDOWN=30 get_circuit_state)
+rm -f "$HOME/curl_args"
+error=$(CLAM_PROVIDER=openai CLAM_CIRCUIT_BREAKER_THRESHOLD=3 send_chat "Completion request" '{}' 5 2>&1 >/dev/null)
+status=$?
+if [ "$(echo "$state" | jq -r '.state')" == "open" ] && [ "$(echo "$state" | jq -r '.failures')" == "3" ] &&
+   [[ "$(echo "$state" | jq -r '.retry_in')" -gt 0 ]] && [ ! -f "$HOME/curl_args" ] &&
+   [ "$status" -eq "$CLAM_EXIT_AUTH" ] && [[ "$error" == *"temporarily unavailable"*"Incorrect API key provided"* ]]; then
+    pass "Three failures open the circuit; the next request is refused without calling the API"
+else
+    fail "Circuit not open: state=$state status=$status error=$error"
+fi
+
+if [ "$(CLAM_PROVIDER=anthropic get_circuit_state | jq -r '.state')" == "closed" ] &&
+   [ "$(CLAM_PROVIDER=openai CLAM_CIRCUIT_BREAKER_THRESHOLD=0 get_circuit_state | jq -r '.state')" == "closed" ]; then
+    pass "Other providers are unaffected and a threshold of 0 never opens"
+else
+    fail "Circuit state leaked across providers or ignored the threshold"
+fi
+
+circuit_file=$(CLAM_PROVIDER=openai get_circuit_file)
+jq -c '.opened_at -= 60' "$circuit_file" > "$circuit_file.tmp" && mv "$circuit_file.tmp" "$circuit_file"
+half_open=$(CLAM_PROVIDER=openai CLAM_CIRCUIT_BREAKER_COOLDOWN=30 get_circuit_state | jq -r '.state')
+CLAM_PROVIDER=openai send_chat "Completion request" '{}' 5 > /dev/null 2>&1
+reopened=$(CLAM_PROVIDER=openai get_circuit_state | jq -r '.state')
+jq -c '.opened_at -= 60' "$circuit_file" > "$circuit_file.tmp" && mv "$circuit_file.tmp" "$circuit_file"
+use_fake_curl '{"choices":[{"message":{"content":"ok"}}]}'
+body=$(CLAM_PROVIDER=openai send_chat "Completion request" '{}' 5 2>/dev/null)
+if [ "$half_open" == "half_open" ] && [ "$reopened" == "open" ] && [ -n "$body" ] &&
+   [ "$(CLAM_PROVIDER=openai get_circuit_state | jq -c '[.state, .failures]')" == '["closed",0]' ]; then
+    pass "After the cooldown a failed probe reopens it and a successful one closes it"
+else
+    fail "Unexpected recovery: half_open=$half_open reopened=$reopened now=$(CLAM_PROVIDER=openai get_circuit_state)"
+fi
+
+CLAM_PROVIDER=openai record_circuit_result 401 "Unauthorized"
+set_config_value "openai_api_key" "sk-new-key" > /dev/null 2>&1
+if [ ! -f "$circuit_file" ]; then
+    pass "Setting the provider's API key clears its circuit"
+else
+    fail "Circuit still recorded after a new API key: $(cat "$circuit_file")"
+fi
+
+CLAM_PROVIDER=openai record_circuit_result 500 "Internal server error"
+enable_safeguards
+use_fake_curl '{"choices":[{"message":{"content":"ok"}}]}'
+body=$(CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key send_chat "Completion request" '{}' 5 2>/dev/null)
+disable_safeguards
+if [ "$body" == '{"choices":[{"message":{"content":"ok"}}]}' ] && [ ! -f "$circuit_file" ] &&
+   [ "$(grep -c -- '-m' "$HOME/curl_args")" -eq 1 ]; then
+    pass "Clearing the circuit with safeguards on sends no harm check and keeps the reply intact"
+else
+    fail "Safeguards intercepted clam's own rm: body=$body curl calls=$(grep -c -- '-m' "$HOME/curl_args")"
+fi
+echo
+
+echo "Test 19: debug_log_llm records redacted requests without the API key"
+echo "---"
+debug_log="$HOME/.clam/llm_debug.log"
+rm -rf "$HOME/.clam/circuit" "$debug_log"
+use_fake_curl '{"choices":[{"message":{"content":"{\"summary\":\"Lists files\"}"}}],"usage":{"prompt_tokens":40,"completion_tokens":5}}'
+CLAM_PROVIDER=openai CLAM_DEBUG_LOG_LLM=false CLAM_ACTIVE_API_KEY=sk-secret-debug-key \
+    send_chat "Explain request" "$(CLAM_PROVIDER=openai build_explain_payload "ls")" 5 > /dev/null 2>&1
+off_logged=$([ -f "$debug_log" ] && echo yes || echo no)
+CLAM_PROVIDER=openai CLAM_MODEL=gpt-4o CLAM_DEBUG_LOG_LLM=true CLAM_ACTIVE_API_KEY=sk-secret-debug-key \
+    send_chat "Explain request" "$(CLAM_PROVIDER=openai build_explain_payload "ls")" 5 > /dev/null 2>&1
+record=$(tail -n 1 "$debug_log" 2>/dev/null)
+if [ "$off_logged" == "no" ] && [ "$(wc -l < "$debug_log")" -eq 1 ] &&
+   [ "$(echo "$record" | jq -c '[.feature, .model, .status, .usage.prompt_tokens]')" == '["Explain request","gpt-4o","200",40]' ] &&
+   [ "$(echo "$record" | jq '.prompt_chars > 0 and .latency_ms >= 0')" == "true" ] &&
+   [[ "$(echo "$record" | jq -r '.response_text')" == *"Lists files"* ]] && ! grep -q "sk-secret-debug-key" "$debug_log"; then
+    pass "One record with model, usage and response text, and no API key"
+else
+    fail "Unexpected debug log: $record"
+fi
+
+use_fake_curl '{"error":{"message":"bad key sk-secret-debug-key"}}' 401
+CLAM_PROVIDER=openai CLAM_DEBUG_LOG_LLM=true CLAM_ACTIVE_API_KEY=sk-secret-debug-key \
+    send_chat "Explain request" '{}' 5 > /dev/null 2>&1
+if [ "$(tail -n 1 "$debug_log" | jq -r '.status')" == "401" ] && ! grep -q "sk-secret-debug-key" "$debug_log"; then
+    pass "A failure is logged with its status and the echoed key stripped"
+else
+    fail "Unexpected failure record: $(tail -n 1 "$debug_log")"
+fi
+
+use_fake_curl '{"choices":[{"message":{"content":"ok"}}]}'
+CLAM_PROVIDER=openai CLAM_DEBUG_LOG_LLM=true CLAM_DEBUG_LOG_MAX_BYTES=10 send_chat "Explain request" '{}' 5 > /dev/null 2>&1
+if [ -f "$debug_log.1" ] && [ "$(wc -l < "$debug_log")" -eq 1 ]; then
+    pass "An oversized log is rotated to llm_debug.log.1"
+else
+    fail "Log not rotated: $(ls "$HOME/.clam")"
+fi
+rm -rf "$HOME/.clam/circuit"
+echo
+
+echo "Test 20: Privacy mode short-circuits every network path"
+echo "---"
+rm -rf "$HOME/.clam/circuit"
+sed -i 's/^provider:.*/provider: openai/' "$HOME/.clam/config"
+export CLAM_OPENAI_API_KEY=test-key
+use_fake_curl '{"choices":[{"message":{"content":"{}"}}]}'
+turned_on=$(toggle_privacy_mode)
+load_config
+completion=$(get_completion "gti status" 2>/dev/null)
+completion_status=$?
+harm=$(detect_command_harm "make deploy" 2>/dev/null)
+get_fep_completion "fix it" > /dev/null 2>&1; fep_status=$?
+explain_error=$(get_command_explanation "make deploy" 2>&1 >/dev/null); explain_status=$?
+generate_command "list files" > /dev/null 2>&1; generate_status=$?
+connection=$(test_connection)
+list_ollama_models > /dev/null 2>&1; ollama_status=$?
+if [ "$turned_on" == "true" ] && grep -q "^privacy_mode: true$" "$HOME/.clam/config" &&
+   [ "$completion_status" -eq 0 ] && [ "$completion" == "git status|||Fixes a typo in the command" ] &&
+   [ "$(echo "$harm" | jq -r '.is_harmful')" == "false" ] &&
+   [ "$fep_status" -ne 0 ] && [ "$explain_status" -ne 0 ] && [[ "$explain_error" == *"privacy mode is on"* ]] &&
+   [ "$generate_status" -ne 0 ] && [ "$ollama_status" -ne 0 ] &&
+   [[ "$(echo "$connection" | jq -r '.reason')" == "Privacy mode is on"* ]] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Completion, harm, fep, explain, generate, clam test and Ollama listing send nothing"
+else
+    fail "A request escaped privacy mode: completion=$completion harm=$harm curl=$(cat "$HOME/curl_args" 2>/dev/null | head -n 3)"
+fi
+
+if ! call_api "https://api.openai.com/v1/chat/completions" '{}' test-key 5 > /dev/null && [ ! -f "$HOME/curl_args" ]; then
+    pass "call_api itself refuses to send in privacy mode"
+else
+    fail "call_api reached curl in privacy mode"
+fi
+
+saved_openai_key="$CLAM_OPENAI_API_KEY"
+unset CLAM_OPENAI_API_KEY CLAM_ACTIVE_API_KEY OPENAI_API_KEY
+sed -i 's/^openai_api_key:.*/openai_api_key:/' "$HOME/.clam/config"
+for command in fep explain generate next recap; do
+    output=$(CLAM_SESSION_COMMANDS=$'0\tls' cmd_$command $command "make deploy" 2>&1 < /dev/null)
+    status=$?
+    if [ "$status" -eq "$CLAM_EXIT_CONFIG" ] && [ "$(echo "$output" | grep -c .)" -eq 1 ] &&
+       [[ "$output" == *"clam $command skipped: privacy mode is on"* ]]; then
+        pass "clam $command without a key prints only the privacy notice"
+    else
+        fail "clam $command without a key (status $status): $output"
+    fi
+done
+
+misjudged=""
+for entry_point in get_fep_completion get_command_explanation generate_command summarize_session; do
+    error=$(CLAM_SESSION_COMMANDS=$'0\tls' $entry_point "make deploy" 2>&1 >/dev/null)
+    status=$?
+    [[ "$status" -eq "$CLAM_EXIT_CONFIG" && "$error" == *"privacy mode is on"* && "$error" != *"API_KEY"* ]] ||
+        misjudged+="[$entry_point: $status $error] "
+done
+echo "ls" > "$CLAM_LAST_OUTPUT_FILE"
+error=$(suggest_next_command 2>&1 >/dev/null)
+status=$?
+[[ "$status" -eq "$CLAM_EXIT_CONFIG" && "$error" == *"privacy mode is on"* ]] || misjudged+="[suggest_next_command: $status $error] "
+command rm -f "$CLAM_LAST_OUTPUT_FILE"
+if [ -z "$misjudged" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Every model entry point reports privacy mode before a missing key"
+else
+    fail "Unexpected privacy handling: $misjudged"
+fi
+export CLAM_OPENAI_API_KEY="$saved_openai_key"
+
+turned_off=$(toggle_privacy_mode)
+load_config
+get_command_explanation "make deploy" > /dev/null 2>&1
+if [ "$turned_off" == "false" ] && [ "$(toggle_privacy_mode off)" == "false" ] && [ -f "$HOME/curl_args" ]; then
+    pass "Toggling again turns it off and requests resume"
+else
+    fail "Privacy mode did not turn off: $turned_off"
+fi
+echo
+
+echo "Test 21: An unparseable structured reply is retried once with a JSON reminder"
+echo "---"
+rm -rf "$HOME/.clam/circuit" "$HOME/.clam/harm_cache"
+use_fake_curl_replies \
+    '{"choices":[{"message":{"content":"This command looks fine to me."}}]}' \
+    '{"choices":[{"message":{"content":"{\"is_harmful\": true, \"explanation\": \"Wipes the disk\"}"}}]}'
+harm=$(CLAM_PROVIDER=groq CLAM_ACTIVE_API_KEY=test-key detect_command_harm "shred /dev/sda" 2>/dev/null)
+if [ "$(echo "$harm" | jq -r '.explanation')" == "Wipes the disk" ] &&
+   [ "$(wc -l < "$HOME/curl_calls")" -eq 2 ] &&
+   [ "$(grep -c 'ONLY valid JSON, no prose' "$HOME/curl_args")" -eq 1 ]; then
+    pass "Harm detection retried with the reminder and used the second verdict"
+else
+    fail "Unexpected harm result: $harm ($(wc -l < "$HOME/curl_calls") calls)"
+fi
+
+use_fake_curl_replies '{"choices":[{"message":{"content":"I am not sure."}}]}'
+harm=$(CLAM_PROVIDER=groq CLAM_ACTIVE_API_KEY=test-key detect_command_harm "shred /dev/sdb" 2>/dev/null)
+if [ "$(echo "$harm" | jq -r '.explanation')" == "Malformed response - defaulting to safe" ] &&
+   [ "$(wc -l < "$HOME/curl_calls")" -eq 2 ]; then
+    pass "Only one retry before falling back to the safe default"
+else
+    fail "Unexpected retry behaviour: $harm ($(wc -l < "$HOME/curl_calls") calls)"
+fi
+
+use_fake_curl_replies \
+    '{"choices":[{"message":{"content":"Try pushing again."}}]}' \
+    '{"choices":[{"message":{"content":"{\"fixes\":[{\"recommended_command\":\"git push origin main\",\"explanation\":\"Typo\"}]}"}}]}'
+response=$(CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key get_fep_completion "" 2>/dev/null)
+fixes=$(parse_fep_fixes "$(extract_message_content "$response")")
+if [ "$(echo "$fixes" | jq -r '.[0].recommended_command')" == "git push origin main" ] &&
+   [ "$(wc -l < "$HOME/curl_calls")" -eq 2 ]; then
+    pass "FEP retried and parsed the fixes from the second reply"
+else
+    fail "Unexpected FEP result: $response"
+fi
+echo
+
+echo "Test 22: respect_system_proxy false stops curl using proxy environment variables"
+echo "---"
+use_fake_curl '{}'
+HTTPS_PROXY=http://env.proxy:3128 CLAM_PROXY_URL="" CLAM_RESPECT_SYSTEM_PROXY=true call_api "https://example.com" "{}" "key" 5 > /dev/null 2>&1
+if ! grep -qx -- "--noproxy" "$HOME/curl_args"; then
+    pass "By default curl is left to pick up HTTPS_PROXY"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+
+use_fake_curl '{}'
+HTTPS_PROXY=http://env.proxy:3128 CLAM_PROXY_URL="" CLAM_RESPECT_SYSTEM_PROXY=false call_api "https://example.com" "{}" "key" 5 > /dev/null 2>&1
+CLAM_PROXY_URL="" CLAM_RESPECT_SYSTEM_PROXY=false call_api_get "http://localhost:11434/api/tags" 5 > /dev/null 2>&1
+if [ "$(grep -x -A1 -- "--noproxy" "$HOME/curl_args" | grep -cx '\*')" -eq 2 ]; then
+    pass "POST and GET requests both pass --noproxy '*'"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+
+use_fake_curl '{}'
+CLAM_PROXY_URL="socks5://proxy.corp:1080" CLAM_RESPECT_SYSTEM_PROXY=false call_api "https://example.com" "{}" "key" 5 > /dev/null 2>&1
+if grep -qx -- "--proxy" "$HOME/curl_args" && ! grep -qx -- "--noproxy" "$HOME/curl_args"; then
+    pass "An explicit proxy_url still wins"
+else
+    fail "curl args: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+
+if ! set_config_value "respect_system_proxy" "sometimes" 2>/dev/null && set_config_value "respect_system_proxy" "false" > /dev/null; then
+    pass "config set accepts only a boolean"
+else
+    fail "respect_system_proxy validation on config set is wrong"
+fi
+set_config_value "respect_system_proxy" "true" > /dev/null
+echo
+
+finish_tests "API Request Tests"
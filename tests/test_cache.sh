@@ -0,0 +1,333 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "Cache Tests"
+echo "================================"
+echo
+
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+export CLAM_OPENAI_API_KEY=test-key
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"is_harmful\":false,\"explanation\":\"ok\"}"}}]}}]}'
+harm_cache_dir="$HOME/.clam/harm_cache"
+
+echo "Test 1: Stats count harm cache entries and their size"
+echo "---"
+for command in "make" "make test" "npm install"; do
+    detect_command_harm "$command" > /dev/null 2>&1
+done
+read -r entries bytes <<< "$(get_cache_stats "$harm_cache_dir" "harm-*.json")"
+if [ "$entries" -eq 3 ] && [ "$bytes" -gt 0 ]; then
+    pass "3 entries using $bytes bytes"
+else
+    fail "Expected 3 entries, got '$entries' ($bytes bytes)"
+fi
+
+if [ "$(get_cache_stats "$HOME/missing" "harm-*.json")" == "0 0" ]; then
+    pass "Missing cache directory reports 0 0"
+else
+    fail "Missing cache directory not reported as empty"
+fi
+echo
+
+echo "Test 2: Clearing one cache leaves the others alone"
+echo "---"
+mkdir -p "$HOME/.clam/cache"
+echo "cached" > "$HOME/.clam/cache/acsh-test.txt"
+echo "y" | cmd_clear clear harm > /dev/null
+if [ "$(get_cache_stats "$harm_cache_dir" "harm-*.json")" == "0 0" ] &&
+   [ -f "$HOME/.clam/cache/acsh-test.txt" ]; then
+    pass "Harm cache emptied, completion cache kept"
+else
+    fail "clam clear harm removed the wrong files"
+fi
+
+echo "y" | cmd_clear clear completion > /dev/null
+if [ ! -f "$HOME/.clam/cache/acsh-test.txt" ]; then
+    pass "Completion cache emptied"
+else
+    fail "Completion cache entry still present"
+fi
+
+if ! cmd_clear clear bogus > /dev/null < /dev/null; then
+    pass "Unknown target rejected"
+else
+    fail "Unknown target accepted"
+fi
+echo
+
+echo "Test 3: Pinned entries survive eviction"
+echo "---"
+cache_dir="$HOME/.clam/cache"
+cache_entry() {
+    echo "$cache_dir/acsh-$(cache_key "$1").txt"
+}
+for i in 1 2 3 4 5; do
+    echo "suggestions $i" > "$(cache_entry "input $i")"
+    touch -d "2024-01-0$i" "$(cache_entry "input $i")"
+done
+pin_cache_entry "input 1"
+pin_cache_entry "input 1"
+CLAM_CACHE_SIZE=3 evict_completion_cache
+if [ -f "$(cache_entry "input 1")" ] && [ ! -f "$(cache_entry "input 2")" ] && [ ! -f "$(cache_entry "input 3")" ] &&
+   [ -f "$(cache_entry "input 5")" ] && [ "$(wc -l < "$cache_dir/pinned")" -eq 1 ]; then
+    pass "Oldest entry kept because it is pinned; next two oldest evicted"
+else
+    fail "Unexpected cache contents: $(ls "$cache_dir" | tr '\n' ' ')"
+fi
+
+unpin_cache_entry "input 1"
+CLAM_CACHE_SIZE=2 evict_completion_cache
+if [ ! -f "$(cache_entry "input 1")" ] && [ "$(list_cache | wc -l)" -eq 2 ]; then
+    pass "Unpinned entry evicted on the next cycle"
+else
+    fail "Unpinned entry still cached"
+fi
+echo
+
+echo "Test 4: Harm results are cached separately per model"
+echo "---"
+harm_reply() {
+    use_fake_curl "{\"choices\":[{\"message\":{\"tool_calls\":[{\"function\":{\"arguments\":\"{\\\"is_harmful\\\":$1,\\\"explanation\\\":\\\"$2\\\"}\"}}]}}]}"
+}
+set_config_value "model" "gpt-4o" > /dev/null
+harm_reply false "safe per gpt-4o"
+first=$(detect_command_harm "chmod 600 key.pem" 2>/dev/null | jq -r '.explanation')
+set_config_value "model" "gpt-4o-mini" > /dev/null
+harm_reply true "risky per gpt-4o-mini"
+second=$(detect_command_harm "chmod 600 key.pem" 2>/dev/null | jq -r '.explanation')
+if [ "$first" == "safe per gpt-4o" ] && [ "$second" == "risky per gpt-4o-mini" ] &&
+   [ "$(get_cache_stats "$harm_cache_dir" "harm-*.json" | cut -d ' ' -f 1)" -eq 2 ]; then
+    pass "Two models produced two independent cache entries"
+else
+    fail "first='$first' second='$second'"
+fi
+
+set_config_value "model" "gpt-4o" > /dev/null
+rm -f "$HOME/curl_args"
+again=$(detect_command_harm "chmod 600 key.pem" 2>/dev/null | jq -r '.explanation')
+if [ "$again" == "safe per gpt-4o" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Switching back reuses the first model's entry without an API call"
+else
+    fail "Unexpected result after switching back: '$again'"
+fi
+echo
+
+echo "Test 5: Cached harm verdicts expire after harm_cache_ttl"
+echo "---"
+harm_reply false "fresh verdict"
+detect_command_harm "tar czf backup.tgz src" > /dev/null 2>&1
+touch -d "-2 minutes" "$harm_cache_dir"/harm-*.json
+harm_reply true "re-checked verdict"
+kept=$(detect_command_harm "tar czf backup.tgz src" 2>/dev/null | jq -r '.explanation')
+sed -i 's/^harm_cache_ttl:.*/harm_cache_ttl: 60/' "$HOME/.clam/config"
+expired=$(detect_command_harm "tar czf backup.tgz src" 2>/dev/null | jq -r '.explanation')
+if [ "$kept" == "fresh verdict" ] && [ "$expired" == "re-checked verdict" ]; then
+    pass "2-minute-old entry kept with ttl 0, re-checked with ttl 60"
+else
+    fail "kept='$kept' expired='$expired'"
+fi
+
+touch -d "-30 seconds" "$HOME/cache_entry"
+if cache_entry_expired "$HOME/cache_entry" 10 && ! cache_entry_expired "$HOME/cache_entry" 60 &&
+   ! cache_entry_expired "$HOME/cache_entry" 0; then
+    pass "cache_entry_expired honours the TTL and treats 0 as never"
+else
+    fail "cache_entry_expired gave the wrong answer"
+fi
+echo
+
+echo "Test 6: harm_cache_size caps the harm cache"
+echo "---"
+rm -f "$harm_cache_dir"/harm-*.json
+sed -i 's/^harm_cache_size:.*/harm_cache_size: 2/' "$HOME/.clam/config"
+harm_reply false "ok"
+for command in "make one" "make two" "make three"; do
+    detect_command_harm "$command" > /dev/null 2>&1
+    sleep 0.01
+done
+if [ "$(get_cache_stats "$harm_cache_dir" "harm-*.json" | cut -d ' ' -f 1)" -eq 2 ] &&
+   [ ! -f "$harm_cache_dir/harm-$(cache_key "make one").json" ]; then
+    pass "Third insert evicted the oldest entry"
+else
+    fail "Unexpected harm cache: $(ls "$harm_cache_dir" | tr '\n' ' ')"
+fi
+
+rm -f "$harm_cache_dir"/harm-*.json
+sed -i 's/^harm_cache_size:.*/harm_cache_size: 0/' "$HOME/.clam/config"
+detect_command_harm "make four" > /dev/null 2>&1
+rm -f "$HOME/curl_args"
+detect_command_harm "make four" > /dev/null 2>&1
+if [ "$(get_cache_stats "$harm_cache_dir" "harm-*.json")" == "0 0" ] && [ -f "$HOME/curl_args" ]; then
+    pass "A cap of 0 keeps nothing and always asks the model"
+else
+    fail "Entries retained with harm_cache_size 0"
+fi
+echo
+
+echo "Test 7: Reused inputs stay cached longer than one-off inputs"
+echo "---"
+hot_hash=$(cache_key "git push origin main")
+cold_hash=$(cache_key "gti pshu")
+echo "suggestions" > "$cache_dir/acsh-$hot_hash.txt"
+echo "suggestions" > "$cache_dir/acsh-$cold_hash.txt"
+for i in 1 2 3; do record_cache_hit "$hot_hash"; done
+touch -d "-100 seconds" "$cache_dir/acsh-$hot_hash.txt" "$cache_dir/acsh-$cold_hash.txt"
+if CLAM_CACHE_TTL=60 completion_cache_expired "$cold_hash" && ! CLAM_CACHE_TTL=60 completion_cache_expired "$hot_hash"; then
+    pass "At 100s with ttl 60: cold key (30s) expired, hot key (240s) kept"
+else
+    fail "cold hits=$(get_cache_hits "$cold_hash"), hot hits=$(get_cache_hits "$hot_hash")"
+fi
+
+if [ "$(effective_cache_ttl 60 0)" -eq 30 ] && [ "$(effective_cache_ttl 60 1)" -eq 60 ] &&
+   [ "$(effective_cache_ttl 60 20)" -eq 480 ] && [ "$(effective_cache_ttl 0 5)" -eq 0 ]; then
+    pass "TTL halves for one-off keys, doubles per reuse, caps at 8x, 0 stays never"
+else
+    fail "Unexpected effective TTLs"
+fi
+
+echo "suggestions" > "$cache_dir/acsh-$hot_hash.txt"
+record_cache_entry "$hot_hash"
+sed -i "s/^$hot_hash 0 .*/$hot_hash 0 $(( $(date +%s) - 100 ))/" "$cache_dir/hits"
+for i in 1 2 3; do
+    record_cache_hit "$hot_hash"
+    touch "$cache_dir/acsh-$hot_hash.txt"
+done
+if CLAM_CACHE_TTL=20 completion_cache_expired "$hot_hash" && [ "$(get_cache_hits "$hot_hash")" -eq 3 ]; then
+    pass "A key hit on every use still expires its TTL after it was written"
+else
+    fail "Repeatedly-hit entry kept past its TTL (created $(get_cache_created "$hot_hash"))"
+fi
+
+sed -i "s/^$hot_hash .*/$hot_hash 20 $(( $(date +%s) - 70 ))/" "$cache_dir/hits"
+touch "$cache_dir/acsh-$hot_hash.txt"
+CLAM_CACHE_TTL=10 completion_cache_expired "$hot_hash" && capped_early=true || capped_early=false
+sed -i "s/^$hot_hash .*/$hot_hash 20 $(( $(date +%s) - 90 ))/" "$cache_dir/hits"
+touch "$cache_dir/acsh-$hot_hash.txt"
+if [ "$capped_early" == false ] && CLAM_CACHE_TTL=10 completion_cache_expired "$hot_hash"; then
+    pass "With ttl 10 a key hit 20 times is kept at 70s and expires at 90s, past the 80s cap"
+else
+    fail "Hot key lifetime not capped at 8x ttl (expired early: $capped_early)"
+fi
+
+sed -i "/^$hot_hash /d" "$cache_dir/hits"
+touch -d "-100 seconds" "$cache_dir/acsh-$hot_hash.txt"
+record_cache_hit "$hot_hash"
+touch "$cache_dir/acsh-$hot_hash.txt"
+if CLAM_CACHE_TTL=10 completion_cache_expired "$hot_hash"; then
+    pass "An entry without a recorded write time keeps its age from before the first hit"
+else
+    fail "First hit reset the age of an entry with no write time: $(grep "^$hot_hash " "$cache_dir/hits")"
+fi
+
+rm "$cache_dir/acsh-$hot_hash.txt"
+CLAM_CACHE_SIZE=100 evict_completion_cache
+if [ "$(get_cache_hits "$hot_hash")" -eq 0 ]; then
+    pass "Hit counts of removed entries are pruned"
+else
+    fail "Stale hit count kept for a removed entry"
+fi
+echo
+
+echo "Test 8: Distinct commands never share a harm verdict"
+echo "---"
+rm -f "$harm_cache_dir"/harm-*.json
+sed -i 's/^harm_cache_size:.*/harm_cache_size: 100/' "$HOME/.clam/config"
+harm_reply true "deletes everything"
+detect_command_harm "rm -rf build" > /dev/null 2>&1
+harm_reply false "ok"
+detect_command_harm "rm -rf build/tmp" > /dev/null 2>&1
+if [ "$(get_cache_stats "$harm_cache_dir" "harm-*.json" | cut -d ' ' -f 1)" -eq 2 ] &&
+   [ "$(detect_command_harm "rm -rf build" 2>/dev/null | jq -r '.is_harmful')" == "true" ] &&
+   [ "$(detect_command_harm "rm -rf build/tmp" 2>/dev/null | jq -r '.is_harmful')" == "false" ]; then
+    pass "Each command keeps its own entry and verdict"
+else
+    fail "Unexpected harm cache: $(ls "$harm_cache_dir" | tr '\n' ' ')"
+fi
+
+# Simulate a collision: another command's verdict stored under this key.
+cp "$harm_cache_dir/harm-$(cache_key "rm -rf build").json" \
+   "$harm_cache_dir/harm-$(cache_key "rm -rf dist").json"
+rm -f "$HOME/curl_args"
+result=$(detect_command_harm "rm -rf dist" 2>/dev/null)
+if [ "$(echo "$result" | jq -r '.explanation')" == "ok" ] && [ -f "$HOME/curl_args" ]; then
+    pass "An entry for a different command is ignored and re-checked"
+else
+    fail "Reused another command's verdict: $result"
+fi
+echo
+
+echo "Test 9: Cache keys are pinned for known inputs"
+echo "---"
+# Changing these invalidates every persisted cache entry. Update them only
+# when that is intended.
+if [ "$(cache_key "git status" openai gpt-4o)" == "a9ebce568193beff24c5a1a7dc73efd3fa144987e82caab9572b8ba570177cbc" ] &&
+   [ "$(cache_key "git status" anthropic claude-3-5-sonnet-20241022)" == "a3cd0e060a547895021f62eb1887a7cc49da53bec90b76bf7499e8d360982c57" ] &&
+   [ "$(cache_key "rm -rf build" ollama codellama)" == "a88ee6d3d9026cc0caae2b6965bca5acf866f76558ca912c1b6ecf4e43cd4142" ]; then
+    pass "Known inputs hash to their pinned keys"
+else
+    fail "Cache key derivation changed: $(cache_key "git status" openai gpt-4o)"
+fi
+
+if [ "$(CLAM_PROVIDER=ollama CLAM_MODEL=codellama cache_key "rm -rf build")" == "$(cache_key "rm -rf build" ollama codellama)" ] &&
+   [ "$(cache_key "git status" openai gpt-4o)" != "$(cache_key "git status" openai gpt-4o-mini)" ]; then
+    pass "Provider and model default to the active config and change the key"
+else
+    fail "Provider or model not reflected in the cache key"
+fi
+echo
+
+echo "Test 10: A cancelled suggestion request returns at once and caches nothing"
+echo "---"
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[{\"command\":\"make deploy\",\"explanation\":\"Deploy\"}]}"}}]}}]}'
+sed -i '2a sleep 10' "$HOME/bin/curl"
+rm -f "$cache_dir"/acsh-*.txt "$HOME/.clam/latency.log"
+started=$(date +%s)
+(
+    READLINE_LINE="make deploy"
+    interactive_clam_widget > "$HOME/widget_output" 2>&1
+) &
+widget_pid=$!
+for i in $(seq 1 50); do
+    [ -f "$HOME/.clam/run/suggestion.pid" ] && grep -q . "$HOME/curl_args" 2>/dev/null && break
+    sleep 0.1
+done
+cancel_output=$(cmd_cancel suggestion)
+wait "$widget_pid"
+elapsed=$(( $(date +%s) - started ))
+if [ "$elapsed" -lt 5 ] && [[ "$cancel_output" == *"Cancelled the suggestion request"* ]] &&
+   grep -q "Cancelled." "$HOME/widget_output" && [ -z "$(ls "$cache_dir"/acsh-*.txt 2>/dev/null)" ] &&
+   [ ! -f "$HOME/.clam/latency.log" ] && ! pgrep -f "$HOME/bin/curl" > /dev/null; then
+    pass "Returned after ${elapsed}s with no cache entry, no latency record and no curl left running"
+else
+    fail "elapsed=${elapsed}s cancel=$cancel_output output=$(cat "$HOME/widget_output") cache=$(ls "$cache_dir")"
+fi
+
+if [ "$(cmd_cancel)" == "No request in flight" ] && [ ! -f "$HOME/.clam/run/suggestion.pid" ]; then
+    pass "Nothing to cancel once the request is gone"
+else
+    fail "Stale cancellation token left behind"
+fi
+
+result=$(run_cancellable fep echo "fixed")
+status=$?
+if [ "$status" -eq 0 ] && [ "$result" == "fixed" ] && [ ! -f "$HOME/.clam/run/fep.pid" ]; then
+    pass "An uncancelled request passes its output and status through"
+else
+    fail "Unexpected run_cancellable result (status $status): $result"
+fi
+
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"is_harmful\":true,\"explanation\":\"Deletes files\"}"}}]}}]}'
+enable_safeguards
+result=$(run_cancellable suggestion echo "make deploy" < /dev/null 2>&1)
+disable_safeguards
+if [ "$result" == "make deploy" ] && [ ! -f "$HOME/curl_args" ] && [ ! -f "$HOME/.clam/run/suggestion.pid" ]; then
+    pass "With safeguards on, cleaning up the token and output files asks no harm check"
+else
+    fail "Safeguards intercepted run_cancellable's cleanup: $result"
+fi
+echo
+
+finish_tests "Cache Tests"
@@ -0,0 +1,140 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "Completion Parsing Tests"
+echo "================================"
+echo
+
+echo "Test 1: Delimited suggestion splits into command and explanation"
+echo "---"
+{ IFS= read -r command; IFS= read -r explanation; } < <(parse_suggestion_line "ls -lh|||List files with human-readable sizes")
+if [ "$command" == "ls -lh" ] && [ "$explanation" == "List files with human-readable sizes" ]; then
+    pass "Command and explanation parsed"
+else
+    fail "Got command='$command' explanation='$explanation'"
+fi
+echo
+
+echo "Test 2: Suggestion without a delimiter is a bare command"
+echo "---"
+{ IFS= read -r command; IFS= read -r explanation; } < <(parse_suggestion_line "git status --short")
+if [ "$command" == "git status --short" ] && [ -z "$explanation" ]; then
+    pass "Whole line treated as the command"
+else
+    fail "Got command='$command' explanation='$explanation'"
+fi
+echo
+
+echo "Test 3: Long explanations are capped"
+echo "---"
+long_explanation=$(printf 'x%.0s' {1..200})
+{ IFS= read -r command; IFS= read -r explanation; } < <(CLAM_MAX_EXPLANATION_LENGTH=40 parse_suggestion_line "du -sh *|||$long_explanation")
+if [ "${#explanation}" -eq 40 ] && [[ "$explanation" == *"..." ]]; then
+    pass "Explanation truncated to 40 characters"
+else
+    fail "Explanation length ${#explanation}: $explanation"
+fi
+echo
+
+echo "Test 4: Mistral responses parse like other OpenAI-compatible JSON content"
+echo "---"
+mistral_body='{"id":"cmpl-1","object":"chat.completion","model":"mistral-small-latest","choices":[{"index":0,"message":{"role":"assistant","content":"{\"suggestions\":[{\"command\":\"ls -la\",\"explanation\":\"List all files\"},{\"command\":\"ls -lS\",\"explanation\":\"Sort by size\"}]}"},"finish_reason":"stop"}],"usage":{"prompt_tokens":120,"completion_tokens":30,"total_tokens":150}}'
+completions=$(CLAM_PROVIDER=mistral parse_completion_response "$mistral_body")
+if [ "$completions" == $'ls -la|||List all files\nls -lS|||Sort by size' ]; then
+    pass "Mistral suggestions parsed"
+else
+    fail "Unexpected completions: $completions"
+fi
+
+model=$(echo "${CLAM_MODELS["mistral:	mistral-large-latest"]}" | jq -r '.provider + " " + .endpoint')
+if [ "$model" == "mistral https://api.mistral.ai/v1/chat/completions" ]; then
+    pass "mistral-large-latest registered with the Mistral endpoint"
+else
+    fail "Unexpected model entry: $model"
+fi
+echo
+
+echo "Test 5: Inputs shorter than min_input_len skip the model call"
+echo "---"
+export CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[{\"command\":\"ls -la\",\"explanation\":\"List all\"}]}"}}]}}]}'
+suggestions=$(CLAM_MIN_INPUT_LEN=4 get_completion "ls" 2>/dev/null)
+if [ -z "$suggestions" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "'ls' suppressed with min_input_len=4"
+else
+    fail "'ls' reached the model: $suggestions"
+fi
+
+suggestions=$(CLAM_MIN_INPUT_LEN=4 get_completion "ls -l" 2>/dev/null)
+if [ "$suggestions" == "ls -la|||List all" ] && [ -f "$HOME/curl_args" ]; then
+    pass "'ls -l' still completed"
+else
+    fail "'ls -l' not completed: $suggestions"
+fi
+
+rm -f "$HOME/curl_args"
+if [ -z "$(get_completion "g" 2>/dev/null)" ] && [ ! -f "$HOME/curl_args" ] && meets_min_input_length "gs"; then
+    pass "By default a single character is too short and two are enough"
+else
+    fail "Default minimum is not 2"
+fi
+echo
+
+echo "Test 6: Obvious typos are corrected locally"
+echo "---"
+if [ "$(correct_typo "gti status")" == "git status" ] && [ "$(correct_typo "grpe -r TODO")" == "grep -r TODO" ] &&
+   [ "$(correct_typo "sl")" == "ls" ] && [ "$(correct_typo "cd..")" == "cd .." ]; then
+    pass "gti, grpe, sl and cd.. corrected"
+else
+    fail "Unexpected corrections: $(correct_typo "gti status") / $(correct_typo "grpe -r TODO") / $(correct_typo "sl")"
+fi
+
+if ! correct_typo "git status" > /dev/null && ! correct_typo "awk '{print}'" > /dev/null && ! correct_typo "qqq" > /dev/null; then
+    pass "Real commands and unrecognisable words are left alone"
+else
+    fail "A legitimate command was corrected"
+fi
+
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[{\"command\":\"git status\",\"explanation\":\"Show status\"},{\"command\":\"git stash\",\"explanation\":\"Stash changes\"}]}"}}]}}]}'
+suggestions=$(get_completion "gti status" 2>/dev/null)
+if [ "$suggestions" == $'git status|||Fixes a typo in the command\ngit stash|||Stash changes' ]; then
+    pass "Correction offered first and not repeated from the model"
+else
+    fail "Unexpected suggestions: $suggestions"
+fi
+
+suggestions=$(CLAM_ACTIVE_API_KEY="" get_completion "gti status" 2>/dev/null)
+if [ "$suggestions" == "git status|||Fixes a typo in the command" ]; then
+    pass "Correction still offered when the model is unavailable"
+else
+    fail "Correction missing without an API key: $suggestions"
+fi
+echo
+
+echo "Test 7: The mock provider answers every feature offline"
+echo "---"
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+sed -i 's/^provider:.*/provider: mock/; s/^harm_cache_size:.*/harm_cache_size: 0/' "$HOME/.clam/config"
+load_config
+use_fake_curl '{}' 500
+suggestions=$(get_completion "make deploy" 2>/dev/null)
+if [ "$suggestions" == "make deploy|||Mock suggestion" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "get_completion returns the canned suggestion without a key or network"
+else
+    fail "Unexpected mock suggestions: $suggestions"
+fi
+
+harmful=$(detect_command_harm "rm -rf build" 2>/dev/null | jq -r '.is_harmful')
+harmless=$(detect_command_harm "make deploy" 2>/dev/null | jq -r '.is_harmful')
+fix=$(extract_message_content "$(get_fep_completion "fix it" 2>/dev/null)" | jq -r '.fixes[0].recommended_command')
+if [ "$harmful" == "true" ] && [ "$harmless" == "false" ] && [ "$fix" == "echo mock" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Harm checks flag rm and FEP returns a canned fix"
+else
+    fail "Unexpected mock replies: harmful=$harmful harmless=$harmless fix=$fix"
+fi
+echo
+
+finish_tests "Completion Parsing Tests"
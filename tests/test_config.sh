@@ -0,0 +1,420 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+
+echo "================================"
+echo "Configuration Tests"
+echo "================================"
+echo
+
+expect_invalid() {
+    local key="$1"
+    local value="$2"
+    local expected_message="$3"
+    local message
+    if message=$(validate_config_value "$key" "$value"); then
+        fail "$key=$value was accepted"
+    elif [[ "$message" == *"$expected_message"* ]]; then
+        pass "$key=$value rejected: $message"
+    else
+        fail "$key=$value rejected with unexpected message: $message"
+    fi
+}
+
+echo "Test 1: Invalid values are rejected with the specific field error"
+echo "---"
+expect_invalid "temperature" "5.0" "temperature must be a number between 0 and 2"
+expect_invalid "temperature" "warm" "temperature must be a number"
+expect_invalid "model" "" "model must not be empty"
+expect_invalid "provider" "openia" "provider must be one of"
+expect_invalid "cache_size" "-1" "cache_size must be an integer"
+expect_invalid "harm_detection_enabled" "yes" "must be true or false"
+echo
+
+echo "Test 2: Valid values are accepted"
+echo "---"
+if validate_config_value "temperature" "0.7" &&
+   validate_config_value "provider" "anthropic" &&
+   validate_config_value "cache_size" "0" &&
+   validate_config_value "openai_api_key" "anything-goes"; then
+    pass "temperature=0.7, provider=anthropic, cache_size=0 and unruled keys accepted"
+else
+    fail "A valid value was rejected"
+fi
+echo
+
+echo "Test 3: config set refuses invalid values and leaves the file untouched"
+echo "---"
+if ! set_config_value "temperature" "5.0" 2>/dev/null && grep -q "^temperature: 0.0$" "$HOME/.clam/config"; then
+    pass "temperature stays 0.0 after rejected update"
+else
+    fail "Invalid temperature was written"
+fi
+
+if set_config_value "temperature" "0.5" > /dev/null && grep -q "^temperature: 0.5$" "$HOME/.clam/config"; then
+    pass "temperature updated to 0.5"
+else
+    fail "Valid temperature not written"
+fi
+echo
+
+echo "Test 4: load_config skips invalid values from a hand-edited file"
+echo "---"
+sed -i 's/^temperature:.*/temperature: 9/' "$HOME/.clam/config"
+unset CLAM_TEMPERATURE
+error_output=$(load_config 2>&1 >/dev/null)
+load_config 2>/dev/null
+if [ -z "$CLAM_TEMPERATURE" ] && [[ "$error_output" == *"Ignoring invalid config value: temperature"* ]]; then
+    pass "temperature=9 ignored with a warning"
+else
+    fail "CLAM_TEMPERATURE=$CLAM_TEMPERATURE, output: $error_output"
+fi
+echo
+
+echo "Test 5: Reset restores defaults and keeps stored API keys"
+echo "---"
+unset OPENAI_API_KEY ANTHROPIC_API_KEY
+set_config_value "provider" "anthropic" > /dev/null
+set_config_value "model" "claude-3-5-haiku-latest" > /dev/null
+set_config_value "anthropic_api_key" "sk-ant-kept" > /dev/null
+reset_config > /dev/null
+if grep -q "^provider: openai$" "$HOME/.clam/config" &&
+   grep -q "^model: gpt-4o$" "$HOME/.clam/config" &&
+   grep -q "^anthropic_api_key: sk-ant-kept$" "$HOME/.clam/config"; then
+    pass "provider openai, model gpt-4o, anthropic key preserved"
+else
+    fail "Unexpected config after reset: $(grep -E '^(provider|model|anthropic_api_key):' "$HOME/.clam/config" | tr '\n' ' ')"
+fi
+
+reset_config true > /dev/null
+if grep -q "^anthropic_api_key:$" "$HOME/.clam/config"; then
+    pass "Reset with clear_keys empties the key"
+else
+    fail "Key survived a clearing reset: $(grep '^anthropic_api_key:' "$HOME/.clam/config")"
+fi
+
+set_config_value "provider" "anthropic" > /dev/null
+(
+    mv() { return 1; }
+    reset_config > /dev/null 2>&1
+)
+status=$?
+if [ "$status" -ne 0 ] && grep -q "^provider: anthropic$" "$HOME/.clam/config" &&
+   [ -z "$(ls "$HOME/.clam" | grep '^config\.')" ]; then
+    pass "A failed reset keeps the previous config and leaves no temp file"
+else
+    fail "Failed reset (status $status) lost the config or left $(ls "$HOME/.clam" | grep '^config\.')"
+fi
+
+set_config_value "anthropic_api_key" "sk-ant-kept-1234567890" > /dev/null
+output=$(bash "$script_dir/../clam.sh" config reset 2>&1)
+if echo "$output" | grep -q "CLAM_PROVIDER:.*openai" && echo "$output" | grep -q "CLAM_ANTHROPIC_API_KEY:" &&
+   ! echo "$output" | grep -q "sk-ant-kept-1234567890"; then
+    pass "clam config reset prints the resulting config with keys masked"
+else
+    fail "Unexpected reset output: $output"
+fi
+
+portable_config="$HOME/portable-reset/clam.conf"
+HOME="$HOME/fresh" CLAM_CONFIG_FILE="$portable_config" reset_config > /dev/null 2>&1
+if grep -q "^provider: openai$" "$portable_config" && [ ! -e "$HOME/fresh/.clam" ]; then
+    pass "Reset writes to CLAM_CONFIG_FILE and creates nothing under ~/.clam"
+else
+    fail "Reset ignored CLAM_CONFIG_FILE: $(ls -a "$HOME/fresh" 2>&1)"
+fi
+echo
+
+echo "Test 6: A failed config write keeps the change in memory and warns once"
+echo "---"
+(
+    write_config_value() { return 1; }
+    set_config_value "temperature" "0.5" > /dev/null 2> "$HOME/first_warning"
+    set_config_value "model" "gpt-4o-mini" > /dev/null 2> "$HOME/second_warning"
+    load_config > /dev/null
+    echo "$CLAM_TEMPERATURE $CLAM_MODEL $CLAM_STORAGE_STATUS"
+) > "$HOME/result"
+if [ "$(cat "$HOME/result")" == "0.5 gpt-4o-mini read-only" ] &&
+   grep -q "kept for this session only" "$HOME/first_warning" && [ ! -s "$HOME/second_warning" ]; then
+    pass "In-memory values survive load_config; warning shown only once"
+else
+    fail "Unexpected fallback state: $(cat "$HOME/result"), warnings: $(cat "$HOME/first_warning" "$HOME/second_warning")"
+fi
+
+if ! grep -q "gpt-4o-mini" "$HOME/.clam/config"; then
+    pass "Config file left untouched"
+else
+    fail "Config file was modified despite the failed write"
+fi
+
+(
+    write_config_value() { return 1; }
+    cmd_config config set temperature 0.5 > "$HOME/set_output" 2>&1
+) > /dev/null
+status=$?
+if [ "$status" -ne 0 ] && grep -q "Configuration not saved" "$HOME/set_output" &&
+   ! grep -q "Configuration updated" "$HOME/set_output"; then
+    pass "clam config set reports the unsaved change and fails"
+else
+    fail "Unsaved change reported as success (status $status): $(cat "$HOME/set_output")"
+fi
+echo
+
+echo "Test 7: Switching by model name sets the matching provider and endpoint"
+echo "---"
+export CLAM_ANTHROPIC_API_KEY=sk-ant-test
+cmd_model model claude-3-5-haiku-20241022 > /dev/null 2>&1
+if grep -q "^model: claude-3-5-haiku-20241022$" "$HOME/.clam/config" &&
+   grep -q "^provider: anthropic$" "$HOME/.clam/config" &&
+   grep -q "^endpoint: https://api.anthropic.com/v1/messages$" "$HOME/.clam/config"; then
+    pass "claude-3-5-haiku-20241022 switched provider to anthropic"
+else
+    fail "Unexpected config: $(grep -E '^(provider|model|endpoint):' "$HOME/.clam/config" | tr '\n' ' ')"
+fi
+
+output=$(cmd_model model gpt-5-imaginary 2>&1)
+status=$?
+if [ "$status" -ne 0 ] && [[ "$output" == *"Unknown model 'gpt-5-imaginary'"* ]] &&
+   grep -q "^model: claude-3-5-haiku-20241022$" "$HOME/.clam/config"; then
+    pass "Unknown model rejected and config left unchanged"
+else
+    fail "Unknown model accepted (status $status): $output"
+fi
+
+CLAM_MODELS['groq:		shared-model']='{"model":"shared-model","provider":"groq"}'
+CLAM_MODELS['mistral:	shared-model']='{"model":"shared-model","provider":"mistral"}'
+output=$(cmd_model model shared-model 2>&1)
+if [[ "$output" == *"offered by groq mistral"* ]]; then
+    pass "Ambiguous name asks for a provider"
+else
+    fail "Ambiguous name not reported: $output"
+fi
+
+use_fake_curl '{"models":[{"name":"llama3:8b"}]}'
+cmd_model model gpt-5-imaginary > /dev/null 2>&1
+if [ ! -f "$HOME/curl_args" ]; then
+    pass "Ollama not queried while a hosted provider is selected"
+else
+    fail "Ollama queried for a hosted provider: $(tr '\n' ' ' < "$HOME/curl_args")"
+fi
+
+cmd_model model ollama llama3:8b > /dev/null 2>&1
+if grep -q "^model: llama3:8b$" "$HOME/.clam/config" && grep -q "^provider: ollama$" "$HOME/.clam/config" &&
+   grep -qx -- "--connect-timeout" "$HOME/curl_args"; then
+    pass "Explicit Ollama switch lists pulled models with a short connect timeout"
+else
+    fail "Ollama model not selectable: $(grep -E '^(provider|model):' "$HOME/.clam/config" | tr '\n' ' ')"
+fi
+echo
+
+echo "Test 8: The config schema lists every key and matches the validation rules"
+echo "---"
+schema=$(get_config_schema)
+default_keys=$(default_config_contents | grep -E '^[a-z_]+:' | cut -d ':' -f 1 | sort)
+if echo "$schema" | jq -e . > /dev/null 2>&1 &&
+   [ "$(echo "$schema" | jq -r '.properties | keys[]' | sort)" == "$default_keys" ]; then
+    pass "Schema is valid JSON with all $(echo "$default_keys" | wc -l) config keys"
+else
+    fail "Schema keys differ from the default config"
+fi
+
+missing=""
+for key in "${!CLAM_CONFIG_RULES[@]}"; do
+    echo "$schema" | jq -e --arg key "$key" '.properties | has($key)' > /dev/null || missing+="$key "
+done
+if [ -z "$missing" ] &&
+   [ "$(echo "$schema" | jq -c '.properties.provider.enum')" == '["openai","anthropic","groq","mistral","ollama","custom","mock"]' ] &&
+   [ "$(echo "$schema" | jq -c '.properties.temperature | [.type, .minimum, .maximum]')" == '["number",0,2]' ] &&
+   [ "$(echo "$schema" | jq -c '.properties.openai_api_key')" == '{"type":"string"}' ]; then
+    pass "Ranges and provider enum come from the rules; API keys have no default"
+else
+    fail "Schema out of sync with CLAM_CONFIG_RULES (missing: $missing)"
+fi
+echo
+
+echo "Test 9: Switching provider replaces a model it does not offer"
+echo "---"
+cmd_model model gpt-4o-mini > /dev/null 2>&1
+output=$(set_config_value "provider" "anthropic")
+if [[ "$output" == *"'gpt-4o-mini' is not offered by anthropic; switched to claude-3-5-sonnet-20241022"* ]] &&
+   grep -q "^model: claude-3-5-sonnet-20241022$" "$HOME/.clam/config" &&
+   grep -q "^endpoint: https://api.anthropic.com/v1/messages$" "$HOME/.clam/config"; then
+    pass "openai model replaced by the anthropic default, endpoint moved along"
+else
+    fail "Unexpected switch ($output): $(grep -E '^(provider|model|endpoint):' "$HOME/.clam/config" | tr '\n' ' ')"
+fi
+
+set_config_value "model" "claude-3-5-haiku-20241022" > /dev/null
+set_config_value "provider" "anthropic" > /dev/null
+set_config_value "model" "my-finetune" > /dev/null
+output=$(set_config_value "provider" "custom")
+if grep -q "^model: my-finetune$" "$HOME/.clam/config" && [ -z "$output" ] &&
+   [ "$(default_model_for groq)" == "llama-3.3-70b-versatile" ] && ! default_model_for custom > /dev/null; then
+    pass "Unknown models and custom gateways are left alone"
+else
+    fail "Model changed unexpectedly ($output): $(grep '^model:' "$HOME/.clam/config")"
+fi
+echo
+
+echo "Test 10: Config diffs list exactly the changed fields"
+echo "---"
+cp "$HOME/.clam/config" "$HOME/config_a"
+cp "$HOME/.clam/config" "$HOME/config_b"
+if [ "$(diff_config "$HOME/config_a" "$HOME/config_b")" == "[]" ]; then
+    pass "Identical configs give an empty diff"
+else
+    fail "Unexpected diff: $(diff_config "$HOME/config_a" "$HOME/config_b")"
+fi
+
+sed -i 's/^temperature:.*/temperature: 0.7/; s/^cache_size:.*/cache_size: 50/; /^block_severity:/d' "$HOME/config_b"
+echo "openai_api_key: sk-new-secret-key" >> "$HOME/config_b"
+changes=$(diff_config "$HOME/config_a" "$HOME/config_b")
+if [ "$(echo "$changes" | jq -c '[.[] | select(.key != "openai_api_key")]')" == '[{"key":"block_severity","old":"critical","new":null},{"key":"cache_size","old":"10","new":"50"},{"key":"temperature","old":"0.0","new":"0.7"}]' ] &&
+   [ "$(echo "$changes" | jq 'length')" -eq 4 ]; then
+    pass "Changed, removed and key fields reported with old and new values"
+else
+    fail "Unexpected diff: $changes"
+fi
+
+shown=$(cmd_config_diff "$HOME/config_b")
+if [[ "$shown" == *"cache_size: 10 → 50"* && "$shown" == *"block_severity: critical → (unset)"* ]] && [[ "$shown" != *"sk-new-secret-key"* ]]; then
+    pass "clam config diff shows changes without printing API keys"
+else
+    fail "Unexpected diff output: $shown"
+fi
+echo
+
+echo "Test 11: API keys are masked down to their last four characters"
+echo "---"
+full_key="sk-proj-abcdefghijklmnopqrstuvwxyz9876"
+if [ "$(mask_api_key "$full_key")" == "sk-...9876" ] && [ "$(mask_api_key "abc12345")" == "...2345" ] &&
+   [ "$(mask_api_key "abcd")" == "****" ]; then
+    pass "Long, short and tiny keys masked"
+else
+    fail "Unexpected masks: $(mask_api_key "$full_key") $(mask_api_key "abc12345") $(mask_api_key "abcd")"
+fi
+
+set_config_value "openai_api_key" "$full_key" > /dev/null
+TERM=dumb cmd_show_config > "$HOME/show_config" 2>&1
+if ! grep -q "$full_key" "$HOME/show_config" && ! grep -q "abcdefghijklmnop" "$HOME/show_config" &&
+   grep -q "CLAM_OPENAI_API_KEY:.*sk-...9876" "$HOME/show_config"; then
+    pass "clam config shows the masked key and never the full one"
+else
+    fail "Unexpected config output: $(grep API_KEY "$HOME/show_config")"
+fi
+echo
+
+echo "Test 12: The provider list covers every provider the config accepts"
+echo "---"
+providers=$(get_providers)
+if [ "$(echo "$providers" | jq -r 'map(.name) | join(",")')" == "${CLAM_CONFIG_RULES[provider]#enum:}" ] &&
+   [ "$(echo "$providers" | jq -c 'map(select(.requires_api_key | not) | .name)')" == '["ollama","custom","mock"]' ]; then
+    pass "All providers listed; only ollama, custom and mock run without a key"
+else
+    fail "Unexpected providers: $providers"
+fi
+
+if [ "$(echo "$providers" | jq -r '.[] | select(.name == "anthropic") | .default_endpoint')" == "https://api.anthropic.com/v1/messages" ] &&
+   [ "$(echo "$providers" | jq -c '.[] | select(.name == "custom") | [.default_model, .default_endpoint]')" == "[null,null]" ]; then
+    pass "Default endpoints come from the model catalogue; custom has none"
+else
+    fail "Unexpected provider defaults: $providers"
+fi
+echo
+
+echo "Test 13: CLAM_CONFIG_FILE redirects reads and writes"
+echo "---"
+cp "$HOME/.clam/config" "$HOME/config_before"
+export CLAM_CONFIG_FILE="$HOME/portable/clam.conf"
+create_default_config > /dev/null
+set_config_value "temperature" "1.5" > /dev/null
+sed -i 's/^max_env_vars:.*/max_env_vars: 7/' "$CLAM_CONFIG_FILE"
+load_config
+if grep -q "^temperature: 1.5$" "$CLAM_CONFIG_FILE" && [ "$CLAM_MAX_ENV_VARS" == "7" ] &&
+   cmp -s "$HOME/.clam/config" "$HOME/config_before"; then
+    pass "Config created, written and loaded at the override; ~/.clam/config untouched"
+else
+    fail "Override not honoured: temperature=$(grep '^temperature:' "$CLAM_CONFIG_FILE") max_env_vars=$CLAM_MAX_ENV_VARS"
+fi
+unset CLAM_CONFIG_FILE
+
+if [ "$(get_config_path)" == "$HOME/.clam/config" ]; then
+    pass "Without the override the default path is used"
+else
+    fail "Unexpected default path: $(get_config_path)"
+fi
+load_config
+echo
+
+echo "Test 14: Endpoints are validated per provider before saving"
+echo "---"
+if validate_endpoint openai "https://api.openai.com/v1/chat/completions" > /dev/null &&
+   validate_endpoint anthropic "https://gateway.internal/v1" > /dev/null &&
+   validate_endpoint custom "https://llm.internal/generate" > /dev/null; then
+    pass "OpenAI URL, Anthropic base URL and a custom gateway path are accepted"
+else
+    fail "A valid endpoint was rejected"
+fi
+
+scheme_error=$(validate_endpoint openai "htps://api.openai.com/v1/chat/completions")
+missing_error=$(validate_endpoint openai "api.openai.com/v1/chat/completions")
+host_error=$(validate_endpoint openai "https:///v1/chat/completions")
+path_error=$(validate_endpoint openai "https://api.openai.com/v1/completion")
+if [[ "$scheme_error" == *"unknown scheme 'htps://'"* && "$missing_error" == *"missing its scheme"* &&
+      "$host_error" == *"has no host"* && "$path_error" == *"path ending in /chat/completions"* ]]; then
+    pass "Scheme typo, missing scheme, missing host and wrong path each give their reason"
+else
+    fail "Unexpected reasons: $scheme_error | $missing_error | $host_error | $path_error"
+fi
+
+saved=$(grep '^endpoint:' "$HOME/.clam/config")
+set_config_value "endpoint" "htps://api.openai.com/v1/chat/completions" 2> "$HOME/endpoint_error"
+rejected=$?
+kept=$(grep '^endpoint:' "$HOME/.clam/config")
+set_config_value "provider" "ollama" > /dev/null 2>&1
+set_config_value "endpoint" "http://gpu-box:11434/" 2> /dev/null
+if [ "$rejected" -eq 1 ] && grep -q "unknown scheme" "$HOME/endpoint_error" &&
+   [ "$kept" == "$saved" ] &&
+   grep -q "^endpoint: http://gpu-box:11434/api/chat$" "$HOME/.clam/config" &&
+   [ "$CLAM_ENDPOINT" == "http://gpu-box:11434/api/chat" ]; then
+    pass "set rejects the typo and saves an Ollama base URL normalized"
+else
+    fail "Unexpected endpoint handling: rejected=$rejected kept='$kept' now='$(grep '^endpoint:' "$HOME/.clam/config")'"
+fi
+set_config_value "provider" "openai" > /dev/null 2>&1
+
+CLAM_PROVIDER=openai bash "$script_dir/../clam.sh" config set provider ollama > /dev/null 2>&1
+CLAM_PROVIDER=openai bash "$script_dir/../clam.sh" config set endpoint http://localhost:11434/api/chat > /dev/null 2> "$HOME/endpoint_error"
+saved_status=$?
+if [ "$saved_status" -eq 0 ] && [ ! -s "$HOME/endpoint_error" ] &&
+   grep -q "^endpoint: http://localhost:11434/api/chat$" "$HOME/.clam/config"; then
+    pass "An endpoint set in a later clam process is checked against the saved provider"
+else
+    fail "Endpoint rejected after a separate provider switch: status=$saved_status $(cat "$HOME/endpoint_error")"
+fi
+set_config_value "provider" "openai" > /dev/null 2>&1
+echo
+
+echo "Test 15: App paths report where each file lives and whether it exists"
+echo "---"
+paths=$(get_app_paths)
+if [ "$(echo "$paths" | jq -r '.config.path')" == "$(get_config_path)" ] &&
+   [ "$(echo "$paths" | jq -r '.config.exists')" == "true" ] &&
+   [ "$(echo "$paths" | jq -c '.usage_log | keys')" == '["exists","path"]' ]; then
+    pass "Config path matches get_config_path and is reported as existing"
+else
+    fail "Unexpected paths: $paths"
+fi
+
+paths=$(CLAM_CONFIG_FILE="$HOME/elsewhere/clam.conf" CLAM_CACHE_DIR="$HOME/no-cache" get_app_paths)
+if [ "$(echo "$paths" | jq -c '.config')" == "{\"path\":\"$HOME/elsewhere/clam.conf\",\"exists\":false}" ] &&
+   [ "$(echo "$paths" | jq -c '.cache.exists')" == "false" ]; then
+    pass "Overrides are resolved and missing files reported"
+else
+    fail "Overrides not reflected: $paths"
+fi
+echo
+
+finish_tests "Configuration Tests"
@@ -0,0 +1,160 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "Explain Command Tests"
+echo "================================"
+echo
+
+echo "Test 1: A representative model reply parses into summary and side effects"
+echo "---"
+content='{"summary":"Recursively deletes the build directory without prompting.","side_effects":["Deletes files under ./build","Cannot be undone"]}'
+explanation=$(parse_explain_response "$content")
+if [ "$(echo "$explanation" | jq -r '.summary')" == "Recursively deletes the build directory without prompting." ] &&
+   [ "$(echo "$explanation" | jq '.side_effects | length')" -eq 2 ]; then
+    pass "Summary and two side effects parsed"
+else
+    fail "Unexpected explanation: $explanation"
+fi
+
+if [ -z "$(parse_explain_response '{"side_effects":[]}')" ] && [ -z "$(parse_explain_response 'not json')" ]; then
+    pass "Replies without a summary are rejected"
+else
+    fail "Reply without summary was accepted"
+fi
+echo
+
+echo "Test 2: Explanations are cached like harm checks"
+echo "---"
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+export CLAM_OPENAI_API_KEY=test-key
+use_fake_curl '{"choices":[{"message":{"content":"{\"summary\":\"Lists files.\",\"side_effects\":[]}"}}]}'
+first=$(get_command_explanation "ls -la" 2>/dev/null)
+rm -f "$HOME/curl_args"
+second=$(get_command_explanation "ls -la" 2>/dev/null)
+if [ "$first" == "$second" ] && [ "$(echo "$second" | jq -r '.summary')" == "Lists files." ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Second lookup served from cache without an API call"
+else
+    fail "first=$first second=$second"
+fi
+
+cache_dir="$HOME/.clam/explain_cache"
+touch -d "-2 minutes" "$cache_dir"/explain-*.json
+use_fake_curl '{"choices":[{"message":{"content":"{\"summary\":\"Lists all files.\",\"side_effects\":[]}"}}]}'
+kept=$(get_command_explanation "ls -la" 2>/dev/null | jq -r '.summary')
+sed -i 's/^explain_cache_ttl:.*/explain_cache_ttl: 60/' "$HOME/.clam/config"
+expired=$(get_command_explanation "ls -la" 2>/dev/null | jq -r '.summary')
+if [ "$kept" == "Lists files." ] && [ "$expired" == "Lists all files." ]; then
+    pass "2-minute-old entry kept with ttl 0, fetched again with ttl 60"
+else
+    fail "kept='$kept' expired='$expired'"
+fi
+
+sed -i 's/^explain_cache_size:.*/explain_cache_size: 2/' "$HOME/.clam/config"
+for command in "make one" "make two" "make three"; do
+    get_command_explanation "$command" > /dev/null 2>&1
+    sleep 0.01
+done
+if [ "$(get_cache_stats "$cache_dir" "explain-*.json" | cut -d ' ' -f 1)" -eq 2 ] &&
+   [ ! -f "$cache_dir/explain-$(cache_key "make one").json" ]; then
+    pass "explain_cache_size 2 evicts the oldest explanations"
+else
+    fail "Unexpected explanation cache: $(ls "$cache_dir" | tr '\n' ' ')"
+fi
+echo
+
+echo "Test 4: Generated commands parse and carry safeguard warnings"
+echo "---"
+generated=$(parse_generate_response '{"command":"find . -name \"*.pdf\" -mtime -7","explanation":"PDFs changed this week"}')
+if [ "$(echo "$generated" | jq -r '.command')" == 'find . -name "*.pdf" -mtime -7' ] &&
+   [ "$(echo "$generated" | jq -r '.explanation')" == "PDFs changed this week" ]; then
+    pass "Command and explanation parsed"
+else
+    fail "Unexpected generate parse: $generated"
+fi
+
+if [ -z "$(parse_generate_response '{"explanation":"no command"}')" ]; then
+    pass "Reply without a command is rejected"
+else
+    fail "Reply without a command was accepted"
+fi
+
+use_fake_curl '{"choices":[{"message":{"content":"{\"command\":\"find . -name \\\"*.pdf\\\" -mtime -7\",\"explanation\":\"PDFs changed this week\"}"}}]}'
+generated=$(generate_command "find all PDFs modified this week" 2>/dev/null)
+if [ "$(echo "$generated" | jq -r '.warning')" == "null" ] && [[ "$(cat "$HOME/curl_args")" == *"for $(get_os_name) in"* ]]; then
+    pass "Safe generation has no warning; prompt names the OS"
+else
+    fail "Unexpected safe generation: $generated"
+fi
+
+use_fake_curl '{"choices":[{"message":{"content":"{\"command\":\"rm -rf ~\",\"explanation\":\"Frees disk space\"}"}}]}'
+generated=$(generate_command "free up disk space" 2>/dev/null)
+if [ "$(echo "$generated" | jq -r '.warning.severity')" == "critical" ] &&
+   [[ "$(echo "$generated" | jq -r '.warning.description')" == *"home directory"* ]]; then
+    pass "Dangerous generation flagged as critical"
+else
+    fail "Dangerous generation not flagged: $generated"
+fi
+echo
+
+echo "Test 5: Session recap"
+echo "---"
+recap=$(parse_recap_response '{"summary":"You built the project, a test run failed, then you fixed the import.","next_steps":["Re-run the test suite","Commit the fix"]}')
+if [[ "$(echo "$recap" | jq -r '.summary')" == "You built the project"* ]] &&
+   [ "$(echo "$recap" | jq -c '.next_steps')" == '["Re-run the test suite","Commit the fix"]' ]; then
+    pass "Summary and two next steps parsed"
+else
+    fail "Unexpected recap: $recap"
+fi
+
+rm -f "$HOME/curl_args"
+if [ "$(CLAM_SESSION_COMMANDS="" summarize_session)" == '{"summary":"","next_steps":[]}' ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Empty session gives an empty recap without calling the API"
+else
+    fail "Empty session was sent to the model"
+fi
+
+_CLAM_SESSION_COMMANDS=()
+record_session_command 0 "make"
+record_session_command 2 "pytest tests"
+record_session_command "" "ignored"
+record_session_command 0 "export TOKEN=abcdefghijklmnopqrstuvwx"
+use_fake_curl '{"choices":[{"message":{"content":"{\"summary\":\"Tests failed.\",\"next_steps\":[\"Fix the test\"]}"}}]}'
+recap=$(CLAM_SESSION_COMMANDS="$(printf '%s\n' "${_CLAM_SESSION_COMMANDS[@]}")" summarize_session 2>/dev/null)
+if [ "${#_CLAM_SESSION_COMMANDS[@]}" -eq 3 ] && [ "$(echo "$recap" | jq -r '.summary')" == "Tests failed." ] &&
+   grep -q 'REDACTED_APIKEY' "$HOME/curl_args" && ! grep -q 'abcdefghijklmnopqrstuvwx' "$HOME/curl_args" &&
+   grep -qF '2\tpytest tests' "$HOME/curl_args"; then
+    pass "Recorded commands and exit codes are sanitized and sent"
+else
+    fail "Unexpected recap request: $recap"
+fi
+
+cat > "$HOME/bin/clam" <<STUB
+#!/bin/bash
+printf '%s' "\$CLAM_SESSION_COMMANDS" > "$HOME/recap_session"
+STUB
+chmod +x "$HOME/bin/clam"
+cmd_enable > /dev/null 2>&1
+child_env=$(bash -c 'env' | grep -c 'pytest tests')
+clam recap
+cmd_disable
+if [ "$child_env" -eq 0 ] && [ "$(cat "$HOME/recap_session")" == "$(printf '%s\n' "${_CLAM_SESSION_COMMANDS[@]}")" ]; then
+    pass "Session commands stay out of child environments and are handed to clam recap"
+else
+    fail "Session leaked ($child_env matches) or not passed: $(cat "$HOME/recap_session")"
+fi
+
+for number in $(seq 1 25); do
+    record_session_command 0 "echo $number"
+done
+if [ "${#_CLAM_SESSION_COMMANDS[@]}" -eq 20 ] && [ "${_CLAM_SESSION_COMMANDS[0]}" == $'0\techo 6' ] &&
+   [ "${_CLAM_SESSION_COMMANDS[19]}" == $'0\techo 25' ]; then
+    pass "Only the newest max_history_commands commands are kept"
+else
+    fail "Unexpected session: ${#_CLAM_SESSION_COMMANDS[@]} entries from '${_CLAM_SESSION_COMMANDS[0]}'"
+fi
+echo
+
+finish_tests "Explain Command Tests"
@@ -0,0 +1,229 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "Fix Error Please Tests"
+echo "================================"
+echo
+
+echo "Test 1: Ranked fixes parse from a fixes array"
+echo "---"
+content='{"fixes":[{"recommended_command":"git push origin main","explanation":"Typo in git","confidence":0.9},{"recommended_command":"git push -u origin main","explanation":"Set upstream","confidence":0.4}]}'
+fixes=$(parse_fep_fixes "$content")
+if [ "$(echo "$fixes" | jq 'length')" -eq 2 ] &&
+   [ "$(echo "$fixes" | jq -r '.[0].recommended_command')" == "git push origin main" ] &&
+   [ "$(echo "$fixes" | jq -r '.[1].confidence')" == "0.4" ]; then
+    pass "Two fixes parsed in rank order with their own confidence"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+
+fixes=$(parse_fep_fixes '[{"recommended_command":"ls -la"},{"recommended_command":"ls"},{"recommended_command":"ll"},{"recommended_command":"dir"}]')
+if [ "$(echo "$fixes" | jq 'length')" -eq 3 ]; then
+    pass "Bare array accepted and capped at three fixes"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+echo
+
+echo "Test 2: A single-object response is treated as one fix"
+echo "---"
+fixes=$(parse_fep_fixes '{"recommended_command":"python3 script.py","explanation":"python is not installed"}')
+if [ "$(echo "$fixes" | jq 'length')" -eq 1 ] &&
+   [ "$(echo "$fixes" | jq -r '.[0].recommended_command')" == "python3 script.py" ] &&
+   [ "$(echo "$fixes" | jq -r '.[0].confidence')" == "null" ]; then
+    pass "Single fix parsed"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+
+fixes=$(parse_fep_fixes 'Sorry, I cannot help with that.')
+if [ -z "$fixes" ] || [ "$(echo "$fixes" | jq 'length')" -eq 0 ]; then
+    pass "Non-JSON reply yields no fixes"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+echo
+
+echo "Test 3: Command-not-found failures ask for a package install hint"
+echo "---"
+echo "bash: foo: command not found" > "$HOME/last_output.txt"
+prompt=$(CLAM_LAST_OUTPUT_FILE="$HOME/last_output.txt" CLAM_LAST_COMMAND="foo --bar" CLAM_LAST_EXIT_CODE=127 build_fep_prompt "")
+if [[ "$prompt" == *"## Missing Command"* && "$prompt" == *'`foo` was not found'* && "$prompt" == *"package installation"* ]]; then
+    pass "Prompt names foo and mentions package installation"
+else
+    fail "Missing-command hint absent from prompt"
+fi
+
+echo "zsh: command not found: rg" > "$HOME/last_output.txt"
+if [ "$(detect_missing_command "rg TODO" 127 "$(cat "$HOME/last_output.txt")")" == "rg" ] &&
+   [ "$(detect_missing_command "htop" 127 "")" == "htop" ] &&
+   [ -z "$(detect_missing_command "false" 1 "")" ]; then
+    pass "zsh format, bare 127 exit and ordinary failures handled"
+else
+    fail "detect_missing_command gave unexpected results"
+fi
+
+echo "fatal: not a git repository" > "$HOME/last_output.txt"
+prompt=$(CLAM_LAST_OUTPUT_FILE="$HOME/last_output.txt" CLAM_LAST_COMMAND="git status" CLAM_LAST_EXIT_CODE=128 build_fep_prompt "")
+if [[ "$prompt" != *"## Missing Command"* ]]; then
+    pass "Other failures get no install hint"
+else
+    fail "Install hint added to an unrelated failure"
+fi
+echo
+
+echo "Test 4: Escape codes are stripped from captured output before prompting"
+echo "---"
+printf '\e[0m\e[01;34msrc\e[0m  \e[01;32mbuild.sh\e[0m  \e[01;31marchive.tar.gz\e[0m\nDownloading 10%%\rDownloading 100%%\r\n' > "$HOME/last_output.txt"
+prompt=$(CLAM_LAST_OUTPUT_FILE="$HOME/last_output.txt" CLAM_LAST_COMMAND="ls --color=always" CLAM_LAST_EXIT_CODE=1 build_fep_prompt "")
+output_section=$(echo "$prompt" | sed -n '/^## Command Output/,/^## /p' | sed '1,2d;/^```$/,$d')
+if [ "$output_section" == $'src  build.sh  archive.tar.gz\nDownloading 100%' ]; then
+    pass "Colors removed and progress overwrite resolved"
+else
+    fail "Unexpected output section: $(echo "$output_section" | od -c | head -n 3)"
+fi
+
+raw_output=$(cat "$HOME/last_output.txt")
+if [ "$(estimate_tokens "$(strip_ansi "$raw_output")")" -lt "$(estimate_tokens "$raw_output")" ] &&
+   grep -q $'\e\[01;34m' "$HOME/last_output.txt"; then
+    pass "Token estimate drops and the raw capture is left untouched"
+else
+    fail "Stripping did not shrink the output or modified the capture file"
+fi
+echo
+
+echo "Test 5: Long output keeps its start and the final error"
+echo "---"
+{
+    echo "$ make all"
+    for i in $(seq 1 200); do echo "compiling module_$i.c with -O2 -Wall -Wextra"; done
+    echo "src/main.c:42:5: error: 'config' undeclared (first use in this function)"
+} > "$HOME/last_output.txt"
+prompt=$(CLAM_LAST_OUTPUT_FILE="$HOME/last_output.txt" CLAM_LAST_COMMAND="make all" CLAM_LAST_EXIT_CODE=2 build_fep_prompt "")
+if [ "$(wc -c < "$HOME/last_output.txt")" -gt 4500 ] &&
+   [[ "$prompt" == *"$ make all"* && "$prompt" == *"error: 'config' undeclared"* && "$prompt" == *"characters omitted"* ]] &&
+   [[ "$prompt" != *"module_100.c"* ]]; then
+    pass "Command echo and error kept, middle elided"
+else
+    fail "Truncated output lost the head, the error or the marker"
+fi
+
+if [ "$(truncate_middle "short" 100)" == "short" ] &&
+   [ "$(truncate_middle "$(printf 'a%.0s' {1..300})" 200 | tr -d '\n' | sed 's/\.\.\. \[[0-9]* characters omitted\] \.\.\.//' | wc -c)" -eq 200 ]; then
+    pass "Short output untouched, long output cut to the budget"
+else
+    fail "truncate_middle returned the wrong size"
+fi
+echo
+
+echo "Test 6: Recent output returns the requested tail without escapes"
+echo "---"
+export CLAM_LAST_OUTPUT_FILE="$HOME/tail_output.txt"
+printf 'line %s\n' $(seq 1 30) > "$CLAM_LAST_OUTPUT_FILE"
+printf '\e[31merror: build failed\e[0m\n' >> "$CLAM_LAST_OUTPUT_FILE"
+tail_output=$(get_recent_output 3)
+if [ "$tail_output" == $'line 29\nline 30\nerror: build failed' ]; then
+    pass "Last 3 lines returned with colour codes removed"
+else
+    fail "Unexpected tail: $tail_output"
+fi
+
+if [ "$(get_recent_output | wc -l)" -eq 20 ]; then
+    pass "Defaults to 20 lines"
+else
+    fail "Default tail length wrong: $(get_recent_output | wc -l)"
+fi
+
+rm "$CLAM_LAST_OUTPUT_FILE"
+if ! get_recent_output 5 > /dev/null; then
+    pass "Missing output reported as a failure"
+else
+    fail "get_recent_output succeeded without captured output"
+fi
+echo
+
+echo "Test 7: Captured output is capped to the most recent bytes"
+echo "---"
+export CLAM_LAST_OUTPUT_FILE="$HOME/capture.txt"
+CLAM_MAX_CAPTURE_BYTES=4096 run_with_capture "yes | head -n 200000; echo last-line; exit 3" > /dev/null
+if [ "$(wc -c < "$CLAM_LAST_OUTPUT_FILE")" -eq 4096 ] && [ "$(tail -n 1 "$CLAM_LAST_OUTPUT_FILE")" == "last-line" ] &&
+   [ "$CLAM_LAST_OUTPUT_TRUNCATED" == "true" ] && [ "$CLAM_LAST_EXIT_CODE" == "3" ]; then
+    pass "400KB of output kept as the last 4096 bytes, flagged as truncated"
+else
+    fail "size=$(wc -c < "$CLAM_LAST_OUTPUT_FILE") truncated=$CLAM_LAST_OUTPUT_TRUNCATED exit=$CLAM_LAST_EXIT_CODE"
+fi
+
+shown=$(run_with_capture "echo short")
+if [ "$shown" == "short" ] && [ "$(cat "$CLAM_LAST_OUTPUT_FILE")" == "short" ]; then
+    pass "Small output shown and captured unchanged"
+else
+    fail "Small output not passed through: '$shown'"
+fi
+
+run_with_capture "echo short" > /dev/null
+if [ "$CLAM_LAST_OUTPUT_TRUNCATED" == "false" ]; then
+    pass "Small output not flagged as truncated"
+else
+    fail "Small output flagged as truncated"
+fi
+echo
+
+echo "Test 8: Next command suggestions come from the last output"
+echo "---"
+suggestions=$(parse_next_command_response '{"suggestions":[{"command":"git add src/main.rs","explanation":"Stage the modified file"},{"command":"git diff","explanation":"Review the changes"},{"explanation":"no command"}]}')
+if [ "$(echo "$suggestions" | jq -r '[.[].command] | join(",")')" == "git add src/main.rs,git diff" ]; then
+    pass "Two ranked suggestions parsed, entry without a command dropped"
+else
+    fail "Unexpected suggestions: $suggestions"
+fi
+
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+export CLAM_LAST_OUTPUT_FILE="$HOME/next_output.txt"
+use_fake_curl '{"choices":[{"message":{"content":"{\"suggestions\":[{\"command\":\"git add src/main.rs\",\"explanation\":\"Stage it\"}]}"}}]}'
+printf '\n  \n' > "$CLAM_LAST_OUTPUT_FILE"
+if [ "$(suggest_next_command)" == "[]" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "Blank output gives no suggestions without calling the API"
+else
+    fail "Blank output was sent to the model"
+fi
+
+printf '\tmodified:   src/main.rs\nexport TOKEN=abcdefghijklmnopqrstuvwx\n' > "$CLAM_LAST_OUTPUT_FILE"
+suggestions=$(CLAM_OPENAI_API_KEY=test-key CLAM_LAST_COMMAND="git status" suggest_next_command 2>/dev/null)
+if [ "$(echo "$suggestions" | jq -r '.[0].command')" == "git add src/main.rs" ] &&
+   grep -q 'Last command: git status' "$HOME/curl_args" && grep -q 'modified:   src/main.rs' "$HOME/curl_args" &&
+   ! grep -q 'abcdefghijklmnopqrstuvwx' "$HOME/curl_args"; then
+    pass "Last command and sanitized output sent; ranked suggestions returned"
+else
+    fail "Unexpected next command request: $suggestions"
+fi
+echo
+
+echo "Test 9: Fixes are recovered from fenced or chatty replies"
+echo "---"
+fenced=$(printf '%s\n' 'Here are some fixes:' '```json' '{"fixes":[{"recommended_command":"git push origin main","explanation":"Typo"}]}' '```')
+fixes=$(parse_fep_fixes "$fenced")
+if [ "$(echo "$fixes" | jq -r '.[0].recommended_command')" == "git push origin main" ]; then
+    pass "JSON inside a fenced block parsed"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+
+fixes=$(parse_fep_fixes '{"recommended_command":"make -j4","explanation":"Use {jobs} wisely"} Let me know if that {does not} work.')
+if [ "$(echo "$fixes" | jq -c 'map(.recommended_command)')" == '["make -j4"]' ]; then
+    pass "Trailing commentary with braces ignored"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+
+fixes=$(parse_fep_fixes 'Context {"exit_code":1} first, then {"fixes":[{"recommended_command":"npm ci","explanation":"Lockfile \"}\" mismatch"}]}')
+if [ "$(echo "$fixes" | jq -c 'map(.recommended_command)')" == '["npm ci"]' ]; then
+    pass "Earlier unrelated objects skipped and quoted braces respected"
+else
+    fail "Unexpected fixes: $fixes"
+fi
+echo
+
+finish_tests "Fix Error Please Tests"
@@ -0,0 +1,108 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "History Search Tests"
+echo "================================"
+echo
+
+load_fake_history "git checkout main" "ls -la" "go test ./..." "git commit -m wip" "git checkout feature" "cd docs"
+
+echo "Test 1: Subsequence matching finds abbreviations"
+echo "---"
+results=$(search_history "gco" | cut -f 2)
+if grep -q "^git checkout feature$" <<< "$results" && ! grep -q "ls -la" <<< "$results"; then
+    pass "'gco' finds git checkout and skips ls -la"
+else
+    fail "Unexpected results: $results"
+fi
+echo
+
+echo "Test 2: Recency breaks ties and indices point at the original entry"
+echo "---"
+first=$(search_history "checkout" | head -n 1)
+if [ "$first" == $'5\tgit checkout feature' ]; then
+    pass "Most recent checkout returned with history index 5"
+else
+    fail "Unexpected first result: $first"
+fi
+echo
+
+echo "Test 3: Duplicates are collapsed to their most recent copy"
+echo "---"
+load_fake_history "make" "ls" "make"
+results=$(search_history "make")
+if [ "$results" == $'3\tmake' ]; then
+    pass "Single make entry at index 3"
+else
+    fail "Unexpected results: $results"
+fi
+echo
+
+echo "Test 4: An empty query returns the recent history, newest first"
+echo "---"
+load_fake_history "one" "two" "three"
+results=$(search_history "" | cut -f 2)
+if [ "$results" == $'three\ntwo\none' ]; then
+    pass "Full history returned newest first"
+else
+    fail "Unexpected results: $results"
+fi
+echo
+
+echo "Test 5: Relevance ranking puts similar commands first"
+echo "---"
+load_fake_history "git push origin main" "ls" "docker ps" "ls -la" "cd src"
+ranked=$(rank_history_by_similarity "git pu" | cut -f 2- | sed -E 's/^[[:space:]]*[0-9]+[[:space:]]+//')
+if [ "$(echo "$ranked" | head -n 1)" == "git push origin main" ] &&
+   [ "$(echo "$ranked" | grep -nx "ls" | cut -d: -f1)" -gt 1 ]; then
+    pass "'git push origin main' ranks above 'ls' for 'git pu'"
+else
+    fail "Unexpected ranking: $(echo "$ranked" | tr '\n' '|')"
+fi
+
+history_output=$(CLAM_RANK_HISTORY_BY_RELEVANCE=true CLAM_MAX_HISTORY_COMMANDS=2 get_command_history "ls -l" |
+    sed -E 's/^[[:space:]]*[0-9]+[[:space:]]+//')
+if [ "$history_output" == $'ls\nls -la' ]; then
+    pass "Top 2 relevant entries kept in history order"
+else
+    fail "Unexpected relevant history: $history_output"
+fi
+
+history_output=$(CLAM_MAX_HISTORY_COMMANDS=2 get_command_history "ls -l" | sed -E 's/^[[:space:]]*[0-9]+[[:space:]]+//')
+if [ "$history_output" == $'ls -la\ncd src' ]; then
+    pass "Recency order used when the flag is off"
+else
+    fail "Flag off changed history: $history_output"
+fi
+echo
+
+echo "Test 6: History files are read when the shell history is unavailable"
+echo "---"
+printf '%s\n' "#1700000000" "git status" "#1700000005" "make test" "ls -la" > "$HOME/bash_history_sample"
+if [ "$(read_history_file "$HOME/bash_history_sample")" == $'git status\nmake test\nls -la' ]; then
+    pass "Bash timestamps dropped"
+else
+    fail "Unexpected bash entries: $(read_history_file "$HOME/bash_history_sample" | tr '\n' '|')"
+fi
+
+printf '%s\n' ": 1700000000:0;git status" ": 1700000003:2;for f in *.log; do\\" "gzip \$f; done" ": 1700000009:0;npm test" > "$HOME/zsh_history_sample"
+if [ "$(read_history_file "$HOME/zsh_history_sample")" == $'git status\nfor f in *.log; do gzip $f; done\nnpm test' ]; then
+    pass "zsh extended-history prefixes stripped and multi-line entry joined"
+else
+    fail "Unexpected zsh entries: $(read_history_file "$HOME/zsh_history_sample" | tr '\n' '|')"
+fi
+
+cp "$HOME/zsh_history_sample" "$HOME/.zsh_history"
+history -c
+if [ "$(HISTFILE="" SHELL=/bin/zsh get_history_file)" == "$HOME/.zsh_history" ] &&
+   [ "$(HISTFILE="" SHELL=/bin/zsh get_indexed_history | tail -n 1)" == $'3\tnpm test' ] &&
+   [ "$(HISTFILE="" SHELL=/bin/zsh CLAM_MAX_HISTORY_COMMANDS=1 get_sanitized_history | sed -E 's/^[[:space:]]*[0-9]+[[:space:]]+//')" == "npm test" ]; then
+    pass "An empty shell history falls back to ~/.zsh_history for zsh users"
+else
+    fail "Fallback history: $(HISTFILE="" SHELL=/bin/zsh get_indexed_history | tr '\n' '|')"
+fi
+echo
+
+finish_tests "History Search Tests"
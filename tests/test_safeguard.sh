@@ -0,0 +1,499 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "Safeguard Pattern Tests"
+echo "================================"
+echo
+
+echo "Test 1: Local patterns report severity and description"
+echo "---"
+result=$(check_command_safeguard "sudo mkfs.ext4 /dev/sdb1")
+if [ "$(echo "$result" | jq -r '.severity')" == "critical" ] &&
+   [[ "$(echo "$result" | jq -r '.description')" == "Formats a filesystem"* ]]; then
+    pass "mkfs matched as critical"
+else
+    fail "Unexpected result: $result"
+fi
+
+if [ "$(check_command_safeguard "ls -la" | jq -r '.matched')" == "false" ]; then
+    pass "ls -la matches nothing"
+else
+    fail "ls -la matched a safeguard pattern"
+fi
+echo
+
+echo "Test 2: block_dangerous refuses critical commands"
+echo "---"
+if ! CLAM_BLOCK_DANGEROUS=true guard_dangerous_command "rm -rf / --no-preserve-root" > /dev/null; then
+    pass "rm -rf / blocked"
+else
+    fail "rm -rf / was allowed"
+fi
+
+if CLAM_BLOCK_DANGEROUS=true guard_dangerous_command "rm -r build" > /dev/null &&
+   ! CLAM_BLOCK_DANGEROUS=true CLAM_BLOCK_SEVERITY=medium guard_dangerous_command "rm -r build" > /dev/null; then
+    pass "Medium commands pass at the default level and are blocked at block_severity=medium"
+else
+    fail "block_severity not respected"
+fi
+
+if CLAM_BLOCK_DANGEROUS=false guard_dangerous_command "rm -rf /" > /dev/null; then
+    pass "Nothing is blocked when block_dangerous is off"
+else
+    fail "Command blocked with block_dangerous off"
+fi
+
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+unset CLAM_BLOCK_DANGEROUS CLAM_BLOCK_SEVERITY
+bash "$script_dir/../clam.sh" config set block_dangerous true > /dev/null 2>&1
+if ! guard_dangerous_command "rm -rf /" > /dev/null &&
+   sed -i 's/^block_severity:.*/block_severity: medium/' "$HOME/.clam/config" &&
+   ! guard_dangerous_command "rm -r build" > /dev/null; then
+    pass "block_dangerous and block_severity are read from the config file on each check"
+else
+    fail "Config file changes ignored: $(grep '^block_' "$HOME/.clam/config")"
+fi
+command rm -f "$HOME/.clam/config"
+echo
+
+echo "Test 3: The override token lets a blocked command through once"
+echo "---"
+if CLAM_BLOCK_DANGEROUS=true guard_dangerous_command "rm -rf /" override > /dev/null; then
+    pass "Override accepted"
+else
+    fail "Override rejected"
+fi
+
+if CLAM_BLOCK_DANGEROUS=true confirm_dangerous_command "rm -rf /" <<< "override" > /dev/null &&
+   ! CLAM_BLOCK_DANGEROUS=true confirm_dangerous_command "rm -rf /" <<< "yes" > /dev/null; then
+    pass "Typing 'override' runs it, anything else cancels"
+else
+    fail "Interactive override handled incorrectly"
+fi
+echo
+
+echo "Test 4: The harm system prompt names the current OS and shell"
+echo "---"
+system_prompt=$(get_harm_system_prompt)
+if [[ "$OSTYPE" == linux* && "$system_prompt" == *"run on Linux in $(basename "${SHELL:-bash}")"* ]]; then
+    pass "Prompt mentions Linux and $(basename "${SHELL:-bash}")"
+elif [[ "$OSTYPE" != linux* && "$system_prompt" == *"run on $(get_os_name)"* ]]; then
+    pass "Prompt mentions $(get_os_name)"
+else
+    fail "OS missing from prompt: $system_prompt"
+fi
+
+windows_prompt=$(get_harm_system_prompt msys /usr/bin/pwsh)
+if [[ "$windows_prompt" == *"run on Windows in pwsh"* && "$windows_prompt" == *"Remove-Item -Recurse -Force"* ]]; then
+    pass "Windows prompt uses PowerShell examples"
+else
+    fail "Windows prompt: $windows_prompt"
+fi
+
+if [[ "$(build_harm_detection_payload "ls" | jq -r '.messages[0].content')" == *"run on $(get_os_name)"* ]]; then
+    pass "Harm payload carries the OS-aware system prompt"
+else
+    fail "Harm payload system prompt is not OS-aware"
+fi
+echo
+
+echo "Test 5: sudo and doas raise the severity of the underlying command"
+echo "---"
+plain=$(check_command_safeguard "rm -r build")
+elevated=$(check_command_safeguard "sudo rm -r build")
+if [ "$(echo "$plain" | jq -r '.severity')" == "medium" ] &&
+   [ "$(echo "$elevated" | jq -r '.severity')" == "high" ] &&
+   [[ "$(echo "$elevated" | jq -r '.description')" == *"elevated privileges"* ]]; then
+    pass "sudo rm -r is high, rm -r is medium"
+else
+    fail "plain: $plain, elevated: $elevated"
+fi
+
+if [ "$(check_command_safeguard "doas -u root shutdown -h now" | jq -r '.severity')" == "critical" ] &&
+   [ "$(check_command_safeguard "sudo rm -rf /" | jq -r '.severity')" == "critical" ]; then
+    pass "doas with options is stripped and critical stays critical"
+else
+    fail "doas/critical handling wrong"
+fi
+
+if [ "$(strip_privilege_prefix "sudo -E doas make install")" == "make install" ] &&
+   [ "$(strip_privilege_prefix "sudo -u postgres psql")" == "psql" ] &&
+   ! strip_privilege_prefix "make install" > /dev/null; then
+    pass "Nested prefixes stripped; plain commands report no prefix"
+else
+    fail "strip_privilege_prefix gave unexpected results"
+fi
+echo
+
+echo "Test 6: Regex rules catch forms literal substrings miss"
+echo "---"
+expect_match() {
+    local command="$1"
+    local severity="$2"
+    local result=$(check_command_safeguard "$command")
+
+    if [ "$(echo "$result" | jq -r '.severity')" == "$severity" ] && [[ "$(echo "$result" | jq -r '.pattern')" == re:* ]]; then
+        pass "'$command' caught as $severity by a regex rule"
+    else
+        fail "'$command': $result"
+    fi
+}
+expect_match "rm   -rf   /" critical
+expect_match "rm -r -f /" critical
+expect_match "dd if=/dev/zero of=/dev/sdb bs=1M" critical
+expect_match "dd if=image.iso of=/dev/nvme0n1" critical
+expect_match "rm -r build; rm -r dist" high
+
+if [ "$(check_command_safeguard "rm -rf /tmp/build" | jq -r '.severity')" == "medium" ] &&
+   [ "$(check_command_safeguard "echo rm is fine" | jq -r '.matched')" == "false" ]; then
+    pass "Deleting a subdirectory of / stays medium; harmless text is not matched"
+else
+    fail "Regex rules over-matched"
+fi
+
+if [ "$(check_command_safeguard "git fetch && git push --force origin main" | jq -r '.pattern')" == "git push --force" ]; then
+    pass "Literal entries still match"
+else
+    fail "Literal entries stopped matching"
+fi
+echo
+
+echo "Test 7: Pattern and model verdicts merge into one assessment"
+echo "---"
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+export CLAM_OPENAI_API_KEY=test-key
+safe_reply='{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"is_harmful\":false,\"explanation\":\"ok\"}"}}]}}]}'
+harmful_reply='{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"is_harmful\":true,\"explanation\":\"Uploads private keys\"}"}}]}}]}'
+
+use_fake_curl "$safe_reply"
+assessment=$(assess_command "rm -r build" 2>/dev/null)
+if [ "$(echo "$assessment" | jq -c '[.is_risky, .severity, .sources]')" == '[true,"medium",["pattern"]]' ]; then
+    pass "Pattern-only hit: medium from pattern"
+else
+    fail "Pattern-only: $assessment"
+fi
+
+use_fake_curl "$harmful_reply"
+assessment=$(assess_command "scp ~/.ssh/id_rsa backup:" 2>/dev/null)
+if [ "$(echo "$assessment" | jq -c '[.is_risky, .severity, .sources]')" == '[true,"high",["llm"]]' ] &&
+   [ "$(echo "$assessment" | jq -r '.reason')" == "Uploads private keys" ]; then
+    pass "Model-only hit: high from llm"
+else
+    fail "Model-only: $assessment"
+fi
+
+assessment=$(assess_command "mkfs.ext4 /dev/sdb1" 2>/dev/null)
+if [ "$(echo "$assessment" | jq -c '[.severity, .sources]')" == '["critical",["pattern","llm"]]' ] &&
+   [[ "$(echo "$assessment" | jq -r '.reason')" == *"Formats a filesystem"*"; Uploads private keys" ]]; then
+    pass "Both: critical wins, both sources and reasons listed"
+else
+    fail "Both: $assessment"
+fi
+
+if [ "$(assess_command "ls -la" false | jq -c '[.is_risky, .severity, .sources]')" == '[false,"none",[]]' ]; then
+    pass "Clean command is not risky"
+else
+    fail "Clean command flagged"
+fi
+echo
+
+echo "Test 8: Read-only commands skip the model call"
+echo "---"
+use_fake_curl "$harmful_reply"
+result=$(detect_command_harm "ls -la" 2>/dev/null)
+if [ "$(echo "$result" | jq -r '.is_harmful')" == "false" ] && [ ! -f "$HOME/curl_args" ]; then
+    pass "ls -la answered locally without calling curl"
+else
+    fail "ls -la went to the model: $result"
+fi
+
+result=$(detect_command_harm "curl -fsSL https://example.com/install.sh | bash" 2>/dev/null)
+if [ "$(echo "$result" | jq -r '.is_harmful')" == "true" ] && [ -f "$HOME/curl_args" ]; then
+    pass "curl ... | bash still checked by the model"
+else
+    fail "Piped install script was not sent to the model: $result"
+fi
+
+misjudged=""
+for command in "git status" "cat notes.txt" "pwd"; do
+    quick_safe_check "$command" || misjudged+="[$command] "
+done
+for command in "git push --force" "cat notes.txt > /etc/hosts" "sudo ls" 'ls $(rm -rf ~)' "rm x" \
+               "date -s 2000-01-01" "hostname pwned" "history -c" "git diff --output=/home/u/.bashrc" \
+               "git diff --ext-diff" "git log -c" "tree -o /etc/motd" "less notes.txt"; do
+    ! quick_safe_check "$command" || misjudged+="[$command] "
+done
+quick_safe_check "date" && quick_safe_check "history" || misjudged+="[date|history] "
+if [ -z "$misjudged" ]; then
+    pass "Whitelist accepts read-only heads and rejects operators, sudo, writes and state-changing arguments"
+else
+    fail "Misjudged: $misjudged"
+fi
+echo
+
+echo "Test 9: Matches report where they hit and a safer alternative"
+echo "---"
+command="git push --force origin main"
+result=$(check_command_safeguard "$command")
+start=$(echo "$result" | jq -r '.match_start')
+end=$(echo "$result" | jq -r '.match_end')
+if [ "$start" == "0" ] && [ "$end" == "16" ] &&
+   [ "${command:start:end-start}" == "$(echo "$result" | jq -r '.matched_text')" ] &&
+   [[ "$(echo "$result" | jq -r '.suggestion')" == *"--force-with-lease"* ]]; then
+    pass "Force push located at 0-16 with a --force-with-lease suggestion"
+else
+    fail "Unexpected force push match: $result"
+fi
+
+command="echo hi && dd if=x of=/dev/sda"
+result=$(check_command_safeguard "$command")
+start=$(echo "$result" | jq -r '.match_start')
+end=$(echo "$result" | jq -r '.match_end')
+if [ "${command:start:2}" == "dd" ] && [ "${command:start:end-start}" == "$(echo "$result" | jq -r '.matched_text')" ]; then
+    pass "Regex rule match located inside a compound command"
+else
+    fail "Unexpected dd match: $result"
+fi
+
+result=$(check_command_safeguard "sudo rm -rf ~")
+if [ "$(echo "$result" | jq -r '.match_start')" == "5" ] &&
+   [ "$(check_command_safeguard ':(){ :|:& };:' | jq -r '.suggestion')" == "null" ]; then
+    pass "Offsets count the stripped sudo prefix; rules without advice give null"
+else
+    fail "Unexpected sudo offsets or fork bomb suggestion: $result"
+fi
+
+assessment=$(assess_command "git push --force origin main" false)
+if [[ "$(echo "$assessment" | jq -r '.suggestion')" == *"--force-with-lease"* ]]; then
+    pass "Assessment carries the suggestion through to the confirmation prompt"
+else
+    fail "Assessment lost the suggestion: $assessment"
+fi
+echo
+
+echo "Test 10: Paste guard"
+echo "---"
+result=$(check_paste "git status")
+if [ "$(echo "$result" | jq -c '[.warning, .executable_lines, .dangerous]')" == '[false,1,[]]' ]; then
+    pass "A single safe line gives no warning"
+else
+    fail "Unexpected result for a safe paste: $result"
+fi
+
+result=$(check_paste $'cd build\n\n# clean up\nrm -rf ./*\nmake')
+if [ "$(echo "$result" | jq -r '.warning')" == "true" ] && [ "$(echo "$result" | jq '.executable_lines')" -eq 3 ] &&
+   [ "$(echo "$result" | jq -c '.dangerous | map([.line, .command])')" == '[[4,"rm -rf ./*"]]' ] &&
+   [[ "$(echo "$result" | jq -r '.reason')" == "Paste runs 3 commands; line 4: "* ]]; then
+    pass "Multi-line paste flags the rm -rf line and the number of commands"
+else
+    fail "Unexpected result for a dangerous paste: $result"
+fi
+
+if [ "$(check_paste $'ls\npwd' | jq -c '[.warning, .dangerous]')" == '[true,[]]' ]; then
+    pass "Several safe lines still warn that more than one command runs"
+else
+    fail "Multiple safe lines not flagged"
+fi
+echo
+
+echo "Test 11: Every matching pattern is listed, most severe first"
+echo "---"
+matches=$(check_all_matches "rm -rf build > /etc/passwd")
+if [ "$(echo "$matches" | jq -c 'map([.severity, .pattern])')" == '[["critical","> /etc/passwd"],["medium","rm -rf"]]' ] &&
+   [ "$(echo "$matches" | jq -c 'map(.matched_text)')" == '["> /etc/passwd","rm -rf"]' ]; then
+    pass "Redirect over /etc/passwd and the recursive delete are both reported"
+else
+    fail "Unexpected matches: $matches"
+fi
+
+if [ "$(check_all_matches "ls -la")" == "[]" ] &&
+   [ "$(check_command_safeguard "rm -rf build > /etc/passwd" | jq -r '.severity')" == "critical" ]; then
+    pass "No matches gives an empty list; the single result is still the critical one"
+else
+    fail "Unexpected results: $(check_all_matches "ls -la")"
+fi
+echo
+
+echo "Test 12: The most severe match is reported regardless of table order"
+echo "---"
+reordered=$(
+    CLAM_SAFEGUARD_PATTERNS=(
+        "medium|rm -r |Recursively deletes files"
+        "critical|> /etc/passwd|Overwrites the user account database"
+    )
+    check_command_safeguard "rm -r build > /etc/passwd"
+)
+if [ "$(echo "$reordered" | jq -c '[.severity, .pattern]')" == '["critical","> /etc/passwd"]' ]; then
+    pass "A critical match listed after a medium one still wins"
+else
+    fail "Reported the first match in table order: $reordered"
+fi
+
+tied=$(check_command_safeguard "chown -R me . && git reset --hard")
+if [ "$(echo "$tied" | jq -c '[.severity, .pattern]')" == '["medium","git reset --hard"]' ]; then
+    pass "Between two medium matches the longer one is reported"
+else
+    fail "Unexpected tie-break: $tied"
+fi
+echo
+
+echo "Test 13: Harm verdicts are recovered from fenced or chatty replies"
+echo "---"
+verdict=$(parse_harm_response "$(printf '%s\n' '```json' '{"is_harmful": true, "explanation": "Deletes {home}"}' '```' 'Stay safe!')")
+if [ "$(echo "$verdict" | jq -c '[.is_harmful, .explanation]')" == '[true,"Deletes {home}"]' ]; then
+    pass "Fenced verdict parsed"
+else
+    fail "Unexpected verdict: $verdict"
+fi
+
+verdict=$(parse_harm_response 'Checked {"command": {"name": "ls"}} and decided {"is_harmful": false, "explanation": "Lists files"} - done.')
+if [ "$(echo "$verdict" | jq -r '.explanation')" == "Lists files" ]; then
+    pass "Nested unrelated object skipped in favour of the verdict"
+else
+    fail "Unexpected verdict: $verdict"
+fi
+
+if [ -z "$(parse_harm_response 'I am not sure about {this}.')" ]; then
+    pass "A reply with no verdict yields nothing"
+else
+    fail "Invented a verdict from prose"
+fi
+
+use_fake_curl '{"choices":[{"message":{"content":"Sure! {\"is_harmful\": true, \"explanation\": \"Wipes the disk\"} Hope this helps."}}]}'
+harm=$(CLAM_PROVIDER=groq CLAM_ACTIVE_API_KEY=test-key detect_command_harm "dd if=/dev/zero of=/dev/sda" 2>/dev/null)
+if [ "$(echo "$harm" | jq -r '.explanation')" == "Wipes the disk" ]; then
+    pass "Harm detection reads a verdict wrapped in commentary"
+else
+    fail "Unexpected harm result: $harm"
+fi
+echo
+
+echo "Test 14: Linting a script flags the lines that match"
+echo "---"
+script=$(cat <<'SCRIPT'
+#!/bin/bash
+# Never run rm -rf / by hand
+set -euo pipefail
+make build # then rm -rf / is only a comment
+rm -rf \
+    ./dist
+echo "deploying"
+chmod -R 777 /
+SCRIPT
+)
+findings=$(lint_script "$script")
+if [ "$(echo "$findings" | jq -c 'map([.line, .severity])')" == '[[5,"medium"],[8,"high"]]' ] &&
+   [ "$(echo "$findings" | jq -r '.[0].command')" == "rm -rf     ./dist" ] &&
+   [ "$(echo "$findings" | jq -r '.[1].description')" == "Makes a directory tree world-writable" ]; then
+    pass "Continued rm on line 5 and chmod on line 8 flagged; comments ignored"
+else
+    fail "Unexpected findings: $findings"
+fi
+
+if [ "$(lint_script $'ls -la\ngit status')" == "[]" ]; then
+    pass "A harmless script has no findings"
+else
+    fail "Unexpected findings: $(lint_script $'ls -la\ngit status')"
+fi
+
+printf '%s\n' "$script" > "$HOME/deploy.sh"
+output=$(cmd_lint lint "$HOME/deploy.sh")
+status=$?
+if [ "$status" -eq 1 ] && [[ "$output" == *"deploy.sh:8: high: Makes a directory tree world-writable"* ]] &&
+   echo "ls" | cmd_lint lint > /dev/null; then
+    pass "clam lint prints file:line findings and fails only when something matches"
+else
+    fail "Unexpected lint output (status $status): $output"
+fi
+echo
+
+echo "Test 15: Several risky elements raise a medium match to high"
+echo "---"
+single=$(check_command_safeguard "chown -R nobody ./*")
+combined=$(check_command_safeguard "chown -R nobody ~/* && echo done")
+if [ "$(echo "$single" | jq -c '[.severity, .score]')" == '["medium",4]' ] &&
+   [ "$(echo "$combined" | jq -c '[.severity, .score]')" == '["high",6]' ] &&
+   [ "$(echo "$combined" | jq -c '.signals')" == '["recursive","wildcard","root or home directory","several commands"]' ]; then
+    pass "chown -R with a wildcard stays medium; adding ~ and a chain scores 6 and escalates"
+else
+    fail "Unexpected results: single=$single combined=$combined"
+fi
+
+home_signals=$(score_command_risk 'rm -rf $HOME' high | jq -c '.signals')
+if [ "$home_signals" == '["recursive","forced","root or home directory"]' ] &&
+   [ "$(score_command_risk 'rm -rf $HOME/' high | jq -c '.signals')" == "$home_signals" ]; then
+    pass "A literal \$HOME counts as the home directory like ~ does"
+else
+    fail "\$HOME not scored: $home_signals"
+fi
+
+forced=$(
+    CLAM_RISK_SIGNALS=("3|--force|forced")
+    check_command_safeguard "git push --force"
+)
+if [ "$(echo "$forced" | jq -c '[.severity, .score, .signals]')" == '["high",5,["forced"]]' ]; then
+    pass "Signals are read from CLAM_RISK_SIGNALS with their own weights"
+else
+    fail "Custom signal not applied: $forced"
+fi
+
+if [ "$(score_command_risk "ls -la" none)" == '{"score":0,"severity":"none","signals":[]}' ] &&
+   [ "$(check_command_safeguard "ls -R ./* && pwd")" == '{"matched":false}' ]; then
+    pass "Signals alone never flag a command that matches no pattern"
+else
+    fail "Unexpected unmatched result: $(check_command_safeguard "ls -R ./* && pwd")"
+fi
+echo
+
+echo "Test 16: Child shells keep the model harm check"
+echo "---"
+rm -rf "$HOME/.clam/harm_cache"
+use_fake_curl "$harmful_reply"
+enable_safeguards
+touch "$HOME/child_rm_target"
+blocked_output=$(bash -c "rm '$HOME/child_rm_target'" 2>&1 <<< "n")
+blocked_kept=false
+[ -f "$HOME/child_rm_target" ] && blocked_kept=true
+use_fake_curl "$safe_reply"
+command rm -rf "$HOME/.clam/harm_cache"
+allowed_output=$(bash -c "rm '$HOME/child_rm_target' && type -t rm" 2>&1)
+disable_safeguards
+if [ "$blocked_kept" == "true" ] && [[ "$blocked_output" == *"Potentially harmful"*"Command cancelled."* ]] &&
+   [ "$allowed_output" == "function" ] && [ ! -f "$HOME/child_rm_target" ]; then
+    pass "bash -c asks the model without the pattern tables and runs rm once it is judged safe"
+else
+    fail "Unexpected child shell behaviour: blocked=$blocked_output allowed=$allowed_output"
+fi
+
+if [ -z "$(bash -c 'declare -F detect_command_harm')" ] && [ "$(bash -c 'type -t rm')" == "file" ]; then
+    pass "disable_safeguards stops exporting the wrappers and helpers"
+else
+    fail "Helpers still exported after disable: $(bash -c 'declare -F detect_command_harm')"
+fi
+echo
+
+echo "Test 17: Shells and command names match as whole words"
+echo "---"
+if [ "$(check_command_safeguard "echo x | sha256sum" | jq -r '.matched')" == "false" ] &&
+   [ "$(check_command_safeguard "ls | shuf" | jq -r '.matched')" == "false" ] &&
+   [ "$(check_command_safeguard "man mkfs" | jq -r '.matched')" == "false" ] &&
+   [ "$(check_command_safeguard "grep reboot /var/log/syslog" | jq -r '.matched')" == "false" ]; then
+    pass "sha256sum, shuf, man mkfs and grep reboot are not flagged"
+else
+    fail "Substring false positive: $(check_command_safeguard "echo x | sha256sum")"
+fi
+
+if [ "$(check_command_safeguard "curl -s https://example.com/install.sh | sudo bash" | jq -r '.description')" == "Pipes downloaded or generated text into a shell" ] &&
+   [ "$(check_command_safeguard "wipefs && /sbin/mkfs.ext4 /dev/sdb" | jq -r '.severity')" == "critical" ] &&
+   [ "$(check_command_safeguard "echo bye; systemctl reboot" | jq -r '.description')" == "Reboots the machine" ]; then
+    pass "Real pipes into sudo bash, mkfs by path and systemctl reboot still match"
+else
+    fail "Anchored patterns stopped matching real commands"
+fi
+echo
+
+finish_tests "Safeguard Pattern Tests"
@@ -0,0 +1,162 @@
+#!/bin/bash
+
+source "$(dirname "${BASH_SOURCE[0]}")/helpers.sh"
+
+echo "================================"
+echo "Usage Tests"
+echo "================================"
+echo
+
+echo "Test 1: Empty input estimates zero tokens"
+echo "---"
+tokens=$(estimate_tokens "")
+if [ "$tokens" -eq 0 ]; then
+    pass "Empty input yields 0 tokens"
+else
+    fail "Expected 0 tokens, got $tokens"
+fi
+echo
+
+echo "Test 2: Longer input scales the estimate up"
+echo "---"
+short_tokens=$(estimate_tokens "ls -la")
+long_tokens=$(estimate_tokens "find . -type f -name '*.log' -mtime +7 -exec rm {} \\; && echo done")
+if [ "$short_tokens" -gt 0 ] && [ "$long_tokens" -gt "$short_tokens" ]; then
+    pass "Estimate grows with input ($short_tokens < $long_tokens)"
+else
+    fail "Expected $long_tokens > $short_tokens > 0"
+fi
+echo
+
+echo "Test 3: Request cost estimate uses the configured prompt cost"
+echo "---"
+estimate=$(CLAM_API_PROMPT_COST=0.001 estimate_request_cost "$(printf 'a%.0s' {1..400})")
+if [ "$(echo "$estimate" | jq -r '.prompt_tokens')" == "100" ] &&
+   [ "$(echo "$estimate" | jq -r '.cost_usd')" == "0.1" ] &&
+   [ "$(echo "$estimate" | jq -r '.is_estimate')" == "true" ]; then
+    pass "400 chars at \$0.001/token is flagged as an estimate of \$0.1"
+else
+    fail "Unexpected estimate: $estimate"
+fi
+echo
+
+echo "Test 4: A heavy-usage prompt stays within the token budget"
+echo "---"
+# Fixture: full history, a crowded repository directory and a command with
+# no --help. If this trips, a context addition is costing too much.
+prompt_budget=1800
+mkdir -p "$HOME/.clam"
+create_default_config > /dev/null
+heavy_dir="$HOME/heavy"
+mkdir -p "$heavy_dir"
+for i in $(seq -w 1 120); do touch "$heavy_dir/module_$i.py"; done
+git -C "$heavy_dir" init -q
+heavy_history=()
+for i in $(seq 1 60); do
+    heavy_history+=("docker compose -f deploy/docker-compose.prod.yml up --build --force-recreate service$i")
+done
+load_fake_history "${heavy_history[@]}"
+tokens=$(cd "$heavy_dir" && prompt_size "deployctl rollout")
+if [ "$tokens" -gt 0 ] && [ "$tokens" -le "$prompt_budget" ]; then
+    pass "Heavy prompt is ~$tokens tokens (budget $prompt_budget)"
+else
+    fail "Heavy prompt is ~$tokens tokens, over the $prompt_budget budget"
+fi
+echo
+
+echo "Test 5: Command outcomes count failures and exit codes"
+echo "---"
+for exit_code in 0 1 0 127 1 0 2; do
+    record_command_result "$exit_code"
+done
+record_command_result ""
+outcomes=$(get_command_outcome_stats)
+if [ "$(echo "$outcomes" | jq -r '.total')" == "7" ] &&
+   [ "$(echo "$outcomes" | jq -r '.failures')" == "4" ] &&
+   [ "$(echo "$outcomes" | jq -c '.exit_codes')" == '{"0":3,"1":2,"2":1,"127":1}' ]; then
+    pass "7 results, 4 failures, exit code histogram correct"
+else
+    fail "Unexpected outcome stats: $outcomes"
+fi
+
+if [ "$(wc -l < "$HOME/.clam/outcomes.log")" -eq 4 ]; then
+    pass "The outcome log keeps one counter per exit code"
+else
+    fail "Outcome log grew per command: $(cat "$HOME/.clam/outcomes.log")"
+fi
+
+for shell_number in 1 2 3 4; do
+    ( for _ in 1 2 3 4 5; do record_command_result 0; done ) &
+done
+wait
+leftovers=$(find "$HOME/.clam" -name 'outcomes.log.*' | wc -l)
+zero_count=$(get_command_outcome_stats | jq -r '.exit_codes["0"]')
+if [ "$leftovers" -eq 0 ] && [ "$zero_count" -gt 3 ] && [ "$zero_count" -le 23 ] &&
+   [ "$(get_command_outcome_stats | jq -r '.failures')" == "4" ]; then
+    pass "Shells recording at once never leave temp files or corrupt the counters"
+else
+    fail "Concurrent recording: $leftovers temp files, $(cat "$HOME/.clam/outcomes.log")"
+fi
+
+if [ "$(CLAM_OUTCOME_LOG_FILE="$HOME/none.log" get_command_outcome_stats | jq -r '.total')" == "0" ]; then
+    pass "No outcome log reports zero commands"
+else
+    fail "Missing outcome log not handled"
+fi
+echo
+
+echo "Test 6: Suggestion acceptance rate"
+echo "---"
+if [ "$(get_suggestion_acceptance_stats)" == '{"accepted":0,"rejected":0,"rate":null}' ]; then
+    pass "No suggestions shown reports a null rate"
+else
+    fail "Unexpected empty stats: $(get_suggestion_acceptance_stats)"
+fi
+
+for outcome in accepted rejected accepted accepted bogus; do
+    record_suggestion_feedback "$outcome"
+done
+acceptance=$(get_suggestion_acceptance_stats)
+if [ "$acceptance" == '{"accepted":3,"rejected":1,"rate":0.75}' ]; then
+    pass "3 of 4 accepted gives a rate of 0.75"
+else
+    fail "Unexpected acceptance stats: $acceptance"
+fi
+echo
+
+echo "Test 7: Completion latency percentiles"
+echo "---"
+if [ "$(get_latency_stats)" == '{"count":0,"average_ms":null,"p50_ms":null,"p95_ms":null,"p99_ms":null}' ]; then
+    pass "No recorded latencies reports nulls"
+else
+    fail "Unexpected empty latency stats: $(get_latency_stats)"
+fi
+
+for latency_ms in $(seq 200 -10 10) bogus; do
+    record_completion_latency "$latency_ms"
+done
+latency=$(get_latency_stats)
+if [ "$latency" == '{"count":20,"average_ms":105,"p50_ms":100,"p95_ms":190,"p99_ms":200}' ]; then
+    pass "10..200 ms gives p50 100, p95 190, p99 200 and an average of 105"
+else
+    fail "Unexpected latency stats: $latency"
+fi
+
+CLAM_LATENCY_WINDOW=5 record_completion_latency 1000
+if [ "$(wc -l < "$HOME/.clam/latency.log")" -eq 5 ] &&
+   [ "$(get_latency_stats | jq -c '[.count, .p99_ms]')" == '[5,1000]' ]; then
+    pass "The window keeps only the newest entries"
+else
+    fail "Window not applied: $(get_latency_stats)"
+fi
+
+use_fake_curl '{"choices":[{"message":{"tool_calls":[{"function":{"arguments":"{\"suggestions\":[]}"}}]}}]}'
+CLAM_PROVIDER=openai CLAM_ACTIVE_API_KEY=test-key get_completion "make deploy" > /dev/null 2>&1
+if [ "$(get_latency_stats | jq -r '.count')" == "6" ]; then
+    pass "A completion request records its latency"
+else
+    fail "Completion latency not recorded: $(get_latency_stats)"
+fi
+echo
+
+finish_tests "Usage Tests"